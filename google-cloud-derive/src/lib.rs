@@ -1,16 +1,22 @@
 extern crate proc_macro;
 
-use proc_macro::TokenStream;
+use proc_macro2::TokenStream;
 
 use darling::{FromDeriveInput, FromField, FromMeta, FromVariant};
 use quote::quote;
 use syn::parse_macro_input;
 
 mod casing;
+#[cfg(test)]
+mod tests;
 
 use crate::casing::{transform_field_casing, transform_variant_casing};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, FromMeta)]
+/// The property holding the payload of a tagged variant, when that payload is not an entity.
+const CONTENT_PROPERTY: &str = "content";
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromMeta)]
 pub(crate) enum RenameAll {
     #[darling(rename = "lowercase")]
     LowerCase,
@@ -18,6 +24,7 @@ pub(crate) enum RenameAll {
     UpperCase,
     #[darling(rename = "PascalCase")]
     PascalCase,
+    #[default]
     #[darling(rename = "camelCase")]
     CamelCase,
     #[darling(rename = "snake_case")]
@@ -30,14 +37,44 @@ pub(crate) enum RenameAll {
     ScreamingKebabCase,
 }
 
-impl Default for RenameAll {
-    fn default() -> RenameAll {
-        RenameAll::CamelCase
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromMeta)]
+pub(crate) enum ArrayPolicy {
+    #[default]
+    #[darling(rename = "strict")]
+    Strict,
+    #[darling(rename = "skip_invalid")]
+    SkipInvalid,
+    #[darling(rename = "collect")]
+    Collect,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, FromMeta)]
+pub(crate) enum DurationUnit {
+    #[darling(rename = "nanos")]
+    Nanos,
+    #[default]
+    #[darling(rename = "micros")]
+    Micros,
+    #[darling(rename = "millis")]
+    Millis,
+    #[darling(rename = "secs")]
+    Secs,
+}
+
+impl DurationUnit {
+    /// The adapter storing durations in this unit, if it is not the default one.
+    fn adapter(self) -> Option<syn::Path> {
+        match self {
+            DurationUnit::Nanos => Some(syn::parse_quote! { ::google_cloud::datastore::Nanos }),
+            DurationUnit::Micros => None,
+            DurationUnit::Millis => Some(syn::parse_quote! { ::google_cloud::datastore::Millis }),
+            DurationUnit::Secs => Some(syn::parse_quote! { ::google_cloud::datastore::Secs }),
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromDeriveInput)]
-#[darling(attributes(datastore), supports(struct_named, enum_unit))]
+#[darling(attributes(datastore), supports(struct_named, enum_unit, enum_newtype))]
 struct Container {
     pub ident: syn::Ident,
     // pub vis: syn::Visibility,
@@ -46,6 +83,10 @@ struct Container {
     // pub attrs: Vec<syn::Attribute>,
     #[darling(default)]
     pub rename_all: RenameAll,
+    #[darling(default)]
+    pub tag: Option<String>,
+    #[darling(default)]
+    pub case_insensitive: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromVariant)]
@@ -54,6 +95,9 @@ struct VariantContainer {
     pub ident: syn::Ident,
     #[darling(default)]
     pub rename: Option<String>,
+    #[darling(multiple)]
+    pub alias: Vec<String>,
+    pub fields: darling::ast::Fields<FieldContainer>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromField)]
@@ -64,6 +108,14 @@ struct FieldContainer {
     pub rename: Option<String>,
     #[darling(default)]
     pub default: Option<syn::Lit>,
+    #[darling(default)]
+    pub ttl: bool,
+    #[darling(default)]
+    pub array: ArrayPolicy,
+    #[darling(default)]
+    pub duration: DurationUnit,
+    #[darling(default)]
+    pub try_each: bool,
     pub ty: syn::Type,
 }
 
@@ -71,8 +123,28 @@ fn derive_into_value_struct(
     ident: syn::Ident,
     fields: Vec<FieldContainer>,
     rename_all: RenameAll,
+    fallible: bool,
 ) -> TokenStream {
-    let idents: Vec<syn::Ident> = fields.iter().map(|field| field.ident.clone().unwrap()).collect();
+    let values: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            let field_ident = field.ident.as_ref().unwrap();
+            let value = match (field.array, field.duration.adapter()) {
+                //? Only the successfully decoded elements are written back.
+                (ArrayPolicy::Collect, _) => quote! { ::google_cloud::datastore::Collect(self.#field_ident) },
+                (_, Some(adapter)) => quote! { #adapter(self.#field_ident) },
+                _ => quote! { self.#field_ident },
+            };
+            match fallible {
+                //? Containers convert their elements one by one when asked to.
+                true if field.try_each => quote! {
+                    ::google_cloud::datastore::TryIntoValue::try_into_value(::google_cloud::datastore::TryEach(#value))?
+                },
+                true => quote! { ::google_cloud::datastore::TryIntoValue::try_into_value(#value)? },
+                false => quote! { ::google_cloud::datastore::IntoValue::into_value(#value) },
+            }
+        })
+        .collect();
     let names: Vec<syn::LitStr> = fields
         .into_iter()
         .map(|field| {
@@ -84,19 +156,31 @@ fn derive_into_value_struct(
         })
         .collect();
 
-    let capacity = names.len();
-
-    let tokens = quote! {
-        impl ::google_cloud::datastore::IntoValue for #ident {
-            fn into_value(self) -> ::google_cloud::datastore::Value {
-                let mut props = ::std::collections::HashMap::with_capacity(#capacity);
-                #(props.insert(::std::string::String::from(#names), self.#idents.into_value());)*
-                ::google_cloud::datastore::Value::EntityValue(props)
-            }
-        }
+    let props = quote! {
+        ::std::collections::HashMap::from([
+            #((::std::string::String::from(#names), #values),)*
+        ])
     };
-
-    tokens.into()
+    match fallible {
+        true => quote! {
+            #[automatically_derived]
+            impl ::google_cloud::datastore::TryIntoValue for #ident {
+                fn try_into_value(self) -> ::std::result::Result<::google_cloud::datastore::Value, ::google_cloud::error::ConvertError> {
+                    let props = #props;
+                    ::std::result::Result::Ok(::google_cloud::datastore::Value::EntityValue(props))
+                }
+            }
+        },
+        false => quote! {
+            #[automatically_derived]
+            impl ::google_cloud::datastore::IntoValue for #ident {
+                fn into_value(self) -> ::google_cloud::datastore::Value {
+                    let props = #props;
+                    ::google_cloud::datastore::Value::EntityValue(props)
+                }
+            }
+        },
+    }
 }
 
 fn derive_into_value_enum(
@@ -117,30 +201,159 @@ fn derive_into_value_enum(
         .collect();
 
     let tokens = quote! {
+        #[automatically_derived]
+        impl ::google_cloud::datastore::IntoValue for #ident {
+            fn into_value(self) -> ::google_cloud::datastore::Value {
+                match self {
+                    #(#ident::#idents => ::google_cloud::datastore::Value::StringValue(::std::string::String::from(#names)),)*
+                }
+            }
+        }
+    };
+
+    tokens
+}
+
+fn derive_into_value_tagged_enum(
+    ident: syn::Ident,
+    variants: Vec<VariantContainer>,
+    rename_all: RenameAll,
+    tag: String,
+) -> TokenStream {
+    let content = CONTENT_PROPERTY;
+    let arms = variants.into_iter().map(|variant| {
+        let name = variant_name(&variant, rename_all);
+        let variant_ident = variant.ident;
+        if variant.fields.is_unit() {
+            quote! {
+                #ident::#variant_ident => {
+                    let props = ::std::collections::HashMap::from([(
+                        ::std::string::String::from(#tag),
+                        ::google_cloud::datastore::Value::StringValue(::std::string::String::from(#name)),
+                    )]);
+                    ::google_cloud::datastore::Value::EntityValue(props)
+                }
+            }
+        } else {
+            //? Entities get the tag as an extra property, other values are wrapped next to it.
+            quote! {
+                #ident::#variant_ident(inner) => {
+                    let tag = ::google_cloud::datastore::Value::StringValue(::std::string::String::from(#name));
+                    let props = match ::google_cloud::datastore::IntoValue::into_value(inner) {
+                        ::google_cloud::datastore::Value::EntityValue(mut props) => {
+                            props.insert(::std::string::String::from(#tag), tag);
+                            props
+                        }
+                        value => ::std::collections::HashMap::from([
+                            (::std::string::String::from(#tag), tag),
+                            (::std::string::String::from(#content), value),
+                        ]),
+                    };
+                    ::google_cloud::datastore::Value::EntityValue(props)
+                }
+            }
+        }
+    });
+
+    let tokens = quote! {
+        #[automatically_derived]
         impl ::google_cloud::datastore::IntoValue for #ident {
             fn into_value(self) -> ::google_cloud::datastore::Value {
                 match self {
-                    #(#ident::#idents => ::google_cloud::datastore::Value::StringValue(#names.to_string()),)*
+                    #(#arms)*
                 }
             }
         }
     };
 
-    tokens.into()
+    tokens
+}
+
+fn variant_name(variant: &VariantContainer, rename_all: RenameAll) -> syn::LitStr {
+    let name = variant
+        .rename
+        .clone()
+        .unwrap_or_else(|| transform_variant_casing(variant.ident.clone(), rename_all));
+    syn::LitStr::new(name.as_str(), variant.ident.span())
+}
+
+/// Tests whether the stored variant name (`variant`, a `&str`) designates this variant,
+/// by its name or one of its aliases.
+fn variant_matches(
+    variant: &VariantContainer,
+    rename_all: RenameAll,
+    case_insensitive: bool,
+) -> impl quote::ToTokens {
+    let span = variant.ident.span();
+    let names = std::iter::once(variant_name(variant, rename_all))
+        .chain(variant.alias.iter().map(|alias| syn::LitStr::new(alias, span)));
+    if case_insensitive {
+        quote! { [#(#names),*].iter().any(|name| variant.eq_ignore_ascii_case(name)) }
+    } else {
+        quote! { ::std::matches!(variant, #(#names)|*) }
+    }
+}
+
+/// Enums holding data are only supported with a tag, to tell the variants apart.
+fn check_enum_variants(
+    variants: &[VariantContainer],
+    tag: &Option<String>,
+) -> Result<(), TokenStream> {
+    match variants.iter().find(|variant| !variant.fields.is_unit()) {
+        Some(variant) if tag.is_none() => Err(syn::Error::new(
+            variant.ident.span(),
+            "enums with data variants need a `#[datastore(tag = \"...\")]` attribute",
+        )
+        .to_compile_error()),
+        _ => Ok(()),
+    }
 }
 
 #[proc_macro_derive(IntoValue, attributes(datastore))]
-pub fn derive_into_value(input: TokenStream) -> TokenStream {
+pub fn derive_into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
-    let container = Container::from_derive_input(&input).unwrap();
+    expand_into_value(&input).into()
+}
+
+fn expand_into_value(input: &syn::DeriveInput) -> TokenStream {
+    let container = Container::from_derive_input(input).unwrap();
 
     let ident = container.ident;
     let rename_all = container.rename_all;
 
     match container.data {
-        darling::ast::Data::Enum(variants) => derive_into_value_enum(ident, variants, rename_all),
+        darling::ast::Data::Enum(variants) => {
+            if let Err(error) = check_enum_variants(&variants, &container.tag) {
+                return error;
+            }
+            match container.tag {
+                Some(tag) => derive_into_value_tagged_enum(ident, variants, rename_all, tag),
+                None => derive_into_value_enum(ident, variants, rename_all),
+            }
+        }
+        darling::ast::Data::Struct(darling::ast::Fields { fields, .. }) => {
+            derive_into_value_struct(ident, fields, rename_all, false)
+        }
+    }
+}
+
+#[proc_macro_derive(TryIntoValue, attributes(datastore))]
+pub fn derive_try_into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    expand_try_into_value(&input).into()
+}
+
+fn expand_try_into_value(input: &syn::DeriveInput) -> TokenStream {
+    let container = Container::from_derive_input(input).unwrap();
+
+    match container.data {
+        darling::ast::Data::Enum(_) => syn::Error::new(
+            container.ident.span(),
+            "`TryIntoValue` can only be derived for structs, enums convert with `IntoValue`",
+        )
+        .to_compile_error(),
         darling::ast::Data::Struct(darling::ast::Fields { fields, .. }) => {
-            derive_into_value_struct(ident, fields, rename_all)
+            derive_into_value_struct(container.ident, fields, container.rename_all, true)
         }
     }
 }
@@ -200,7 +413,7 @@ fn derive_from_value_struct(
     fields: Vec<FieldContainer>,
     rename_all: RenameAll,
 ) -> TokenStream {
-    let field_bindings = fields.iter().map(|field| {
+    let field_values = fields.iter().map(|field| {
         let field_ident = field.ident.as_ref().unwrap();
         let field_name = syn::LitStr::new(
             &field.rename.clone().unwrap_or_else(|| transform_field_casing(field_ident.clone(), rename_all)),
@@ -208,97 +421,180 @@ fn derive_from_value_struct(
         );
         let field_ty = &field.ty;
 
-        let assign_value = if let Some(default) = &field.default {
+        if field.array == ArrayPolicy::SkipInvalid {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => #default,
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => <::google_cloud::datastore::SkipInvalid<_> as ::google_cloud::datastore::FromValue>::from_value(value)?.into_inner(),
+                    ::std::option::Option::None => ::std::vec::Vec::new(),
+                },
+            }
+        } else if field.array == ArrayPolicy::Collect {
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => <::google_cloud::datastore::Collect<_> as ::google_cloud::datastore::FromValue>::from_value(value)?.into_inner(),
+                    ::std::option::Option::None => ::std::vec::Vec::new(),
+                },
+            }
+        } else if let Some(adapter) = field.duration.adapter() {
+            //? Defaults are given as a count of the unit.
+            let default = match &field.default {
+                Some(default) => quote! {
+                    <#adapter<_> as ::google_cloud::datastore::FromValue>::from_value(::google_cloud::datastore::Value::IntegerValue(#default))?.into_inner()
+                },
+                None => quote! { ::std::default::Default::default() },
+            };
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => <#adapter<_> as ::google_cloud::datastore::FromValue>::from_value(value)?.into_inner(),
+                    ::std::option::Option::None => #default,
+                },
+            }
+        } else if let Some(default) = &field.default {
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => #default,
+                },
             }
         } else if is_option_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => None,
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::std::option::Option::None,
+                },
             }
         } else if is_string_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => ::std::string::String::new(),
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::std::string::String::new(),
+                },
             }
         } else if is_bool_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => false,
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => false,
+                },
             }
         } else if is_i64_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => 0,
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => 0,
+                },
             }
         } else if is_f64_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => 0.0,
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => 0.0,
+                },
             }
         } else if is_vec_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => ::std::vec::Vec::new(),
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::std::vec::Vec::new(),
+                },
+            }
+        } else if is_duration_type(field_ty) {
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::std::default::Default::default(),
+                },
             }
         } else if is_naivedatetime_type(field_ty) {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => ::chrono::NaiveDateTime::from_timestamp(0, 0),
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::chrono::DateTime::UNIX_EPOCH.naive_utc(),
+                },
+            }
+        } else if is_datetime_type(field_ty) {
+            //? Both `DateTime<Utc>` and `DateTime<FixedOffset>` convert from the UTC epoch.
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::std::convert::From::from(::chrono::DateTime::UNIX_EPOCH),
+                },
+            }
+        } else if is_offsetdatetime_type(field_ty) {
+            quote! {
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => ::time::OffsetDateTime::UNIX_EPOCH,
+                },
             }
         } else {
             quote! {
-                let #field_ident = match props.remove(#field_name) {
-                    Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
-                    None => return Err(::google_cloud::error::ConvertError::MissingProperty(#field_name.to_string())),
-                };
+                #field_ident: match props.remove(#field_name) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => return ::std::result::Result::Err(
+                        ::google_cloud::error::ConvertError::MissingProperty(::std::string::String::from(#field_name))
+                    ),
+                },
             }
-        };
-
-        assign_value
+        }
     });
 
-    let field_names: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    //? Structs without fields leave the properties untouched.
+    let props = match fields.is_empty() {
+        true => quote! { _ },
+        false => quote! { mut props },
+    };
+
+    let ttl_fields: Vec<_> = fields.iter().filter(|f| f.ttl).collect();
+    let expiring = match ttl_fields.as_slice() {
+        [] => quote! {},
+        [field] => {
+            let field_ident = field.ident.as_ref().unwrap();
+            let expiry = match is_option_type(&field.ty) {
+                true => quote! { self.#field_ident },
+                false => quote! { ::std::option::Option::Some(self.#field_ident) },
+            };
+            quote! {
+                #[automatically_derived]
+                impl ::google_cloud::datastore::Expiring for #ident {
+                    fn expires_at(&self) -> ::std::option::Option<::chrono::NaiveDateTime> {
+                        #expiry
+                    }
+                }
+            }
+        }
+        [_, extra, ..] => {
+            return syn::Error::new(
+                extra.ident.as_ref().unwrap().span(),
+                "only one field can be marked with `#[datastore(ttl)]`",
+            )
+            .to_compile_error();
+        }
+    };
 
     let tokens = quote! {
+        #expiring
+
+        #[automatically_derived]
         impl ::google_cloud::datastore::FromValue for #ident {
             fn from_value(value: ::google_cloud::datastore::Value) -> ::std::result::Result<#ident, ::google_cloud::error::ConvertError> {
-                let mut props = match value {
+                let #props = match value {
                     ::google_cloud::datastore::Value::EntityValue(props) => props,
-                    _ => return Err(::google_cloud::error::ConvertError::UnexpectedPropertyType {
-                        expected: "entity".to_string(),
-                        got: value.type_name().to_string(),
+                    _ => return ::std::result::Result::Err(::google_cloud::error::ConvertError::UnexpectedPropertyType {
+                        expected: ::std::string::String::from("entity"),
+                        got: ::std::string::String::from(value.type_name()),
                     }),
                 };
 
-                #(#field_bindings)*
-
-                Ok(#ident {
-                    #(#field_names),*
+                //? The fields are decoded in their declaration order.
+                ::std::result::Result::Ok(#ident {
+                    #(#field_values)*
                 })
             }
         }
     };
 
-    tokens.into()
+    tokens
 }
 
 fn is_option_type(ty: &syn::Type) -> bool {
@@ -309,6 +605,10 @@ fn is_string_type(ty: &syn::Type) -> bool {
     matches!(ty, syn::Type::Path(type_path) if type_path.path.is_ident("String"))
 }
 
+fn is_duration_type(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(type_path) if type_path.path.segments.last().map(|s| s.ident == "Duration" || s.ident == "TimeDelta").unwrap_or(false))
+}
+
 fn is_bool_type(ty: &syn::Type) -> bool {
     matches!(ty, syn::Type::Path(type_path) if type_path.path.is_ident("bool"))
 }
@@ -335,28 +635,102 @@ fn is_naivedatetime_type(ty: &syn::Type) -> bool {
     })
 }
 
+fn is_datetime_type(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(type_path) if {
+        let segments = &type_path.path.segments;
+        segments.last().map(|s| s.ident == "DateTime").unwrap_or(false)
+    })
+}
+
+fn is_offsetdatetime_type(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(type_path) if {
+        let segments = &type_path.path.segments;
+        segments.last().map(|s| s.ident == "OffsetDateTime").unwrap_or(false)
+    })
+}
+
 fn derive_from_value_enum(
     ident: syn::Ident,
     variants: Vec<VariantContainer>,
     rename_all: RenameAll,
+    case_insensitive: bool,
 ) -> TokenStream {
-    let idents: Vec<syn::Ident> = variants.iter().map(|variant| variant.ident.clone()).collect();
-    let names: Vec<syn::LitStr> = variants
-        .into_iter()
-        .map(|variant| {
-            let renamed = variant.rename;
-            let variant = variant.ident;
-            let span = variant.span();
-            let name = renamed.unwrap_or_else(|| transform_variant_casing(variant, rename_all));
-            syn::LitStr::new(name.as_str(), span)
-        })
-        .collect();
+    let arms = variants.into_iter().map(|variant| {
+        let matches = variant_matches(&variant, rename_all, case_insensitive);
+        let variant_ident = variant.ident;
+        quote! {
+            if #matches {
+                return ::std::result::Result::Ok(#ident::#variant_ident);
+            }
+        }
+    });
 
     let tokens = quote! {
+        #[automatically_derived]
         impl ::google_cloud::datastore::FromValue for #ident {
             fn from_value(value: ::google_cloud::datastore::Value) -> ::std::result::Result<#ident, ::google_cloud::error::ConvertError> {
                 let value = match value {
                     ::google_cloud::datastore::Value::StringValue(value) => value,
+                    _ => return ::std::result::Result::Err(
+                        ::google_cloud::error::ConvertError::UnexpectedPropertyType {
+                            expected: ::std::string::String::from("string"),
+                            got: ::std::string::String::from(value.type_name()),
+                        }
+                    ),
+                };
+                let variant = value.as_str();
+                #(#arms)*
+                ::std::result::Result::Err(::google_cloud::error::ConvertError::UnknownVariant(value))
+            }
+        }
+    };
+
+    tokens
+}
+
+fn derive_from_value_tagged_enum(
+    ident: syn::Ident,
+    variants: Vec<VariantContainer>,
+    rename_all: RenameAll,
+    tag: String,
+    case_insensitive: bool,
+) -> TokenStream {
+    let content = CONTENT_PROPERTY;
+    let arms = variants.into_iter().map(|variant| {
+        let matches = variant_matches(&variant, rename_all, case_insensitive);
+        let variant_ident = variant.ident;
+        if variant.fields.is_unit() {
+            quote! {
+                if #matches {
+                    return ::std::result::Result::Ok(#ident::#variant_ident);
+                }
+            }
+        } else {
+            //? A lone content property holds a wrapped value, unless the entity itself decodes.
+            quote! {
+                if #matches {
+                    let inner = match props.get(#content) {
+                        ::std::option::Option::Some(content) if props.len() == 1 => {
+                            let content = content.clone();
+                            match ::google_cloud::datastore::FromValue::from_value(::google_cloud::datastore::Value::EntityValue(props)) {
+                                ::std::result::Result::Ok(inner) => inner,
+                                ::std::result::Result::Err(_) => ::google_cloud::datastore::FromValue::from_value(content)?,
+                            }
+                        }
+                        _ => ::google_cloud::datastore::FromValue::from_value(::google_cloud::datastore::Value::EntityValue(props))?,
+                    };
+                    return ::std::result::Result::Ok(#ident::#variant_ident(inner));
+                }
+            }
+        }
+    });
+
+    let tokens = quote! {
+        #[automatically_derived]
+        impl ::google_cloud::datastore::FromValue for #ident {
+            fn from_value(value: ::google_cloud::datastore::Value) -> ::std::result::Result<#ident, ::google_cloud::error::ConvertError> {
+                let mut props = match value {
+                    ::google_cloud::datastore::Value::EntityValue(props) => props,
                     _ => return ::std::result::Result::Err(
                         ::google_cloud::error::ConvertError::UnexpectedPropertyType {
                             expected: ::std::string::String::from("entity"),
@@ -364,27 +738,92 @@ fn derive_from_value_enum(
                         }
                     ),
                 };
-                match value.as_str() {
-                    #(#names => ::std::result::Result::Ok(#ident::#idents),)*
-                    _ => todo!("[datastore-derive] unknown enum variant encountered"),
+                let variant: ::std::string::String = match props.remove(#tag) {
+                    ::std::option::Option::Some(value) => ::google_cloud::datastore::FromValue::from_value(value)?,
+                    ::std::option::Option::None => return ::std::result::Result::Err(
+                        ::google_cloud::error::ConvertError::MissingProperty(::std::string::String::from(#tag))
+                    ),
+                };
+                {
+                    let variant = variant.as_str();
+                    #(#arms)*
                 }
+                ::std::result::Result::Err(::google_cloud::error::ConvertError::UnknownVariant(variant))
             }
         }
     };
 
-    tokens.into()
+    tokens
+}
+
+#[proc_macro_derive(Projection, attributes(datastore))]
+pub fn derive_projection(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    expand_projection(&input).into()
+}
+
+fn expand_projection(input: &syn::DeriveInput) -> TokenStream {
+    let container = Container::from_derive_input(input).unwrap();
+
+    let ident = container.ident;
+    let fields = match container.data {
+        darling::ast::Data::Enum(_) => {
+            return syn::Error::new(ident.span(), "`Projection` can only be derived for structs")
+                .to_compile_error();
+        }
+        darling::ast::Data::Struct(darling::ast::Fields { fields, .. }) => fields,
+    };
+    //? Projecting no property at all would query the full entities instead.
+    if fields.is_empty() {
+        return syn::Error::new(ident.span(), "`Projection` needs at least one field to project")
+            .to_compile_error();
+    }
+    //? The same names as `FromValue`, for the projected properties to decode.
+    let names = fields.into_iter().map(|field| {
+        let field_ident = field.ident.unwrap();
+        let span = field_ident.span();
+        let name = field
+            .rename
+            .unwrap_or_else(|| transform_field_casing(field_ident, container.rename_all));
+        syn::LitStr::new(&name, span)
+    });
+
+    quote! {
+        #[automatically_derived]
+        impl ::google_cloud::datastore::Projection for #ident {
+            const PROPERTIES: &'static [&'static str] = &[#(#names),*];
+        }
+    }
 }
 
 #[proc_macro_derive(FromValue, attributes(datastore))]
-pub fn derive_from_value(input: TokenStream) -> TokenStream {
+pub fn derive_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as syn::DeriveInput);
-    let container = Container::from_derive_input(&input).unwrap();
+    expand_from_value(&input).into()
+}
+
+fn expand_from_value(input: &syn::DeriveInput) -> TokenStream {
+    let container = Container::from_derive_input(input).unwrap();
 
     let ident = container.ident;
     let rename_all = container.rename_all;
 
     match container.data {
-        darling::ast::Data::Enum(variants) => derive_from_value_enum(ident, variants, rename_all),
+        darling::ast::Data::Enum(variants) => {
+            if let Err(error) = check_enum_variants(&variants, &container.tag) {
+                return error;
+            }
+            match container.tag {
+                Some(tag) => derive_from_value_tagged_enum(
+                    ident,
+                    variants,
+                    rename_all,
+                    tag,
+                    container.case_insensitive,
+                ),
+                None => derive_from_value_enum(ident, variants, rename_all, container.case_insensitive),
+            }
+        }
         darling::ast::Data::Struct(darling::ast::Fields { fields, .. }) => {
             derive_from_value_struct(ident, fields, rename_all)
         }
@@ -0,0 +1,70 @@
+//! Snapshots of the code generated by the derives.
+//!
+//! The derives of each `tests/expand/<name>.rs` file are expanded and compared with
+//! `tests/expand/<name>.expanded.rs`, which is written when it is missing, or when
+//! `MACROTEST=overwrite` is set (as with `macrotest`).
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+
+use crate::{expand_from_value, expand_into_value, expand_projection, expand_try_into_value};
+
+/// Expands the derives of the items of a file, formatted as `cargo expand` would.
+fn expand(path: &Path) -> String {
+    let source = fs::read_to_string(path).unwrap();
+    let file = syn::parse_file(&source).unwrap();
+
+    let mut tokens = proc_macro2::TokenStream::new();
+    for item in file.items {
+        let input: syn::DeriveInput = syn::parse2(item.into_token_stream()).unwrap();
+        for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("derive")) {
+            let derives = attr
+                .parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated)
+                .unwrap();
+            for derive in derives {
+                let name = derive.segments.last().unwrap().ident.to_string();
+                tokens.extend(match name.as_str() {
+                    "IntoValue" => expand_into_value(&input),
+                    "TryIntoValue" => expand_try_into_value(&input),
+                    "FromValue" => expand_from_value(&input),
+                    "Projection" => expand_projection(&input),
+                    _ => continue,
+                });
+            }
+        }
+    }
+
+    prettyplease::unparse(&syn1::parse_file(&tokens.to_string()).unwrap())
+}
+
+#[test]
+fn expansions_match_their_snapshots() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("expand");
+    let overwrite = env::var_os("MACROTEST").is_some_and(|value| value == "overwrite");
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.to_string_lossy().ends_with(".expanded.rs"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    for input in inputs {
+        let expanded = expand(&input);
+        let snapshot = input.with_extension("expanded.rs");
+        match fs::read_to_string(&snapshot) {
+            Ok(expected) if !overwrite => assert!(
+                expected == expanded,
+                "{} is outdated (set `MACROTEST=overwrite` to update it), the derives now expand to:\n{}",
+                snapshot.display(),
+                expanded
+            ),
+            _ => fs::write(&snapshot, expanded).unwrap(),
+        }
+    }
+}
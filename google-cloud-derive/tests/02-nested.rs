@@ -1,3 +1,5 @@
+#![allow(clippy::disallowed_names)]
+
 use google_cloud::datastore::{FromValue, IntoValue};
 use google_cloud::error::ConvertError;
 
@@ -0,0 +1,30 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use google_cloud::datastore::{Expiring, FromValue, IntoValue};
+
+#[derive(Debug, FromValue, IntoValue)]
+pub struct Session {
+    user: String,
+    #[datastore(ttl)]
+    expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, FromValue, IntoValue)]
+pub struct Token {
+    value: String,
+    #[datastore(ttl)]
+    expires_at: Option<NaiveDateTime>,
+}
+
+fn main() {
+    let now = Utc::now().naive_utc();
+
+    let session =
+        Session { user: String::from("john"), expires_at: now - TimeDelta::try_hours(1).unwrap() };
+    let session = Session::from_value(session.into_value()).unwrap();
+    assert_eq!(session.user, "john");
+    assert!(session.is_expired());
+
+    let token = Token { value: String::from("secret"), expires_at: None };
+    assert_eq!(token.value, "secret");
+    assert!(!token.is_expired());
+}
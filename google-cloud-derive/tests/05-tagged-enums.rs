@@ -0,0 +1,60 @@
+use google_cloud::datastore::{FromValue, IntoValue, Value};
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+pub struct CreatedEvent {
+    name: String,
+}
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+pub struct DeletedEvent {
+    reason: String,
+}
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+#[datastore(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Created(CreatedEvent),
+    Deleted(DeletedEvent),
+    Moved(String),
+    Retried(i64),
+    Purged,
+}
+
+fn main() {
+    let event = Event::Created(CreatedEvent { name: String::from("john") });
+    let converted = event.into_value();
+    match &converted {
+        Value::EntityValue(props) => {
+            assert_eq!(props.get("type"), Some(&Value::StringValue(String::from("created"))));
+            assert_eq!(props.get("name"), Some(&Value::StringValue(String::from("john"))));
+        }
+        _ => panic!("expected an entity"),
+    }
+    let recovered = Event::from_value(converted).unwrap();
+    assert_eq!(recovered, Event::Created(CreatedEvent { name: String::from("john") }));
+
+    //? Payloads which are not entities are stored next to the tag.
+    let event = Event::Moved(String::from("archive"));
+    let converted = event.into_value();
+    match &converted {
+        Value::EntityValue(props) => {
+            assert_eq!(props.get("type"), Some(&Value::StringValue(String::from("moved"))));
+            assert_eq!(props.get("content"), Some(&Value::StringValue(String::from("archive"))));
+        }
+        _ => panic!("expected an entity"),
+    }
+    let recovered = Event::from_value(converted).unwrap();
+    assert_eq!(recovered, Event::Moved(String::from("archive")));
+    let recovered = Event::from_value(Event::Retried(3).into_value()).unwrap();
+    assert_eq!(recovered, Event::Retried(3));
+
+    let recovered = Event::from_value(Event::Purged.into_value()).unwrap();
+    assert_eq!(recovered, Event::Purged);
+
+    let unknown = Value::EntityValue(
+        vec![(String::from("type"), Value::StringValue(String::from("renamed")))]
+            .into_iter()
+            .collect(),
+    );
+    assert!(Event::from_value(unknown).is_err());
+}
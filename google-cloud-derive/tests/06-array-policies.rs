@@ -0,0 +1,46 @@
+use google_cloud::datastore::{FromValue, IntoValue, Value};
+use google_cloud::error::ConvertError;
+
+#[derive(Debug, FromValue, IntoValue)]
+pub struct Legacy {
+    #[datastore(array = "skip_invalid")]
+    scores: Vec<i64>,
+    #[datastore(array = "collect")]
+    tags: Vec<Result<String, ConvertError>>,
+    #[datastore(array = "strict")]
+    flags: Vec<bool>,
+}
+
+fn main() {
+    let mixed = Value::ArrayValue(vec![
+        Value::IntegerValue(1),
+        Value::StringValue(String::from("two")),
+        Value::IntegerValue(3),
+    ]);
+    let tags = Value::ArrayValue(vec![Value::StringValue(String::from("a")), Value::IntegerValue(2)]);
+    let props = vec![
+        (String::from("scores"), mixed),
+        (String::from("tags"), tags),
+        (String::from("flags"), Value::ArrayValue(vec![Value::BooleanValue(true)])),
+    ];
+    let value = Value::EntityValue(props.into_iter().collect());
+
+    let legacy = Legacy::from_value(value).unwrap();
+    assert_eq!(legacy.scores, vec![1, 3]);
+    assert_eq!(legacy.tags.len(), 2);
+    assert!(legacy.tags[0].is_ok() && legacy.tags[1].is_err());
+    assert_eq!(legacy.flags, vec![true]);
+
+    //? Only the valid tags are written back.
+    match legacy.into_value() {
+        Value::EntityValue(props) => {
+            let tags = Value::ArrayValue(vec![Value::StringValue(String::from("a"))]);
+            assert_eq!(props.get("tags"), Some(&tags));
+        }
+        _ => panic!("expected an entity"),
+    }
+
+    //? Strict arrays still fail on the first invalid element.
+    let props = vec![(String::from("flags"), Value::ArrayValue(vec![Value::IntegerValue(1)]))];
+    assert!(Legacy::from_value(Value::EntityValue(props.into_iter().collect())).is_err());
+}
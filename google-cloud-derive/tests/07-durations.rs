@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use google_cloud::datastore::{FromValue, IntoValue, Value};
+
+#[derive(Debug, FromValue, IntoValue)]
+pub struct Job {
+    timeout: Duration,
+    #[datastore(duration = "millis")]
+    backoff: Duration,
+    #[datastore(duration = "secs")]
+    retention: chrono::Duration,
+    #[datastore(duration = "nanos", default = 250)]
+    jitter: Duration,
+}
+
+fn main() {
+    let job = Job {
+        timeout: Duration::from_secs(2),
+        backoff: Duration::from_millis(1500),
+        retention: chrono::Duration::try_days(7).unwrap(),
+        jitter: Duration::from_nanos(40),
+    };
+    match job.into_value() {
+        Value::EntityValue(props) => {
+            assert_eq!(props.get("timeout"), Some(&Value::IntegerValue(2_000_000)));
+            assert_eq!(props.get("backoff"), Some(&Value::IntegerValue(1500)));
+            assert_eq!(props.get("retention"), Some(&Value::IntegerValue(7 * 24 * 3600)));
+            assert_eq!(props.get("jitter"), Some(&Value::IntegerValue(40)));
+        }
+        _ => panic!("expected an entity"),
+    }
+
+    //? Missing durations are zero, unless they have a default (counted in their unit).
+    let job = Job::from_value(Value::EntityValue(Default::default())).unwrap();
+    assert_eq!(job.timeout, Duration::ZERO);
+    assert_eq!(job.retention, chrono::Duration::zero());
+    assert_eq!(job.jitter, Duration::from_nanos(250));
+
+    //? Negative values do not fit a `std::time::Duration`.
+    let props = vec![(String::from("timeout"), Value::IntegerValue(-1))];
+    assert!(Job::from_value(Value::EntityValue(props.into_iter().collect())).is_err());
+}
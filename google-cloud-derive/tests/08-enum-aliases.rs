@@ -0,0 +1,41 @@
+use google_cloud::datastore::{FromValue, IntoValue, Value};
+use google_cloud::error::ConvertError;
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+#[datastore(rename_all = "SCREAMING_SNAKE_CASE", case_insensitive)]
+pub enum Status {
+    Active,
+    #[datastore(alias = "disabled", alias = "OFF")]
+    Inactive,
+    #[datastore(rename = "on-hold")]
+    OnHold,
+}
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+pub enum Strict {
+    #[datastore(alias = "legacy")]
+    Current,
+}
+
+fn string(value: &str) -> Value {
+    Value::StringValue(String::from(value))
+}
+
+fn main() {
+    assert_eq!(Status::from_value(string("ACTIVE")).unwrap(), Status::Active);
+    assert_eq!(Status::from_value(string("Active")).unwrap(), Status::Active);
+    //? Aliases are matched case-insensitively too.
+    assert_eq!(Status::from_value(string("Disabled")).unwrap(), Status::Inactive);
+    assert_eq!(Status::from_value(string("off")).unwrap(), Status::Inactive);
+    assert_eq!(Status::from_value(string("ON-HOLD")).unwrap(), Status::OnHold);
+    assert!(matches!(
+        Status::from_value(string("archived")),
+        Err(ConvertError::UnknownVariant(variant)) if variant == "archived"
+    ));
+
+    //? Values are still written with their canonical name.
+    assert_eq!(Status::Inactive.into_value(), string("INACTIVE"));
+
+    assert_eq!(Strict::from_value(string("legacy")).unwrap(), Strict::Current);
+    assert!(Strict::from_value(string("Legacy")).is_err());
+}
@@ -0,0 +1,69 @@
+#![deny(warnings)]
+#![deny(clippy::all, clippy::pedantic)]
+
+//? Only the conversion traits used by `main` are imported: the derived code names its own.
+use chrono::NaiveDateTime;
+use google_cloud::datastore::{Expiring, FromValue};
+
+#[derive(Debug, Default, PartialEq, FromValue, google_cloud::datastore::IntoValue)]
+pub struct Empty {}
+
+#[derive(Debug, PartialEq, FromValue, google_cloud::datastore::IntoValue)]
+pub struct Profile {
+    name: String,
+    #[datastore(default = 18)]
+    age: i64,
+    joined_at: NaiveDateTime,
+    #[datastore(ttl)]
+    expires_at: Option<NaiveDateTime>,
+}
+
+//? The fields are not bound to variables named after them.
+#[allow(non_snake_case)]
+#[derive(Debug, PartialEq, FromValue, google_cloud::datastore::IntoValue)]
+pub struct Legacy {
+    userId: String,
+    r#type: String,
+}
+
+#[derive(Debug, PartialEq, FromValue, google_cloud::datastore::IntoValue)]
+#[datastore(case_insensitive)]
+pub enum Status {
+    Active,
+    #[datastore(alias = "gone")]
+    Deleted,
+}
+
+#[derive(Debug, PartialEq, FromValue, google_cloud::datastore::IntoValue)]
+#[datastore(tag = "type")]
+pub enum Event {
+    Created(Empty),
+    Closed,
+}
+
+fn main() {
+    use google_cloud::datastore::IntoValue;
+
+    assert_eq!(Empty::from_value(Empty {}.into_value()).unwrap(), Empty {});
+
+    let profile = Profile {
+        name: String::from("john"),
+        age: 30,
+        joined_at: NaiveDateTime::default(),
+        expires_at: None,
+    };
+    assert!(!profile.is_expired());
+    let profile = Profile::from_value(profile.into_value()).unwrap();
+    assert_eq!(profile.age, 30);
+
+    let legacy = Legacy { userId: String::from("john"), r#type: String::from("admin") };
+    assert_eq!(Legacy::from_value(legacy.into_value()).unwrap().userId, "john");
+
+    assert_eq!(Status::from_value("GONE".into_value()).unwrap(), Status::Deleted);
+    assert_eq!(Status::from_value(Status::Active.into_value()).unwrap(), Status::Active);
+    assert_eq!(Event::from_value(Event::Closed.into_value()).unwrap(), Event::Closed);
+    assert_eq!(
+        Event::from_value(Event::Created(Empty {}).into_value()).unwrap(),
+        Event::Created(Empty {})
+    );
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use google_cloud::datastore::{Entity, IntoValue, Key, TryIntoValue, Value};
+use google_cloud::error::ConvertError;
+
+#[derive(Debug, IntoValue)]
+pub struct Owner {
+    name: String,
+}
+
+#[derive(Debug, TryIntoValue)]
+#[datastore(rename_all = "snake_case")]
+pub struct Counter {
+    owner: Owner,
+    total_hits: u64,
+    maximum: Option<i64>,
+    #[datastore(try_each)]
+    peak: Option<u64>,
+    #[datastore(try_each)]
+    samples: Vec<u64>,
+    #[datastore(try_each)]
+    hits_by_page: HashMap<String, u64>,
+}
+
+impl Counter {
+    fn new(owner: &str, total_hits: u64) -> Counter {
+        Counter {
+            owner: Owner { name: String::from(owner) },
+            total_hits,
+            maximum: None,
+            peak: None,
+            samples: Vec::new(),
+            hits_by_page: HashMap::new(),
+        }
+    }
+}
+
+fn main() {
+    let counter = Counter {
+        peak: Some(7),
+        samples: vec![1, 2],
+        hits_by_page: HashMap::from([(String::from("home"), 3)]),
+        ..Counter::new("john", 10)
+    };
+    let value = counter.try_into_value().unwrap();
+    assert_eq!(value.at_path("total_hits").unwrap(), &Value::IntegerValue(10));
+    assert_eq!(value.at_path("owner.name").unwrap(), &"john".into_value());
+    assert_eq!(value.at_path("peak").unwrap(), &Some(7).into_value());
+    assert_eq!(value.at_path("samples").unwrap(), &vec![1, 2].into_value());
+    assert_eq!(value.at_path("hits_by_page.home").unwrap(), &Value::IntegerValue(3));
+
+    //? Out of range integers fail the conversion, and the entity with them.
+    let counter = Counter { maximum: Some(1), ..Counter::new("jane", u64::MAX) };
+    let error = Entity::new(Key::new("counters").id(1), counter).unwrap_err();
+    assert!(matches!(error, ConvertError::OutOfRange(_)));
+
+    //? Including those held in the containers marked with `try_each`.
+    let counters = [
+        Counter { peak: Some(u64::MAX), ..Counter::new("jane", 1) },
+        Counter { samples: vec![1, u64::MAX], ..Counter::new("jane", 1) },
+        Counter {
+            hits_by_page: HashMap::from([(String::from("home"), u64::MAX)]),
+            ..Counter::new("jane", 1)
+        },
+    ];
+    for counter in counters {
+        assert!(matches!(counter.try_into_value(), Err(ConvertError::OutOfRange(_))));
+    }
+
+    //? Types deriving `IntoValue` are written as well.
+    let entity = Entity::new(Key::new("owners").id(1), Owner { name: String::from("john") });
+    assert!(entity.is_ok());
+}
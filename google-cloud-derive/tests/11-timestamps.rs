@@ -0,0 +1,43 @@
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use google_cloud::datastore::{FromValue, IntoValue, Value};
+use time::OffsetDateTime;
+
+#[derive(Debug, PartialEq, FromValue, IntoValue)]
+pub struct Order {
+    placed_at: DateTime<Utc>,
+    local_time: DateTime<FixedOffset>,
+    shipped_at: OffsetDateTime,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+fn main() {
+    let placed_at = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+    let local_time =
+        FixedOffset::east_opt(2 * 3600).unwrap().from_utc_datetime(&placed_at.naive_utc());
+    let shipped_at = OffsetDateTime::from_unix_timestamp(placed_at.timestamp() + 3600).unwrap();
+    let order = Order { placed_at, local_time, shipped_at, delivered_at: None };
+
+    let converted = order.into_value();
+    match &converted {
+        Value::EntityValue(props) => {
+            //? Timestamps are stored in UTC, whatever their offset.
+            let expected = Value::TimestampValue(placed_at.naive_utc());
+            assert_eq!(props.get("placedAt"), Some(&expected));
+            assert_eq!(props.get("localTime"), Some(&expected));
+        }
+        _ => panic!("expected an entity"),
+    }
+
+    let order = Order::from_value(converted).unwrap();
+    assert_eq!(order.placed_at, placed_at);
+    assert_eq!(order.local_time, local_time);
+    assert_eq!(order.local_time.offset().local_minus_utc(), 0);
+    assert_eq!(order.shipped_at, shipped_at);
+    assert_eq!(order.delivered_at, None);
+
+    //? Missing timestamps default to the epoch.
+    let order = Order::from_value(Value::EntityValue(Default::default())).unwrap();
+    assert_eq!(order.placed_at, DateTime::UNIX_EPOCH);
+    assert_eq!(order.local_time, DateTime::UNIX_EPOCH);
+    assert_eq!(order.shipped_at, OffsetDateTime::UNIX_EPOCH);
+}
@@ -0,0 +1,33 @@
+use chrono::NaiveDateTime;
+use google_cloud::datastore::{FromValue, IntoValue, Projection, Query, Value};
+use std::collections::HashMap;
+
+#[derive(Debug, FromValue, Projection)]
+pub struct UserSummary {
+    display_name: String,
+    #[datastore(rename = "signup")]
+    signed_up_at: NaiveDateTime,
+}
+
+#[derive(Debug, FromValue, Projection)]
+#[datastore(rename_all = "snake_case")]
+pub struct Visit {
+    page_url: String,
+}
+
+fn main() {
+    assert_eq!(UserSummary::PROPERTIES, ["displayName", "signup"]);
+    assert_eq!(Visit::PROPERTIES, ["page_url"]);
+    let properties = HashMap::from([(String::from("page_url"), "/home".into_value())]);
+    assert_eq!(Visit::from_value(Value::EntityValue(properties)).unwrap().page_url, "/home");
+    let _query = Query::new("users").project_as::<UserSummary>();
+
+    let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+    let properties = HashMap::from([
+        (String::from("displayName"), "John".into_value()),
+        (String::from("signup"), Value::TimestampValue(time)),
+    ]);
+    let summary = UserSummary::from_value(Value::EntityValue(properties)).unwrap();
+    assert_eq!(summary.display_name, "John");
+    assert_eq!(summary.signed_up_at, time);
+}
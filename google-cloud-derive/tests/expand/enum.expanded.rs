@@ -0,0 +1,43 @@
+#[automatically_derived]
+impl ::google_cloud::datastore::FromValue for Status {
+    fn from_value(
+        value: ::google_cloud::datastore::Value,
+    ) -> ::std::result::Result<Status, ::google_cloud::error::ConvertError> {
+        let value = match value {
+            ::google_cloud::datastore::Value::StringValue(value) => value,
+            _ => {
+                return ::std::result::Result::Err(::google_cloud::error::ConvertError::UnexpectedPropertyType {
+                    expected: ::std::string::String::from("string"),
+                    got: ::std::string::String::from(value.type_name()),
+                });
+            }
+        };
+        let variant = value.as_str();
+        if ["active"].iter().any(|name| variant.eq_ignore_ascii_case(name)) {
+            return ::std::result::Result::Ok(Status::Active);
+        }
+        if ["deleted", "gone"].iter().any(|name| variant.eq_ignore_ascii_case(name)) {
+            return ::std::result::Result::Ok(Status::Deleted);
+        }
+        ::std::result::Result::Err(
+            ::google_cloud::error::ConvertError::UnknownVariant(value),
+        )
+    }
+}
+#[automatically_derived]
+impl ::google_cloud::datastore::IntoValue for Status {
+    fn into_value(self) -> ::google_cloud::datastore::Value {
+        match self {
+            Status::Active => {
+                ::google_cloud::datastore::Value::StringValue(
+                    ::std::string::String::from("active"),
+                )
+            }
+            Status::Deleted => {
+                ::google_cloud::datastore::Value::StringValue(
+                    ::std::string::String::from("deleted"),
+                )
+            }
+        }
+    }
+}
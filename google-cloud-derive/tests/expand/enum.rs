@@ -0,0 +1,7 @@
+#[derive(FromValue, IntoValue)]
+#[datastore(case_insensitive)]
+pub enum Status {
+    Active,
+    #[datastore(alias = "gone")]
+    Deleted,
+}
@@ -0,0 +1,4 @@
+#[automatically_derived]
+impl ::google_cloud::datastore::Projection for UserSummary {
+    const PROPERTIES: &'static [&'static str] = &["display_name", "signup"];
+}
@@ -0,0 +1,7 @@
+#[derive(Projection)]
+#[datastore(rename_all = "snake_case")]
+pub struct UserSummary {
+    display_name: String,
+    #[datastore(rename = "signup")]
+    signed_up_at: NaiveDateTime,
+}
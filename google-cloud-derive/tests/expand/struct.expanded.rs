@@ -0,0 +1,72 @@
+#[automatically_derived]
+impl ::google_cloud::datastore::Expiring for Profile {
+    fn expires_at(&self) -> ::std::option::Option<::chrono::NaiveDateTime> {
+        self.expires_at
+    }
+}
+#[automatically_derived]
+impl ::google_cloud::datastore::FromValue for Profile {
+    fn from_value(
+        value: ::google_cloud::datastore::Value,
+    ) -> ::std::result::Result<Profile, ::google_cloud::error::ConvertError> {
+        let mut props = match value {
+            ::google_cloud::datastore::Value::EntityValue(props) => props,
+            _ => {
+                return ::std::result::Result::Err(::google_cloud::error::ConvertError::UnexpectedPropertyType {
+                    expected: ::std::string::String::from("entity"),
+                    got: ::std::string::String::from(value.type_name()),
+                });
+            }
+        };
+        ::std::result::Result::Ok(Profile {
+            name: match props.remove("userName") {
+                ::std::option::Option::Some(value) => {
+                    ::google_cloud::datastore::FromValue::from_value(value)?
+                }
+                ::std::option::Option::None => ::std::string::String::new(),
+            },
+            age: match props.remove("age") {
+                ::std::option::Option::Some(value) => {
+                    ::google_cloud::datastore::FromValue::from_value(value)?
+                }
+                ::std::option::Option::None => 18,
+            },
+            joined_at: match props.remove("joined_at") {
+                ::std::option::Option::Some(value) => {
+                    ::google_cloud::datastore::FromValue::from_value(value)?
+                }
+                ::std::option::Option::None => ::chrono::DateTime::UNIX_EPOCH.naive_utc(),
+            },
+            expires_at: match props.remove("expires_at") {
+                ::std::option::Option::Some(value) => {
+                    ::google_cloud::datastore::FromValue::from_value(value)?
+                }
+                ::std::option::Option::None => ::std::option::Option::None,
+            },
+        })
+    }
+}
+#[automatically_derived]
+impl ::google_cloud::datastore::IntoValue for Profile {
+    fn into_value(self) -> ::google_cloud::datastore::Value {
+        let props = ::std::collections::HashMap::from([
+            (
+                ::std::string::String::from("userName"),
+                ::google_cloud::datastore::IntoValue::into_value(self.name),
+            ),
+            (
+                ::std::string::String::from("age"),
+                ::google_cloud::datastore::IntoValue::into_value(self.age),
+            ),
+            (
+                ::std::string::String::from("joined_at"),
+                ::google_cloud::datastore::IntoValue::into_value(self.joined_at),
+            ),
+            (
+                ::std::string::String::from("expires_at"),
+                ::google_cloud::datastore::IntoValue::into_value(self.expires_at),
+            ),
+        ]);
+        ::google_cloud::datastore::Value::EntityValue(props)
+    }
+}
@@ -0,0 +1,11 @@
+#[derive(FromValue, IntoValue)]
+#[datastore(rename_all = "snake_case")]
+pub struct Profile {
+    #[datastore(rename = "userName")]
+    name: String,
+    #[datastore(default = 18)]
+    age: i64,
+    joined_at: NaiveDateTime,
+    #[datastore(ttl)]
+    expires_at: Option<NaiveDateTime>,
+}
@@ -0,0 +1,137 @@
+#[automatically_derived]
+impl ::google_cloud::datastore::FromValue for Event {
+    fn from_value(
+        value: ::google_cloud::datastore::Value,
+    ) -> ::std::result::Result<Event, ::google_cloud::error::ConvertError> {
+        let mut props = match value {
+            ::google_cloud::datastore::Value::EntityValue(props) => props,
+            _ => {
+                return ::std::result::Result::Err(::google_cloud::error::ConvertError::UnexpectedPropertyType {
+                    expected: ::std::string::String::from("entity"),
+                    got: ::std::string::String::from(value.type_name()),
+                });
+            }
+        };
+        let variant: ::std::string::String = match props.remove("type") {
+            ::std::option::Option::Some(value) => {
+                ::google_cloud::datastore::FromValue::from_value(value)?
+            }
+            ::std::option::Option::None => {
+                return ::std::result::Result::Err(
+                    ::google_cloud::error::ConvertError::MissingProperty(
+                        ::std::string::String::from("type"),
+                    ),
+                );
+            }
+        };
+        {
+            let variant = variant.as_str();
+            if ::std::matches!(variant, "created") {
+                let inner = match props.get("content") {
+                    ::std::option::Option::Some(content) if props.len() == 1 => {
+                        let content = content.clone();
+                        match ::google_cloud::datastore::FromValue::from_value(
+                            ::google_cloud::datastore::Value::EntityValue(props),
+                        ) {
+                            ::std::result::Result::Ok(inner) => inner,
+                            ::std::result::Result::Err(_) => {
+                                ::google_cloud::datastore::FromValue::from_value(content)?
+                            }
+                        }
+                    }
+                    _ => {
+                        ::google_cloud::datastore::FromValue::from_value(
+                            ::google_cloud::datastore::Value::EntityValue(props),
+                        )?
+                    }
+                };
+                return ::std::result::Result::Ok(Event::Created(inner));
+            }
+            if ::std::matches!(variant, "renamed") {
+                let inner = match props.get("content") {
+                    ::std::option::Option::Some(content) if props.len() == 1 => {
+                        let content = content.clone();
+                        match ::google_cloud::datastore::FromValue::from_value(
+                            ::google_cloud::datastore::Value::EntityValue(props),
+                        ) {
+                            ::std::result::Result::Ok(inner) => inner,
+                            ::std::result::Result::Err(_) => {
+                                ::google_cloud::datastore::FromValue::from_value(content)?
+                            }
+                        }
+                    }
+                    _ => {
+                        ::google_cloud::datastore::FromValue::from_value(
+                            ::google_cloud::datastore::Value::EntityValue(props),
+                        )?
+                    }
+                };
+                return ::std::result::Result::Ok(Event::Renamed(inner));
+            }
+            if ::std::matches!(variant, "closed") {
+                return ::std::result::Result::Ok(Event::Closed);
+            }
+        }
+        ::std::result::Result::Err(
+            ::google_cloud::error::ConvertError::UnknownVariant(variant),
+        )
+    }
+}
+#[automatically_derived]
+impl ::google_cloud::datastore::IntoValue for Event {
+    fn into_value(self) -> ::google_cloud::datastore::Value {
+        match self {
+            Event::Created(inner) => {
+                let tag = ::google_cloud::datastore::Value::StringValue(
+                    ::std::string::String::from("created"),
+                );
+                let props = match ::google_cloud::datastore::IntoValue::into_value(
+                    inner,
+                ) {
+                    ::google_cloud::datastore::Value::EntityValue(mut props) => {
+                        props.insert(::std::string::String::from("type"), tag);
+                        props
+                    }
+                    value => {
+                        ::std::collections::HashMap::from([
+                            (::std::string::String::from("type"), tag),
+                            (::std::string::String::from("content"), value),
+                        ])
+                    }
+                };
+                ::google_cloud::datastore::Value::EntityValue(props)
+            }
+            Event::Renamed(inner) => {
+                let tag = ::google_cloud::datastore::Value::StringValue(
+                    ::std::string::String::from("renamed"),
+                );
+                let props = match ::google_cloud::datastore::IntoValue::into_value(
+                    inner,
+                ) {
+                    ::google_cloud::datastore::Value::EntityValue(mut props) => {
+                        props.insert(::std::string::String::from("type"), tag);
+                        props
+                    }
+                    value => {
+                        ::std::collections::HashMap::from([
+                            (::std::string::String::from("type"), tag),
+                            (::std::string::String::from("content"), value),
+                        ])
+                    }
+                };
+                ::google_cloud::datastore::Value::EntityValue(props)
+            }
+            Event::Closed => {
+                let props = ::std::collections::HashMap::from([
+                    (
+                        ::std::string::String::from("type"),
+                        ::google_cloud::datastore::Value::StringValue(
+                            ::std::string::String::from("closed"),
+                        ),
+                    ),
+                ]);
+                ::google_cloud::datastore::Value::EntityValue(props)
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+#[derive(FromValue, IntoValue)]
+#[datastore(tag = "type")]
+pub enum Event {
+    Created(Profile),
+    Renamed(String),
+    Closed,
+}
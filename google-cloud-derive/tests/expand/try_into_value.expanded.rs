@@ -0,0 +1,27 @@
+#[automatically_derived]
+impl ::google_cloud::datastore::TryIntoValue for Counter {
+    fn try_into_value(
+        self,
+    ) -> ::std::result::Result<
+        ::google_cloud::datastore::Value,
+        ::google_cloud::error::ConvertError,
+    > {
+        let props = ::std::collections::HashMap::from([
+            (
+                ::std::string::String::from("name"),
+                ::google_cloud::datastore::TryIntoValue::try_into_value(self.name)?,
+            ),
+            (
+                ::std::string::String::from("total"),
+                ::google_cloud::datastore::TryIntoValue::try_into_value(self.total)?,
+            ),
+            (
+                ::std::string::String::from("samples"),
+                ::google_cloud::datastore::TryIntoValue::try_into_value(
+                    ::google_cloud::datastore::TryEach(self.samples),
+                )?,
+            ),
+        ]);
+        ::std::result::Result::Ok(::google_cloud::datastore::Value::EntityValue(props))
+    }
+}
@@ -0,0 +1,7 @@
+#[derive(TryIntoValue)]
+pub struct Counter {
+    name: String,
+    total: u64,
+    #[datastore(try_each)]
+    samples: Vec<u64>,
+}
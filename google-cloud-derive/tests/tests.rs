@@ -6,4 +6,15 @@ fn tests() {
     tests.pass("tests/01-simple.rs");
     tests.pass("tests/02-nested.rs");
     tests.pass("tests/03-enums.rs");
+    tests.pass("tests/04-ttl.rs");
+    tests.pass("tests/05-tagged-enums.rs");
+    tests.pass("tests/06-array-policies.rs");
+    tests.pass("tests/07-durations.rs");
+    tests.pass("tests/08-enum-aliases.rs");
+    tests.pass("tests/09-strict-output.rs");
+    tests.pass("tests/10-try-into-value.rs");
+    tests.pass("tests/11-timestamps.rs");
+    tests.pass("tests/12-projections.rs");
+    tests.compile_fail("tests/ui/into-and-try-into-value.rs");
+    tests.compile_fail("tests/ui/empty-projection.rs");
 }
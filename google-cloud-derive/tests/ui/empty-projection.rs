@@ -0,0 +1,6 @@
+use google_cloud::datastore::{FromValue, Projection};
+
+#[derive(Debug, FromValue, Projection)]
+pub struct Unit {}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use google_cloud::datastore::{IntoValue, TryIntoValue};
+
+//? Deriving `IntoValue` already makes the type `TryIntoValue`.
+#[derive(IntoValue, TryIntoValue)]
+pub struct Owner {
+    name: String,
+}
+
+fn main() {}
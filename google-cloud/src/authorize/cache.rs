@@ -0,0 +1,162 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+
+use crate::authorize::{Token, TokenManager, TokenProvider};
+use crate::error::AuthError;
+
+/// Obtains new tokens, for a `TokenCache` to serve.
+pub(crate) trait FetchToken: Send + 'static {
+    /// Obtains a new token, regardless of the cached one.
+    fn fetch(&mut self) -> impl Future<Output = Result<Token, AuthError>> + Send;
+
+    /// The project billed for the requests authenticated by the tokens, if any.
+    fn quota_project(&self) -> Option<String> {
+        None
+    }
+}
+
+impl FetchToken for TokenManager {
+    fn fetch(&mut self) -> impl Future<Output = Result<Token, AuthError>> + Send {
+        TokenManager::fetch(self)
+    }
+
+    fn quota_project(&self) -> Option<String> {
+        self.source.quota_project()
+    }
+}
+
+/// A cache of access tokens, shared by all the requests of a client.
+///
+/// The cached token is read without waiting on other requests, and is renewed in the
+/// background ahead of its expiry. If it expires anyway (when the background renewal fails,
+/// or outside of a Tokio runtime), a single request renews it while the others wait for it.
+pub(crate) struct TokenCache<F = TokenManager> {
+    shared: Arc<Shared<F>>,
+}
+
+struct Shared<F> {
+    token: RwLock<Option<Token>>,
+    fetcher: Mutex<F>,
+    quota_project: Option<String>,
+    refresh_ahead: TimeDelta,
+    refresher_started: AtomicBool,
+}
+
+impl<F: FetchToken> TokenCache<F> {
+    /// How long before its expiry a token is renewed in the background.
+    pub(crate) const DEFAULT_REFRESH_AHEAD: TimeDelta = TimeDelta::minutes(5);
+    /// How long to wait before trying again when a background renewal fails.
+    const RETRY_DELAY: Duration = Duration::from_secs(10);
+    /// The shortest wait between background renewals, for tokens which are short-lived
+    /// (or already within their expiry margin) when they are obtained.
+    const MIN_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+    pub(crate) fn new(fetcher: F) -> TokenCache<F> {
+        TokenCache::with_refresh_ahead(fetcher, TokenCache::<F>::DEFAULT_REFRESH_AHEAD)
+    }
+
+    pub(crate) fn with_refresh_ahead(fetcher: F, refresh_ahead: TimeDelta) -> TokenCache<F> {
+        let shared = Shared {
+            token: RwLock::new(None),
+            quota_project: fetcher.quota_project(),
+            fetcher: Mutex::new(fetcher),
+            refresh_ahead,
+            refresher_started: AtomicBool::new(false),
+        };
+        TokenCache { shared: Arc::new(shared) }
+    }
+
+    pub(crate) async fn token(&self) -> Result<String, AuthError> {
+        if let Some((token, _)) = self.shared.cached(Utc::now()) {
+            return Ok(token);
+        }
+
+        let token = self.shared.renew(Utc::now()).await?;
+        self.start_refresher();
+        Ok(token)
+    }
+
+    /// Starts renewing the token in the background, if it is not done already.
+    fn start_refresher(&self) {
+        if self.shared.refresher_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            self.shared.refresher_started.store(false, Ordering::SeqCst);
+            return;
+        };
+
+        //? The task only holds a weak reference, to stop once the clients are gone.
+        let shared = Arc::downgrade(&self.shared);
+        runtime.spawn(refresh_loop(shared));
+    }
+}
+
+async fn refresh_loop<F: FetchToken>(shared: Weak<Shared<F>>) {
+    while let Some((delay, expiry)) = shared.upgrade().and_then(|shared| shared.refresh_delay()) {
+        tokio::time::sleep(delay).await;
+
+        let Some(cache) = shared.upgrade() else {
+            break;
+        };
+        if let Err(err) = cache.renew(expiry).await {
+            log::warn!("failed to renew the access token ahead of its expiry: {}", err);
+            drop(cache);
+            tokio::time::sleep(TokenCache::<F>::RETRY_DELAY).await;
+        }
+    }
+}
+
+impl<F: FetchToken> Shared<F> {
+    /// The cached token and its expiry, if it is still valid at `now`.
+    fn cached(&self, now: DateTime<Utc>) -> Option<(String, DateTime<Utc>)> {
+        let token = self.token.read().unwrap();
+        token
+            .as_ref()
+            .filter(|token| token.expiry > now)
+            .map(|token| (token.value.access_token(), token.expiry))
+    }
+
+    /// Fetches a new token, unless the cached one expires after `stale_until`.
+    ///
+    /// Renewals are serialized: the callers waiting for one get its token.
+    async fn renew(&self, stale_until: DateTime<Utc>) -> Result<String, AuthError> {
+        let mut fetcher = self.fetcher.lock().await;
+        if let Some((token, _)) = self.cached(stale_until) {
+            return Ok(token);
+        }
+
+        let token = fetcher.fetch().await?;
+        let access_token = token.value.access_token();
+        *self.token.write().unwrap() = Some(token);
+        Ok(access_token)
+    }
+
+    /// How long to wait before renewing the cached token, and its current expiry.
+    ///
+    /// Short-lived tokens are renewed halfway through their remaining lifetime at the latest,
+    /// but never less than a second after the previous renewal.
+    fn refresh_delay(&self) -> Option<(Duration, DateTime<Utc>)> {
+        let expiry = self.token.read().unwrap().as_ref()?.expiry;
+        let remaining = expiry - Utc::now();
+        let ahead = self.refresh_ahead.min(remaining / 2);
+        let delay = (remaining - ahead).to_std().unwrap_or(Duration::ZERO);
+        Some((delay.max(TokenCache::<F>::MIN_REFRESH_DELAY), expiry))
+    }
+}
+
+impl<F: FetchToken> TokenProvider for TokenCache<F> {
+    fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+        Box::pin(TokenCache::token(self))
+    }
+
+    fn quota_project(&self) -> Option<String> {
+        self.shared.quota_project.clone()
+    }
+}
@@ -1,19 +1,43 @@
+use std::env;
 use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use chrono::DateTime;
 use chrono::{offset::Utc, TimeDelta};
+use futures::future::BoxFuture;
 use hyper::client::{Client, HttpConnector};
 use hyper_rustls::HttpsConnector;
 use json::json;
 use serde::{Deserialize, Serialize};
 
-use crate::error::AuthError;
+use crate::error::{AuthError, CredentialsError};
+
+mod cache;
+
+pub(crate) use self::cache::*;
 
 #[allow(unused)]
 pub(crate) const TLS_CERTS: &[u8] = include_bytes!("../../roots.pem");
 
 const AUTH_ENDPOINT: &str = "https://oauth2.googleapis.com/token";
 
+/// The header naming the project billed for the requests, and whose quota they use.
+pub(crate) const QUOTA_PROJECT_HEADER: &str = "x-goog-user-project";
+
+/// The endpoint of the IAM Credentials API, used to impersonate service accounts.
+const IAM_CREDENTIALS_ENDPOINT: &str = "https://iamcredentials.googleapis.com/v1";
+/// The scope needed by the source credentials to impersonate a service account.
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// The host of the metadata server, reachable from GCE, GKE and Cloud Run.
+const METADATA_HOST: &str = "metadata.google.internal";
+/// The environment variable overriding the host of the metadata server.
+const METADATA_HOST_ENV_VAR: &str = "GCE_METADATA_HOST";
+
 /// Represents application credentials for accessing Google Cloud Platform services.
 #[allow(missing_docs)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -31,11 +55,344 @@ pub struct ApplicationCredentials {
     pub client_x509_cert_url: String,
 }
 
+impl ApplicationCredentials {
+    /// The `type` of service account key files.
+    pub const TYPE: &'static str = "service_account";
+    /// The environment variable holding the path of the credentials file.
+    pub const ENV_VAR: &'static str = "GOOGLE_APPLICATION_CREDENTIALS";
+    /// The environment variable holding the contents of the credentials file.
+    pub const JSON_ENV_VAR: &'static str = "GOOGLE_APPLICATION_CREDENTIALS_JSON";
+
+    /// Loads the credentials from the environment.
+    ///
+    /// They are read from the file pointed to by `GOOGLE_APPLICATION_CREDENTIALS` or,
+    /// when it is not set, from the JSON held by `GOOGLE_APPLICATION_CREDENTIALS_JSON`.
+    pub fn from_env() -> Result<ApplicationCredentials, CredentialsError> {
+        if let Some(path) = env_var(ApplicationCredentials::ENV_VAR)? {
+            return ApplicationCredentials::from_file(path);
+        }
+        if let Some(contents) = env_var(ApplicationCredentials::JSON_ENV_VAR)? {
+            return ApplicationCredentials::from_json_str(&contents);
+        }
+
+        Err(CredentialsError::MissingEnv(ApplicationCredentials::ENV_VAR.into()))
+    }
+
+    /// Loads the credentials from the contents of a service account key file.
+    pub fn from_json_str(contents: &str) -> Result<ApplicationCredentials, CredentialsError> {
+        json::from_str(contents).map_err(CredentialsError::InvalidJson)
+    }
+
+    /// Loads the credentials from a service account key file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ApplicationCredentials, CredentialsError> {
+        let path = path.as_ref();
+        let contents = read_file(path)?;
+
+        json::from_slice(&contents)
+            .map_err(|source| CredentialsError::Invalid { path: path.to_path_buf(), source })
+    }
+}
+
+/// Represents the credentials of a user, as written by `gcloud auth application-default login`.
+///
+/// Access tokens are obtained by exchanging the refresh token at the OAuth endpoint.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizedUserCredentials {
+    #[serde(rename = "type")]
+    pub cred_type: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_project_id: Option<String>,
+}
+
+impl AuthorizedUserCredentials {
+    /// The `type` of authorized user credentials files.
+    pub const TYPE: &'static str = "authorized_user";
+
+    /// Loads the credentials from the contents of an authorized user file.
+    pub fn from_json_str(contents: &str) -> Result<AuthorizedUserCredentials, CredentialsError> {
+        json::from_str(contents).map_err(CredentialsError::InvalidJson)
+    }
+
+    /// Loads the credentials from an authorized user file.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+    ) -> Result<AuthorizedUserCredentials, CredentialsError> {
+        let path = path.as_ref();
+        let contents = read_file(path)?;
+
+        json::from_slice(&contents)
+            .map_err(|source| CredentialsError::Invalid { path: path.to_path_buf(), source })
+    }
+
+    /// The path of the file written by `gcloud auth application-default login`, if any.
+    pub fn well_known_file() -> Option<PathBuf> {
+        let config = match env::var_os("CLOUDSDK_CONFIG") {
+            Some(config) => PathBuf::from(config),
+            None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?).join("gcloud"),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config").join("gcloud"),
+        };
+        Some(config.join("application_default_credentials.json"))
+    }
+}
+
+/// Represents credentials impersonating a service account, through the IAM Credentials API.
+///
+/// The source credentials are only used to obtain short-lived tokens for the target service
+/// account, on which they need the `roles/iam.serviceAccountTokenCreator` role (or, with
+/// delegates, on the first delegate of the chain, which needs it on the next one, and so on).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpersonatedCredentials {
+    target_principal: String,
+    delegates: Vec<String>,
+    lifetime: Duration,
+    source: Box<TokenSource>,
+}
+
+impl ImpersonatedCredentials {
+    /// The `type` of impersonated credentials files.
+    pub const TYPE: &'static str = "impersonated_service_account";
+    /// The default lifetime of the impersonated tokens.
+    pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+
+    /// Impersonates `target_principal` (a service account email) with the credentials
+    /// of the environment, looked up as described in the documentation of this module.
+    pub fn from_env(
+        target_principal: impl Into<String>,
+    ) -> Result<ImpersonatedCredentials, CredentialsError> {
+        Ok(ImpersonatedCredentials::from_source(TokenSource::from_env()?, target_principal))
+    }
+
+    /// Impersonates `target_principal` with a service account key.
+    pub fn from_service_account(
+        creds: ApplicationCredentials,
+        target_principal: impl Into<String>,
+    ) -> ImpersonatedCredentials {
+        let source = TokenSource::ServiceAccount(Box::new(creds));
+        ImpersonatedCredentials::from_source(source, target_principal)
+    }
+
+    /// Impersonates `target_principal` with the credentials of a user.
+    pub fn from_authorized_user(
+        creds: AuthorizedUserCredentials,
+        target_principal: impl Into<String>,
+    ) -> ImpersonatedCredentials {
+        let source = TokenSource::AuthorizedUser(creds);
+        ImpersonatedCredentials::from_source(source, target_principal)
+    }
+
+    /// Impersonates `target_principal` with the service account of the metadata server.
+    pub fn from_metadata_server(target_principal: impl Into<String>) -> ImpersonatedCredentials {
+        ImpersonatedCredentials::from_source(TokenSource::MetadataServer, target_principal)
+    }
+
+    pub(crate) fn from_source(
+        source: TokenSource,
+        target_principal: impl Into<String>,
+    ) -> ImpersonatedCredentials {
+        ImpersonatedCredentials {
+            target_principal: target_principal.into(),
+            delegates: Vec::new(),
+            lifetime: ImpersonatedCredentials::DEFAULT_LIFETIME,
+            source: Box::new(source),
+        }
+    }
+
+    /// Sets the chain of service accounts (by email) through which the target is impersonated.
+    pub fn delegates<T>(mut self, delegates: impl IntoIterator<Item = T>) -> ImpersonatedCredentials
+    where
+        T: Into<String>,
+    {
+        self.delegates = delegates.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the lifetime of the impersonated tokens (up to an hour, unless the organization
+    /// allows more).
+    pub fn lifetime(mut self, lifetime: Duration) -> ImpersonatedCredentials {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// The email of the impersonated service account.
+    pub fn target_principal(&self) -> &str {
+        self.target_principal.as_str()
+    }
+
+    /// The chain of service accounts through which the target is impersonated.
+    pub fn get_delegates(&self) -> &[String] {
+        self.delegates.as_slice()
+    }
+
+    pub(crate) fn token_uri(&self) -> String {
+        format!(
+            "{}/{}:generateAccessToken",
+            IAM_CREDENTIALS_ENDPOINT,
+            service_account_name(&self.target_principal)
+        )
+    }
+
+    pub(crate) fn request_body(&self, scopes: &str) -> json::Value {
+        let delegates: Vec<String> =
+            self.delegates.iter().map(|delegate| service_account_name(delegate)).collect();
+        json!({
+            "delegates": delegates,
+            "scope": scopes.split(' ').collect::<Vec<_>>(),
+            "lifetime": format!("{}s", self.lifetime.as_secs()),
+        })
+    }
+}
+
+/// The resource name of a service account, given its email (or its resource name already).
+fn service_account_name(email: &str) -> String {
+    if email.starts_with("projects/") {
+        email.to_string()
+    } else {
+        format!("projects/-/serviceAccounts/{}", email)
+    }
+}
+
+/// The contents of a file written by
+/// `gcloud auth application-default login --impersonate-service-account`.
+#[derive(Deserialize)]
+struct ImpersonatedCredentialsFile {
+    service_account_impersonation_url: String,
+    #[serde(default)]
+    delegates: Vec<String>,
+    source_credentials: json::Value,
+}
+
+impl ImpersonatedCredentialsFile {
+    fn into_credentials(self) -> Result<ImpersonatedCredentials, json::Error> {
+        let target = self
+            .service_account_impersonation_url
+            .rsplit('/')
+            .next()
+            .and_then(|target| target.strip_suffix(":generateAccessToken"))
+            .ok_or_else(|| {
+                serde::de::Error::custom("invalid `service_account_impersonation_url`")
+            })?;
+        let source = TokenSource::parse(&json::to_vec(&self.source_credentials)?)?;
+
+        Ok(ImpersonatedCredentials::from_source(source, target).delegates(self.delegates))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImpersonationResponse {
+    pub(crate) access_token: String,
+    pub(crate) expire_time: String,
+}
+
+impl ImpersonationResponse {
+    /// The expiry of the token, with a margin to renew it before it gets rejected.
+    pub(crate) fn expiry(&self) -> Option<DateTime<Utc>> {
+        let margin = TimeDelta::try_minutes(1).unwrap();
+        let expire_time = DateTime::parse_from_rfc3339(&self.expire_time).ok()?;
+        Some(expire_time.with_timezone(&Utc) - margin)
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, CredentialsError> {
+    fs::read(path).map_err(|source| match source.kind() {
+        io::ErrorKind::NotFound => CredentialsError::NotFound(path.to_path_buf()),
+        _ => CredentialsError::Unreadable { path: path.to_path_buf(), source },
+    })
+}
+
+fn env_var(name: &str) -> Result<Option<String>, CredentialsError> {
+    match env::var_os(name) {
+        Some(value) => {
+            value.into_string().map(Some).map_err(|_| CredentialsError::InvalidEnv(name.into()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// A source of access tokens, to authenticate the requests of the clients.
+///
+/// The clients use the credentials of the environment by default: implement this trait
+/// to get tokens from elsewhere (a vault, a proxy, or a test double) and hand it to
+/// `from_token_provider`.
+///
+/// ```
+/// # use futures::future::BoxFuture;
+/// # use google_cloud::authorize::TokenProvider;
+/// # use google_cloud::error::AuthError;
+/// struct FixedToken(String);
+///
+/// impl TokenProvider for FixedToken {
+///     fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+///         Box::pin(async move { Ok(self.0.clone()) })
+///     }
+/// }
+/// ```
+pub trait TokenProvider: Send + Sync {
+    /// Returns a valid access token, sent as a bearer token with the requests.
+    ///
+    /// It is called before every request: caching the token is up to the implementation.
+    fn token(&self) -> BoxFuture<'_, Result<String, AuthError>>;
+
+    /// Returns the project billed for the requests, sent in the `x-goog-user-project` header.
+    ///
+    /// User credentials need one, as the APIs would otherwise bill the project of the
+    /// OAuth client (which they reject). There is none by default.
+    fn quota_project(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T: TokenProvider + ?Sized> TokenProvider for Arc<T> {
+    fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+        (**self).token()
+    }
+
+    fn quota_project(&self) -> Option<String> {
+        (**self).quota_project()
+    }
+}
+
+/// Gets a token from the provider, formatted as the value of an `authorization` header.
+pub(crate) async fn authorization(provider: &dyn TokenProvider) -> Result<String, AuthError> {
+    Ok(TokenValue::Bearer(provider.token().await?).to_string())
+}
+
+/// Adds the headers of the provider (its token, and its quota project if any) to a request.
+///
+/// Only the gRPC clients use it, the storage client sends the quota project by default.
+#[allow(unused)]
+pub(crate) async fn authorize_request<T>(
+    provider: &dyn TokenProvider,
+    request: &mut tonic::Request<T>,
+) -> Result<(), AuthError> {
+    let token = authorization(provider).await?;
+    let metadata = request.metadata_mut();
+    let token = token.parse().map_err(|_| AuthError::InvalidHeader("authorization"))?;
+    metadata.insert("authorization", token);
+    if let Some(project) = provider.quota_project() {
+        let value = project.parse().map_err(|_| AuthError::InvalidHeader(QUOTA_PROJECT_HEADER))?;
+        metadata.insert(QUOTA_PROJECT_HEADER, value);
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum TokenValue {
     Bearer(String),
 }
 
+impl TokenValue {
+    pub(crate) fn access_token(&self) -> String {
+        match self {
+            TokenValue::Bearer(token) => token.clone(),
+        }
+    }
+}
+
 impl fmt::Display for TokenValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -46,81 +403,270 @@ impl fmt::Display for TokenValue {
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Token {
-    value: TokenValue,
-    expiry: DateTime<Utc>,
+    pub(crate) value: TokenValue,
+    pub(crate) expiry: DateTime<Utc>,
+}
+
+/// Where the access tokens are obtained from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenSource {
+    /// Tokens are obtained by signing JWTs with a service account key.
+    ServiceAccount(Box<ApplicationCredentials>),
+    /// Tokens are obtained by exchanging the refresh token of a user.
+    AuthorizedUser(AuthorizedUserCredentials),
+    /// Tokens are obtained from the metadata server, for the service account attached
+    /// to the instance (or bound to the pod through workload identity, on GKE).
+    MetadataServer,
+    /// Tokens are obtained by impersonating a service account with other credentials.
+    Impersonated(ImpersonatedCredentials),
+}
+
+impl TokenSource {
+    /// Resolves the credentials from the environment, in the order described in the
+    /// documentation of this module.
+    pub(crate) fn from_env() -> Result<TokenSource, CredentialsError> {
+        if let Some(path) = env_var(ApplicationCredentials::ENV_VAR)? {
+            return TokenSource::from_file(path.as_ref());
+        }
+        if let Some(contents) = env_var(ApplicationCredentials::JSON_ENV_VAR)? {
+            return TokenSource::parse(contents.as_bytes()).map_err(CredentialsError::InvalidJson);
+        }
+        match AuthorizedUserCredentials::well_known_file() {
+            Some(path) if path.is_file() => TokenSource::from_file(&path),
+            _ => Ok(TokenSource::MetadataServer),
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<TokenSource, CredentialsError> {
+        let contents = read_file(path)?;
+
+        TokenSource::parse(&contents)
+            .map_err(|source| CredentialsError::Invalid { path: path.to_path_buf(), source })
+    }
+
+    /// Parses credentials of any supported type, dispatching on their `type` field.
+    pub(crate) fn parse(contents: &[u8]) -> Result<TokenSource, json::Error> {
+        #[derive(Deserialize)]
+        struct CredentialsType {
+            #[serde(rename = "type")]
+            cred_type: String,
+        }
+
+        let kind: CredentialsType = json::from_slice(contents)?;
+        match kind.cred_type.as_str() {
+            AuthorizedUserCredentials::TYPE => {
+                Ok(TokenSource::AuthorizedUser(json::from_slice(contents)?))
+            }
+            ImpersonatedCredentials::TYPE => {
+                let file: ImpersonatedCredentialsFile = json::from_slice(contents)?;
+                Ok(TokenSource::Impersonated(file.into_credentials()?))
+            }
+            ApplicationCredentials::TYPE => {
+                Ok(TokenSource::ServiceAccount(Box::new(json::from_slice(contents)?)))
+            }
+            other => {
+                Err(serde::de::Error::custom(format!("unsupported credentials type `{}`", other)))
+            }
+        }
+    }
+
+    /// The project billed for the requests, when the credentials name one.
+    pub(crate) fn quota_project(&self) -> Option<String> {
+        match self {
+            TokenSource::AuthorizedUser(creds) => creds.quota_project_id.clone(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct TokenManager {
     client: Client<HttpsConnector<HttpConnector>>,
     scopes: String,
-    creds: ApplicationCredentials,
+    source: TokenSource,
     current_token: Option<Token>,
+    /// The token manager of the source credentials, when impersonating a service account.
+    source_manager: Option<Box<TokenManager>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-struct AuthResponse {
-    access_token: String,
+pub(crate) struct AuthResponse {
+    pub(crate) access_token: String,
+    #[serde(default)]
+    pub(crate) expires_in: Option<i64>,
+}
+
+impl AuthResponse {
+    /// The expiry of the token, with a margin to renew it before it gets rejected.
+    pub(crate) fn expiry(&self, issued_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let margin = TimeDelta::try_minutes(1).unwrap();
+        let lifetime = TimeDelta::try_seconds(self.expires_in?)?;
+        Some(issued_at + lifetime - margin)
+    }
+}
+
+pub(crate) fn metadata_token_uri(scopes: &str) -> String {
+    let host = env::var(METADATA_HOST_ENV_VAR).unwrap_or_else(|_| METADATA_HOST.to_string());
+    let scopes = scopes.replace(' ', ",");
+    format!(
+        "http://{}/computeMetadata/v1/instance/service-accounts/default/token?scopes={}",
+        host, scopes
+    )
 }
 
 impl TokenManager {
     pub(crate) fn new(creds: ApplicationCredentials, scopes: &[&str]) -> TokenManager {
+        TokenManager::from_source(TokenSource::ServiceAccount(Box::new(creds)), scopes)
+    }
+
+    /// Creates a token manager from the credentials found in the environment,
+    /// or from the metadata server if there are none (see `TokenSource::from_env`).
+    pub(crate) fn from_env(scopes: &[&str]) -> Result<TokenManager, CredentialsError> {
+        Ok(TokenManager::from_source(TokenSource::from_env()?, scopes))
+    }
+
+    pub(crate) fn from_source(source: TokenSource, scopes: &[&str]) -> TokenManager {
+        //? The metadata server is only reachable over plain HTTP.
         let https = hyper_rustls::HttpsConnectorBuilder::new()
             .with_native_roots()
-            .https_only()
+            .https_or_http()
             .enable_http1()
             .build();
 
+        let source_manager = match &source {
+            TokenSource::Impersonated(creds) => {
+                let source = creds.source.as_ref().clone();
+                Some(Box::new(TokenManager::from_source(source, &[CLOUD_PLATFORM_SCOPE])))
+            }
+            _ => None,
+        };
+
         TokenManager {
-            creds,
+            source,
             client: Client::builder().build::<_, hyper::Body>(https),
             scopes: scopes.join(" "),
             current_token: None,
+            source_manager,
         }
     }
 
     pub(crate) async fn token(&mut self) -> Result<String, AuthError> {
-        let hour = TimeDelta::try_minutes(45).unwrap();
-        let current_time = chrono::Utc::now();
         match self.current_token {
-            Some(ref token) if token.expiry >= current_time => Ok(token.value.to_string()),
+            Some(ref token) if token.expiry >= chrono::Utc::now() => Ok(token.value.access_token()),
             _ => {
-                let expiry = current_time + hour;
-                let claims = json!({
-                    "iss": self.creds.client_email.as_str(),
-                    "scope": self.scopes.as_str(),
-                    "aud": AUTH_ENDPOINT,
-                    "exp": expiry.timestamp(),
-                    "iat": current_time.timestamp(),
-                });
-                let token = jwt::encode(
-                    &jwt::Header::new(jwt::Algorithm::RS256),
-                    &claims,
-                    &jwt::EncodingKey::from_rsa_pem(&self.creds.private_key.as_bytes())?,
-                )?;
-                let form = format!(
-                    "grant_type=urn:ietf:params:oauth:grant-type:jwt-bearer&assertion={}",
-                    token.as_str()
-                );
+                let token = self.fetch().await?;
+                let access_token = token.value.access_token();
+                self.current_token = Some(token);
+
+                Ok(access_token)
+            }
+        }
+    }
+
+    /// Obtains a new token from the source, regardless of the cached one.
+    pub(crate) async fn fetch(&mut self) -> Result<Token, AuthError> {
+        let current_time = chrono::Utc::now();
+        //? The source token is cached by its own manager, to only mint new ones
+        //? when it expires.
+        let source_token = match self.source_manager.as_mut() {
+            Some(manager) => Some(Box::pin(manager.token()).await?),
+            None => None,
+        };
 
+        let (req, default_expiry) = match &self.source {
+            TokenSource::ServiceAccount(creds) => {
+                let expiry = current_time + TimeDelta::try_minutes(45).unwrap();
+                (self.service_account_request(creds, current_time, expiry)?, expiry)
+            }
+            TokenSource::AuthorizedUser(creds) => {
+                let body = json!({
+                    "grant_type": "refresh_token",
+                    "client_id": creds.client_id.as_str(),
+                    "client_secret": creds.client_secret.as_str(),
+                    "refresh_token": creds.refresh_token.as_str(),
+                });
                 let req = hyper::Request::builder()
                     .method("POST")
                     .uri(AUTH_ENDPOINT)
-                    .header("Content-Type", "application/x-www-form-urlencoded")
-                    .body(hyper::Body::from(form))?;
+                    .header("Content-Type", "application/json")
+                    .body(hyper::Body::from(body.to_string()))?;
+                (req, current_time + TimeDelta::try_minutes(45).unwrap())
+            }
+            TokenSource::MetadataServer => {
+                let req = hyper::Request::builder()
+                    .method("GET")
+                    .uri(metadata_token_uri(&self.scopes))
+                    .header("Metadata-Flavor", "Google")
+                    .body(hyper::Body::empty())?;
+                (req, current_time + TimeDelta::try_minutes(5).unwrap())
+            }
+            TokenSource::Impersonated(creds) => {
+                let req = hyper::Request::builder()
+                    .method("POST")
+                    .uri(creds.token_uri())
+                    .header("Content-Type", "application/json")
+                    .header(
+                        "Authorization",
+                        TokenValue::Bearer(source_token.unwrap_or_default()).to_string(),
+                    )
+                    .body(hyper::Body::from(creds.request_body(&self.scopes).to_string()))?;
+                (req, current_time + TimeDelta::try_minutes(5).unwrap())
+            }
+        };
+
+        let response = self.client.request(req).await?;
+        let status = response.status();
+        let data = hyper::body::to_bytes(response.into_body()).await?.to_vec();
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&data).into_owned();
+            return Err(AuthError::Endpoint { status, body });
+        }
+
+        let (access_token, expiry) = match &self.source {
+            TokenSource::Impersonated(_) => {
+                let response: ImpersonationResponse = json::from_slice(&data)?;
+                let expiry = response.expiry().unwrap_or(default_expiry);
+                (response.access_token, expiry)
+            }
+            _ => {
+                let response: AuthResponse = json::from_slice(&data)?;
+                let expiry = response.expiry(current_time).unwrap_or(default_expiry);
+                (response.access_token, expiry)
+            }
+        };
 
-                let data = hyper::body::to_bytes(self.client.request(req).await?.into_body())
-                    .await?
-                    .to_vec();
+        Ok(Token { expiry, value: TokenValue::Bearer(access_token) })
+    }
 
-                let ar: AuthResponse = json::from_slice(&data)?;
+    fn service_account_request(
+        &self,
+        creds: &ApplicationCredentials,
+        current_time: DateTime<Utc>,
+        expiry: DateTime<Utc>,
+    ) -> Result<hyper::Request<hyper::Body>, AuthError> {
+        let claims = json!({
+            "iss": creds.client_email.as_str(),
+            "scope": self.scopes.as_str(),
+            "aud": AUTH_ENDPOINT,
+            "exp": expiry.timestamp(),
+            "iat": current_time.timestamp(),
+        });
+        let token = jwt::encode(
+            &jwt::Header::new(jwt::Algorithm::RS256),
+            &claims,
+            &jwt::EncodingKey::from_rsa_pem(creds.private_key.as_bytes())?,
+        )?;
+        let form = format!(
+            "grant_type=urn:ietf:params:oauth:grant-type:jwt-bearer&assertion={}",
+            token.as_str()
+        );
 
-                let value = TokenValue::Bearer(ar.access_token);
-                let token = value.to_string();
-                self.current_token = Some(Token { expiry, value });
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri(AUTH_ENDPOINT)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(hyper::Body::from(form))?;
 
-                Ok(token)
-            }
-        }
+        Ok(req)
     }
 }
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use crate::datastore::api;
+use crate::datastore::api::aggregation_query::aggregation::{Avg, Count, Operator, Sum};
+use crate::datastore::Value;
+
+/// Aggregation operations, each one stored under an alias in the results.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// Count of the entities matching the query, stored under the given alias.
+    ///
+    /// The count stops at 1000 entities, use `Aggregation::CountUpTo` for another limit.
+    Count(String),
+    /// Count of the entities matching the query, stopping at the given number of entities (alias, up_to).
+    ///
+    /// The limit bounds the number of index entries scanned, thus the latency and the cost
+    /// of the aggregation.
+    CountUpTo(String, i64),
+    /// Sum of a property (alias, property).
+    Sum(String, String),
+    /// Average of a property (alias, property).
+    Avg(String, String),
+}
+
+impl Aggregation {
+    /// The default limit of `Aggregation::Count`.
+    pub const DEFAULT_COUNT_UP_TO: i64 = 1000;
+}
+
+/// The results of an aggregation query, by alias.
+///
+/// ```
+/// # use google_cloud::datastore::AggregationResults;
+/// let results = AggregationResults::default();
+/// assert_eq!(results.count("total"), None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregationResults {
+    pub(crate) properties: HashMap<String, Value>,
+}
+
+impl AggregationResults {
+    /// Gets the result of a `Count` (or `CountUpTo`) aggregation.
+    pub fn count(&self, alias: &str) -> Option<i64> {
+        match self.properties.get(alias) {
+            Some(Value::IntegerValue(count)) => Some(*count),
+            _ => None,
+        }
+    }
+
+    /// Gets the result of a `Sum` aggregation.
+    ///
+    /// Sums of integers are converted to floating-point values, see `AggregationResults::sum_integer`
+    /// to keep their exact value.
+    pub fn sum(&self, alias: &str) -> Option<f64> {
+        match self.properties.get(alias) {
+            Some(Value::IntegerValue(sum)) => Some(*sum as f64),
+            Some(Value::DoubleValue(sum)) => Some(*sum),
+            _ => None,
+        }
+    }
+
+    /// Gets the result of a `Sum` aggregation over integer values only.
+    ///
+    /// Datastore falls back to a floating-point sum when some of the values are floating-point,
+    /// or when the sum overflows: the result is then `None`.
+    pub fn sum_integer(&self, alias: &str) -> Option<i64> {
+        match self.properties.get(alias) {
+            Some(Value::IntegerValue(sum)) => Some(*sum),
+            _ => None,
+        }
+    }
+
+    /// Gets the result of an `Avg` aggregation.
+    ///
+    /// The average is `None` when no entity has a numeric value for the property.
+    pub fn avg(&self, alias: &str) -> Option<f64> {
+        match self.properties.get(alias) {
+            Some(Value::DoubleValue(avg)) => Some(*avg),
+            _ => None,
+        }
+    }
+
+    /// Gets the raw result of an aggregation.
+    pub fn get(&self, alias: &str) -> Option<&Value> {
+        self.properties.get(alias)
+    }
+
+    /// Consumes the results, returning the raw values by alias.
+    pub fn into_inner(self) -> HashMap<String, Value> {
+        self.properties
+    }
+}
+
+impl From<api::AggregationResultBatch> for AggregationResults {
+    fn from(batch: api::AggregationResultBatch) -> AggregationResults {
+        //? An aggregation query yields a single result, grouping is not supported.
+        let properties = batch
+            .aggregation_results
+            .into_iter()
+            .flat_map(|result| result.aggregate_properties)
+            .filter_map(|(alias, value)| Some((alias, Value::from(value.value_type?))))
+            .collect();
+        AggregationResults { properties }
+    }
+}
+
+pub(crate) fn convert_aggregation(aggregation: Aggregation) -> api::aggregation_query::Aggregation {
+    let (alias, operator) = match aggregation {
+        Aggregation::Count(alias) => {
+            (alias, Operator::Count(Count { up_to: Some(Aggregation::DEFAULT_COUNT_UP_TO) }))
+        }
+        Aggregation::CountUpTo(alias, up_to) => {
+            (alias, Operator::Count(Count { up_to: Some(up_to) }))
+        }
+        Aggregation::Sum(alias, property) => (
+            alias,
+            Operator::Sum(Sum { property: Some(api::PropertyReference { name: property }) }),
+        ),
+        Aggregation::Avg(alias, property) => (
+            alias,
+            Operator::Avg(Avg { property: Some(api::PropertyReference { name: property }) }),
+        ),
+    };
+    api::aggregation_query::Aggregation { operator: Some(operator), alias }
+}
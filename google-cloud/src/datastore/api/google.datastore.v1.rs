@@ -379,41 +379,6 @@ pub mod aggregation_query {
     #[allow(clippy::derive_partial_eq_without_eq)]
     #[derive(Clone, PartialEq, ::prost::Message)]
     pub struct Aggregation {
-        /// Optional. Optional name of the property to store the result of the
-        /// aggregation.
-        ///
-        /// If not provided, Datastore will pick a default name following the format
-        /// `property_<incremental_id++>`. For example:
-        ///
-        /// ```
-        /// AGGREGATE
-        ///    COUNT_UP_TO(1) AS count_up_to_1,
-        ///    COUNT_UP_TO(2),
-        ///    COUNT_UP_TO(3) AS count_up_to_3,
-        ///    COUNT(*)
-        /// OVER (
-        ///    ...
-        /// );
-        /// ```
-        ///
-        /// becomes:
-        ///
-        /// ```
-        /// AGGREGATE
-        ///    COUNT_UP_TO(1) AS count_up_to_1,
-        ///    COUNT_UP_TO(2) AS property_1,
-        ///    COUNT_UP_TO(3) AS count_up_to_3,
-        ///    COUNT(*) AS property_2
-        /// OVER (
-        ///    ...
-        /// );
-        /// ```
-        ///
-        /// Requires:
-        ///
-        /// * Must be unique across all aggregation aliases.
-        /// * Conform to [entity property
-        /// name]\[google.datastore.v1.Entity.properties\] limitations.
         #[prost(string, tag = "7")]
         pub alias: ::prost::alloc::string::String,
         /// The type of aggregation to perform, required.
@@ -429,26 +394,6 @@ pub mod aggregation_query {
         #[allow(clippy::derive_partial_eq_without_eq)]
         #[derive(Clone, PartialEq, ::prost::Message)]
         pub struct Count {
-            /// Optional. Optional constraint on the maximum number of entities to
-            /// count.
-            ///
-            /// This provides a way to set an upper bound on the number of entities
-            /// to scan, limiting latency, and cost.
-            ///
-            /// Unspecified is interpreted as no bound.
-            ///
-            /// If a zero value is provided, a count result of zero should always be
-            /// expected.
-            ///
-            /// High-Level Example:
-            ///
-            /// ```
-            /// AGGREGATE COUNT_UP_TO(1000) OVER ( SELECT * FROM k );
-            /// ```
-            ///
-            /// Requires:
-            ///
-            /// * Must be non-negative when present.
             #[prost(message, optional, tag = "1")]
             pub up_to: ::core::option::Option<i64>,
         }
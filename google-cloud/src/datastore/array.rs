@@ -0,0 +1,90 @@
+use crate::datastore::{FromValue, IntoValue, Value};
+use crate::error::ConvertError;
+
+/// Array decoding adapter which leaves out the elements that fail to convert.
+///
+/// `Vec<T>` fails on the first invalid element, which makes arrays with mixed types
+/// (common in legacy data) unreadable:
+///
+/// ```
+/// # use google_cloud::datastore::{FromValue, SkipInvalid, Value};
+/// let value = Value::ArrayValue(vec![Value::IntegerValue(1), Value::StringValue("2".into())]);
+/// let SkipInvalid(values) = SkipInvalid::<i64>::from_value(value).unwrap();
+/// assert_eq!(values, vec![1]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkipInvalid<T>(pub Vec<T>);
+
+/// Array decoding adapter which keeps the conversion result of each element.
+///
+/// ```
+/// # use google_cloud::datastore::{Collect, FromValue, Value};
+/// let value = Value::ArrayValue(vec![Value::IntegerValue(1), Value::StringValue("2".into())]);
+/// let Collect(values) = Collect::<i64>::from_value(value).unwrap();
+/// assert!(values[0].is_ok() && values[1].is_err());
+/// ```
+#[derive(Debug)]
+pub struct Collect<T>(pub Vec<Result<T, ConvertError>>);
+
+impl<T> SkipInvalid<T> {
+    /// Unwraps the decoded elements.
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T> Collect<T> {
+    /// Unwraps the conversion results.
+    pub fn into_inner(self) -> Vec<Result<T, ConvertError>> {
+        self.0
+    }
+}
+
+fn array_values(value: Value) -> Result<Vec<Value>, ConvertError> {
+    match value {
+        Value::ArrayValue(values) => Ok(values),
+        _ => Err(ConvertError::UnexpectedPropertyType {
+            expected: String::from("array"),
+            got: String::from(value.type_name()),
+        }),
+    }
+}
+
+impl<T> FromValue for SkipInvalid<T>
+where
+    T: FromValue,
+{
+    fn from_value(value: Value) -> Result<SkipInvalid<T>, ConvertError> {
+        let values = array_values(value)?;
+        Ok(SkipInvalid(values.into_iter().filter_map(|value| T::from_value(value).ok()).collect()))
+    }
+}
+
+impl<T> FromValue for Collect<T>
+where
+    T: FromValue,
+{
+    fn from_value(value: Value) -> Result<Collect<T>, ConvertError> {
+        let values = array_values(value)?;
+        Ok(Collect(values.into_iter().map(T::from_value).collect()))
+    }
+}
+
+impl<T> IntoValue for SkipInvalid<T>
+where
+    T: IntoValue,
+{
+    fn into_value(self) -> Value {
+        self.0.into_value()
+    }
+}
+
+impl<T> IntoValue for Collect<T>
+where
+    T: IntoValue,
+{
+    /// Only the elements that were successfully decoded are written back.
+    fn into_value(self) -> Value {
+        self.0.into_iter().filter_map(Result::ok).collect::<Vec<T>>().into_value()
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::datastore::{api, Client, Key};
+
+/// Receives an event for every entity change a client commits (see `ClientBuilder::audit_sink`).
+///
+/// It is called inline once each commit succeeded, with the changes it applied:
+/// it should hand them off (to a log, a channel...) and return quickly.
+pub trait AuditSink: fmt::Debug + Send + Sync {
+    /// Records a change applied to an entity.
+    fn record(&self, event: &AuditEvent<'_>);
+}
+
+impl<T: AuditSink + ?Sized> AuditSink for Arc<T> {
+    fn record(&self, event: &AuditEvent<'_>) {
+        T::record(self, event)
+    }
+}
+
+/// The kind of change applied to an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditOperation {
+    /// The entity was inserted.
+    Insert,
+    /// The entity was updated.
+    Update,
+    /// The entity was inserted or replaced.
+    Upsert,
+    /// The entity was deleted (if it existed).
+    Delete,
+}
+
+/// A change applied to an entity, as reported to an `AuditSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent<'a> {
+    /// The kind of change.
+    pub operation: AuditOperation,
+    /// The project of the entity.
+    pub project: &'a str,
+    /// The key of the entity, completed when the store allocated its ID.
+    pub key: &'a Key,
+    /// The names of the properties written, sorted (empty for deletions).
+    pub properties: &'a [String],
+    /// The actor the client acts on behalf of, if any (see `Client::with_actor`).
+    pub actor: Option<&'a str>,
+    /// Whether the change was committed by a transaction.
+    pub transactional: bool,
+}
+
+/// An `AuditSink` emitting every change as a `tracing` event, at the `INFO` level
+/// and with the `google_cloud::datastore::audit` target.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingAuditSink;
+
+#[cfg(feature = "tracing")]
+impl AuditSink for TracingAuditSink {
+    fn record(&self, event: &AuditEvent<'_>) {
+        tracing::info!(
+            target: "google_cloud::datastore::audit",
+            operation = ?event.operation,
+            project = event.project,
+            namespace = event.key.get_namespace().unwrap_or_default(),
+            kind = event.key.get_kind(),
+            key = ?event.key,
+            properties = ?event.properties,
+            actor = event.actor,
+            transactional = event.transactional,
+            "entity changed",
+        );
+    }
+}
+
+/// The changes of a commit, noted before sending it so as to report them once it succeeded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuditLog {
+    /// One change per mutation, so that they line up with the results of the commit
+    /// (`None` for a mutation without an operation or a key, which cannot be reported).
+    pub(crate) changes: Vec<Option<AuditChange>>,
+}
+
+/// The change of a single mutation.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditChange {
+    pub(crate) operation: AuditOperation,
+    pub(crate) key: Key,
+    pub(crate) properties: Vec<String>,
+}
+
+impl AuditLog {
+    pub(crate) fn of(mutations: &[api::Mutation]) -> AuditLog {
+        use api::mutation::Operation;
+
+        let changes = mutations
+            .iter()
+            .map(|mutation| {
+                let (operation, entity) = match mutation.operation.as_ref()? {
+                    Operation::Insert(entity) => (AuditOperation::Insert, entity),
+                    Operation::Update(entity) => (AuditOperation::Update, entity),
+                    Operation::Upsert(entity) => (AuditOperation::Upsert, entity),
+                    Operation::Delete(key) => {
+                        let key = Key::from(key.clone());
+                        return Some(AuditChange {
+                            operation: AuditOperation::Delete,
+                            key,
+                            properties: Vec::new(),
+                        });
+                    }
+                };
+                let mut properties: Vec<String> = entity.properties.keys().cloned().collect();
+                properties.sort_unstable();
+                let key = Key::from(entity.key.clone()?);
+                Some(AuditChange { operation, key, properties })
+            })
+            .collect();
+
+        AuditLog { changes }
+    }
+
+    /// Reports the changes which were applied, given the results of the commit in order.
+    pub(crate) fn report(
+        self,
+        sink: &dyn AuditSink,
+        project: &str,
+        actor: Option<&str>,
+        transactional: bool,
+        results: &[api::MutationResult],
+    ) {
+        for (change, result) in self.changes.into_iter().zip(results) {
+            //? A conditional write which found a modified entity changed nothing.
+            let change = match change {
+                Some(change) if !result.conflict_detected => change,
+                _ => continue,
+            };
+            //? The store only sends back the keys it allocated.
+            let key = result.key.clone().map_or(change.key, Key::from);
+            sink.record(&AuditEvent {
+                operation: change.operation,
+                project,
+                key: &key,
+                properties: &change.properties,
+                actor,
+                transactional,
+            });
+        }
+    }
+}
+
+impl Client {
+    /// Notes the changes of a commit, if the client has an audit sink.
+    pub(crate) fn audit_log(&self, mutations: &[api::Mutation]) -> Option<AuditLog> {
+        self.audit.as_ref().map(|_| AuditLog::of(mutations))
+    }
+
+    /// Reports the changes of a successful commit to the audit sink of the client.
+    pub(crate) fn report_audit(
+        &self,
+        log: Option<AuditLog>,
+        transactional: bool,
+        results: &[api::MutationResult],
+    ) {
+        if let (Some(log), Some(sink)) = (log, self.audit.as_deref()) {
+            log.report(sink, &self.project_name, self.actor.as_deref(), transactional, results);
+        }
+    }
+}
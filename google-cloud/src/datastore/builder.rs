@@ -0,0 +1,565 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::Duration;
+
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tower::discover::Change;
+
+use crate::authorize::{
+    ApplicationCredentials, ImpersonatedCredentials, TokenCache, TokenManager, TokenProvider,
+    TokenSource, TLS_CERTS,
+};
+use crate::datastore::api::datastore_client::DatastoreClient;
+use crate::datastore::api::longrunning::operations_client::OperationsClient;
+use crate::datastore::{
+    AuditSink, Client, Error, IdAllocator, IndexExcluded, LookupProgress, LookupProgressCallback,
+    MetricsSink, NonFinitePolicy, SlowQuery, SlowQueryCallback, SlowQueryLog,
+};
+
+/// Builder for a Datastore client with a custom configuration.
+///
+/// ```no_run
+/// # use google_cloud::datastore::Client;
+/// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+/// let client = Client::builder("my-project")
+///     .max_decoding_message_size(16 * 1024 * 1024)
+///     .initial_stream_window_size(Some(4 * 1024 * 1024))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    pub(crate) project_name: String,
+    pub(crate) endpoint: String,
+    pub(crate) domain_name: Option<String>,
+    pub(crate) ca_certificate: Option<Vec<u8>>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) tcp_keepalive: Option<Duration>,
+    pub(crate) keep_alive_interval: Option<Duration>,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) keep_alive_while_idle: bool,
+    pub(crate) max_decoding_message_size: usize,
+    pub(crate) max_encoding_message_size: usize,
+    pub(crate) initial_stream_window_size: Option<u32>,
+    pub(crate) initial_connection_window_size: Option<u32>,
+    pub(crate) http2_adaptive_window: bool,
+    pub(crate) queue_expired: bool,
+    pub(crate) commit_concurrency: usize,
+    pub(crate) transaction_attempts: usize,
+    pub(crate) pool_size: usize,
+    pub(crate) channel: Option<Channel>,
+    pub(crate) slow_query_log: Option<SlowQueryLog>,
+    pub(crate) yield_interval: usize,
+    pub(crate) id_allocator: Option<Arc<dyn IdAllocator>>,
+    pub(crate) lookup_progress: Option<LookupProgressCallback>,
+    pub(crate) metrics: Option<Arc<dyn MetricsSink>>,
+    pub(crate) audit: Option<Arc<dyn AuditSink>>,
+    pub(crate) non_finite_policy: NonFinitePolicy,
+}
+
+impl ClientBuilder {
+    /// Default budget for a single response (32 MiB), enough for a full lookup of 32 KiB entities.
+    pub const DEFAULT_MAX_DECODING_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+    /// Default limit for a single request (10 MiB), the maximum size of a Datastore commit.
+    pub const DEFAULT_MAX_ENCODING_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+    /// Default HTTP/2 stream window (2 MiB).
+    pub const DEFAULT_INITIAL_STREAM_WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+    /// Default HTTP/2 connection window (8 MiB).
+    pub const DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE: u32 = 8 * 1024 * 1024;
+    /// Default number of attempts of `Client::run_in_transaction`.
+    pub const DEFAULT_TRANSACTION_ATTEMPTS: usize = 5;
+    /// Default number of entities converted between two yields to the runtime.
+    pub const DEFAULT_YIELD_INTERVAL: usize = 512;
+
+    /// Creates a new builder for the specified project, with the default configuration.
+    pub fn new(project_name: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            project_name: project_name.into(),
+            endpoint: String::from(Client::ENDPOINT),
+            domain_name: None,
+            ca_certificate: None,
+            connect_timeout: None,
+            request_timeout: None,
+            user_agent: None,
+            tcp_keepalive: None,
+            keep_alive_interval: None,
+            keep_alive_timeout: None,
+            keep_alive_while_idle: false,
+            max_decoding_message_size: ClientBuilder::DEFAULT_MAX_DECODING_MESSAGE_SIZE,
+            max_encoding_message_size: ClientBuilder::DEFAULT_MAX_ENCODING_MESSAGE_SIZE,
+            initial_stream_window_size: Some(ClientBuilder::DEFAULT_INITIAL_STREAM_WINDOW_SIZE),
+            initial_connection_window_size: Some(
+                ClientBuilder::DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE,
+            ),
+            http2_adaptive_window: false,
+            queue_expired: false,
+            commit_concurrency: 1,
+            transaction_attempts: ClientBuilder::DEFAULT_TRANSACTION_ATTEMPTS,
+            pool_size: 1,
+            channel: None,
+            slow_query_log: None,
+            yield_interval: ClientBuilder::DEFAULT_YIELD_INTERVAL,
+            id_allocator: None,
+            lookup_progress: None,
+            metrics: None,
+            audit: None,
+            non_finite_policy: NonFinitePolicy::default(),
+        }
+    }
+
+    /// Sets the URL of the Datastore endpoint (`https://datastore.googleapis.com` by default).
+    ///
+    /// Plain `http://` endpoints, such as the Datastore emulator, are reached without TLS.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use google_cloud::datastore::Client;
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let client = Client::builder("my-project")
+    ///     .endpoint("https://datastore.europe-west1.rep.googleapis.com")
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .request_timeout(Duration::from_secs(30))
+    ///     .user_agent("billing-service/1.4")
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn endpoint(mut self, url: impl Into<String>) -> ClientBuilder {
+        self.endpoint = url.into();
+        self
+    }
+
+    /// Sets the domain name the TLS certificate of the endpoint is checked against,
+    /// instead of the host of the endpoint URL.
+    pub fn domain_name(mut self, domain_name: impl Into<String>) -> ClientBuilder {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Trusts the certificate authorities of a PEM bundle instead of the bundled roots,
+    /// to go through a TLS-intercepting proxy for instance.
+    pub fn ca_certificate(mut self, pem: impl Into<Vec<u8>>) -> ClientBuilder {
+        self.ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// Sets how long establishing a connection may take (no limit by default).
+    pub fn connect_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the deadline of each request (no deadline by default).
+    ///
+    /// The deadline is sent to Datastore, which gives up on the request past it, and enforced
+    /// by the client: late requests fail with a `DEADLINE_EXCEEDED` or `CANCELLED` status.
+    /// Requests sent through `ClientBuilder::channel` are only bounded by the server.
+    pub fn request_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the user agent sent with the requests, in front of the one of the gRPC transport.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> ClientBuilder {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Enables TCP keep-alive probes on the connections, sent after `idle` without traffic.
+    pub fn tcp_keepalive(mut self, idle: Option<Duration>) -> ClientBuilder {
+        self.tcp_keepalive = idle;
+        self
+    }
+
+    /// Sends HTTP/2 pings every `interval`, to detect broken connections
+    /// (and keep them open through proxies dropping idle ones).
+    pub fn keep_alive_interval(mut self, interval: Duration) -> ClientBuilder {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long to wait for the acknowledgement of a ping before dropping the connection.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps pinging idle connections, which have no request in flight
+    /// (only busy connections are pinged by default).
+    pub fn keep_alive_while_idle(mut self, enabled: bool) -> ClientBuilder {
+        self.keep_alive_while_idle = enabled;
+        self
+    }
+
+    /// Sets the largest response size (in bytes) the client expects to receive.
+    ///
+    /// Lookups are split in batches so that each response stays below this size: the batch
+    /// size is derived from the size of previous responses, shrinks when the server reports
+    /// that a response is too large and grows back once responses get smaller.
+    /// The transport itself (tonic 0.8) does not cap decoded messages.
+    pub fn max_decoding_message_size(mut self, limit: usize) -> ClientBuilder {
+        self.max_decoding_message_size = limit;
+        self
+    }
+
+    /// Sets the largest request size (in bytes) the client accepts to send.
+    ///
+    /// Larger requests fail with an `OUT_OF_RANGE` status before reaching the network.
+    pub fn max_encoding_message_size(mut self, limit: usize) -> ClientBuilder {
+        self.max_encoding_message_size = limit;
+        self
+    }
+
+    /// Sets the HTTP/2 initial window size of each stream (`None` for the HTTP/2 default).
+    pub fn initial_stream_window_size(mut self, size: Option<u32>) -> ClientBuilder {
+        self.initial_stream_window_size = size;
+        self
+    }
+
+    /// Sets the HTTP/2 initial window size of the connection (`None` for the HTTP/2 default).
+    pub fn initial_connection_window_size(mut self, size: Option<u32>) -> ClientBuilder {
+        self.initial_connection_window_size = size;
+        self
+    }
+
+    /// Enables HTTP/2 adaptive flow control, which overrides the initial window sizes.
+    pub fn http2_adaptive_window(mut self, enabled: bool) -> ClientBuilder {
+        self.http2_adaptive_window = enabled;
+        self
+    }
+
+    /// Queues the keys of the expired entities skipped by the `*_unexpired` reads,
+    /// to delete them later with `Client::purge_expired`.
+    pub fn queue_expired(mut self, enabled: bool) -> ClientBuilder {
+        self.queue_expired = enabled;
+        self
+    }
+
+    /// Sets how many commits large writes keep in flight at once (one by default).
+    ///
+    /// Writes too large for a single commit are split in several ones: sending them
+    /// concurrently hides the commit latency, while the results are still reported in order.
+    pub fn commit_concurrency(mut self, window: usize) -> ClientBuilder {
+        self.commit_concurrency = window.max(1);
+        self
+    }
+
+    /// Sets how many times `Client::run_in_transaction` runs an operation whose transaction
+    /// keeps being aborted, the first attempt included.
+    pub fn transaction_attempts(mut self, attempts: usize) -> ClientBuilder {
+        self.transaction_attempts = attempts.max(1);
+        self
+    }
+
+    /// Reports the queries taking longer than `threshold`, all their batches included.
+    ///
+    /// The reports are logged as warnings (with the `log` crate), unless a callback is set
+    /// with `ClientBuilder::on_slow_query`.
+    pub fn slow_query_threshold(mut self, threshold: Duration) -> ClientBuilder {
+        let callback = self.slow_query_log.and_then(|log| log.callback);
+        self.slow_query_log = Some(SlowQueryLog { threshold, callback });
+        self
+    }
+
+    /// Sends the slow query reports to a callback instead of the log.
+    ///
+    /// It has no effect without a `ClientBuilder::slow_query_threshold`.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use google_cloud::datastore::Client;
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let client = Client::builder("my-project")
+    ///     .slow_query_threshold(Duration::from_millis(500))
+    ///     .on_slow_query(|report| eprintln!("slow query {:016x}: {:?}", report.fingerprint, report.duration))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_slow_query(
+        mut self,
+        callback: impl Fn(&SlowQuery) + Send + Sync + 'static,
+    ) -> ClientBuilder {
+        let callback: SlowQueryCallback = Arc::new(callback);
+        if let Some(log) = self.slow_query_log.as_mut() {
+            log.callback = Some(callback);
+        } else {
+            self.slow_query_log =
+                Some(SlowQueryLog { threshold: Duration::MAX, callback: Some(callback) });
+        }
+        self
+    }
+
+    /// Reports the progress of every lookup to a callback, after each of its rounds.
+    ///
+    /// Lookups of a single call can be tracked with `Client::get_all_with_progress` instead.
+    pub fn on_lookup_progress(
+        mut self,
+        callback: impl Fn(&LookupProgress) + Send + Sync + 'static,
+    ) -> ClientBuilder {
+        self.lookup_progress = Some(LookupProgressCallback(Arc::new(callback)));
+        self
+    }
+
+    /// Sets how many entities the reads convert before yielding to the runtime
+    /// (`DEFAULT_YIELD_INTERVAL` by default, zero to never yield).
+    ///
+    /// Converting tens of thousands of entities in a single poll would otherwise keep
+    /// the other tasks of the thread waiting.
+    pub fn yield_interval(mut self, interval: usize) -> ClientBuilder {
+        self.yield_interval = interval;
+        self
+    }
+
+    /// Allocates the IDs of incomplete keys with `allocator` before inserting them,
+    /// instead of letting the store pick them.
+    ///
+    /// Meant for tests and emulator runs:
+    /// with a `SequentialIdAllocator`, the same inserts always get the same keys.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Client, SequentialIdAllocator};
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let client = Client::builder("my-project")
+    ///     .endpoint("http://localhost:8081")
+    ///     .id_allocator(SequentialIdAllocator::new())
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn id_allocator(mut self, allocator: impl IdAllocator + 'static) -> ClientBuilder {
+        self.id_allocator = Some(Arc::new(allocator));
+        self
+    }
+
+    /// Reports the metrics of every RPC the client sends (operation, status code, duration,
+    /// attempt number and payload sizes) to `sink`, for example an `RpcStats`.
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use google_cloud::datastore::{Client, RpcStats};
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let stats = Arc::new(RpcStats::new());
+    /// let client = Client::builder("my-project").metrics_sink(Arc::clone(&stats)).build().await?;
+    /// // ...
+    /// for (operation, stats) in stats.take() {
+    ///     println!("{}: {} requests, {} errors", operation, stats.requests, stats.errors);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn metrics_sink(mut self, sink: impl MetricsSink + 'static) -> ClientBuilder {
+        self.metrics = Some(Arc::new(sink));
+        self
+    }
+
+    /// Reports every entity change the client commits (kind of change, key, names of the
+    /// properties written and actor, see `Client::with_actor`) to `sink`, for compliance logging.
+    ///
+    /// Changes are reported once their commit succeeded, conditional writes which were not
+    /// applied are left out. With the `tracing` feature, `TracingAuditSink` emits them
+    /// as `tracing` events.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{AuditEvent, AuditSink, Client};
+    /// #[derive(Debug)]
+    /// struct AuditLog;
+    ///
+    /// impl AuditSink for AuditLog {
+    ///     fn record(&self, event: &AuditEvent<'_>) {
+    ///         println!("{:?} {:?} by {:?}", event.operation, event.key, event.actor);
+    ///     }
+    /// }
+    ///
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let client = Client::builder("my-project").audit_sink(AuditLog).build().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn audit_sink(mut self, sink: impl AuditSink + 'static) -> ClientBuilder {
+        self.audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets what the client does with the NaN and infinite doubles of the entities it writes
+    /// (`NonFinitePolicy::AllowNonFinite` by default, writing them as they are).
+    pub fn non_finite_policy(mut self, policy: NonFinitePolicy) -> ClientBuilder {
+        self.non_finite_policy = policy;
+        self
+    }
+
+    /// Sets how many connections the client opens to Datastore (one by default).
+    ///
+    /// A single HTTP/2 connection caps the throughput of heavily concurrent workloads:
+    /// with several connections, each request goes to the least loaded one.
+    /// Pooled connections are established lazily, connection errors then surface on the first requests.
+    pub fn pool_size(mut self, size: usize) -> ClientBuilder {
+        self.pool_size = size.max(1);
+        self
+    }
+
+    /// Uses an already established channel instead of dialing the Datastore endpoint.
+    ///
+    /// This allows connecting through a sidecar, a Unix domain socket or any custom connector
+    /// (see `Endpoint::connect_with_connector`). The endpoint, TLS, timeout, keep-alive
+    /// and HTTP/2 window settings of this builder and its pool size are then ignored:
+    /// they belong to the channel, only the request deadline still applies.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::Client;
+    /// # use tonic::transport::Endpoint;
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let channel = Endpoint::from_static("http://localhost:8081").connect().await?;
+    /// let client = Client::builder("my-project").channel(channel).build().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn channel(mut self, channel: Channel) -> ClientBuilder {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub(crate) fn endpoint_config(&self) -> Result<Endpoint, Error> {
+        let mut endpoint = Endpoint::from_shared(self.endpoint.clone())?
+            .tcp_keepalive(self.tcp_keepalive)
+            .keep_alive_while_idle(self.keep_alive_while_idle)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .http2_adaptive_window(self.http2_adaptive_window);
+
+        if !self.endpoint.starts_with("http://") {
+            let ca_certificate = self.ca_certificate.as_deref().unwrap_or(TLS_CERTS);
+            let mut tls_config =
+                ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_certificate));
+            if let Some(domain_name) = &self.domain_name {
+                tls_config = tls_config.domain_name(domain_name);
+            }
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+        if let Some(user_agent) = &self.user_agent {
+            endpoint = endpoint.user_agent(user_agent.clone())?;
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = self.keep_alive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval);
+        }
+        if let Some(timeout) = self.keep_alive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Creates the client.
+    ///
+    /// Credentials are looked up in the environment, as described in the
+    /// [`authorize`](crate::authorize) module.
+    pub async fn build(self) -> Result<Client, Error> {
+        let token_manager = TokenManager::from_env(Client::SCOPES.as_ref())?;
+
+        self.build_with_token_provider(TokenCache::new(token_manager)).await
+    }
+
+    /// Creates the client with custom credentials.
+    pub async fn build_with_credentials(
+        self,
+        creds: ApplicationCredentials,
+    ) -> Result<Client, Error> {
+        let token_manager = TokenManager::new(creds, Client::SCOPES.as_ref());
+
+        self.build_with_token_provider(TokenCache::new(token_manager)).await
+    }
+
+    /// Creates the client with credentials impersonating a service account.
+    pub async fn build_with_impersonated_credentials(
+        self,
+        creds: ImpersonatedCredentials,
+    ) -> Result<Client, Error> {
+        let source = TokenSource::Impersonated(creds);
+        let token_manager = TokenManager::from_source(source, Client::SCOPES.as_ref());
+
+        self.build_with_token_provider(TokenCache::new(token_manager)).await
+    }
+
+    /// Creates the client, authenticated by a custom token provider.
+    ///
+    /// ```no_run
+    /// # use futures::future::BoxFuture;
+    /// # use google_cloud::authorize::TokenProvider;
+    /// # use google_cloud::datastore::Client;
+    /// # use google_cloud::error::AuthError;
+    /// struct VaultToken;
+    ///
+    /// impl TokenProvider for VaultToken {
+    ///     fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+    ///         Box::pin(async move { Ok(std::env::var("DATASTORE_TOKEN").unwrap_or_default()) })
+    ///     }
+    /// }
+    ///
+    /// # async fn run() -> Result<(), google_cloud::datastore::Error> {
+    /// let client = Client::builder("my-project").build_with_token_provider(VaultToken).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn build_with_token_provider(
+        self,
+        token_provider: impl TokenProvider + 'static,
+    ) -> Result<Client, Error> {
+        let channel = match self.channel {
+            Some(channel) => channel,
+            None => {
+                let endpoint = self.endpoint_config()?;
+
+                if self.pool_size > 1 {
+                    //? The endpoints are keyed by index: `Channel::balance_list` would merge
+                    //? them into a single connection, since they share the same URI.
+                    let (channel, sender) = Channel::balance_channel(self.pool_size);
+                    for index in 0..self.pool_size {
+                        let change = Change::Insert(index, endpoint.clone());
+                        sender.send(change).await.expect("the balanced channel is alive");
+                    }
+                    channel
+                } else {
+                    endpoint.connect().await?
+                }
+            }
+        };
+
+        Ok(Client {
+            project_name: self.project_name,
+            request_timeout: self.request_timeout,
+            namespace: None,
+            service: DatastoreClient::new(channel.clone()),
+            operations: OperationsClient::new(channel),
+            token_provider: Arc::new(token_provider),
+            index_excluded: IndexExcluded::new()?,
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+            lookup_batch_size: Arc::new(AtomicUsize::new(Client::MAX_LOOKUP_KEYS)),
+            queue_expired: self.queue_expired,
+            commit_concurrency: self.commit_concurrency,
+            transaction_attempts: self.transaction_attempts,
+            slow_query_log: self.slow_query_log,
+            yield_interval: self.yield_interval,
+            id_allocator: self.id_allocator,
+            lookup_progress: self.lookup_progress,
+            metrics: self.metrics,
+            audit: self.audit,
+            non_finite_policy: self.non_finite_policy,
+            actor: None,
+            expired_keys: Arc::new(SyncMutex::new(Vec::new())),
+        })
+    }
+}
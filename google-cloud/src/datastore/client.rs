@@ -1,32 +1,67 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::env;
-use std::fs::File;
-use std::path::Path;
-use std::sync::Arc;
-
-use tokio::sync::Mutex;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig};
-use tonic::{IntoRequest, Request};
-
-use crate::authorize::{ApplicationCredentials, TokenManager, TLS_CERTS};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use prost::Message;
+use tonic::transport::Channel;
+use tonic::{Code, IntoRequest, Request, Status};
+
+use crate::authorize::{
+    authorize_request, ApplicationCredentials, ImpersonatedCredentials, TokenProvider,
+};
 use crate::datastore::api;
 use crate::datastore::api::datastore_client::DatastoreClient;
+use crate::datastore::api::longrunning::operations_client::OperationsClient;
 use crate::datastore::{
-    Entity, Error, Filter, FromValue, IntoEntity, Key, KeyID, Order, Query, Value,
+    convert_aggregation, convert_gql_query, entity_properties, fnv1a, keys_kind, mutation_outcomes,
+    mutations_kind, traced_transaction, Aggregation, AggregationResults, AuditSink, ClientBuilder,
+    Entity, Error, Expiring, Filter, FromValue, GqlQuery, IdAllocator, IntoEntity, Key, KeyID,
+    LookupProgress, LookupProgressCallback, LookupProgressFn, LookupTracker, MetricsSink,
+    MutationOutcome, MutationTarget, NonFinitePolicy, Order, Query, Rpc, Value,
 };
 
-use super::api::aggregation_query::aggregation::{Count, Sum};
+use super::api::mutation::ConflictDetectionStrategy;
 use super::api::transaction_options::{ReadOnly, ReadWrite};
-use super::{CompositeFilter, IndexExcluded, Transaction};
+use super::{CompositeFilter, IndexExcluded, SlowQueryLog, Transaction};
 
 /// The Datastore client, tied to a specific project.
+///
+/// Its methods take `&self`: a client can be shared (with an `Arc` or cheap clones)
+/// between tasks sending concurrent requests.
+///
+/// With the `tracing` feature, each RPC runs in a span named after it (`datastore.lookup`,
+/// `datastore.commit`...) recording the project, the kind, the attempt number, the latency
+/// and the status code, and each attempt of `run_in_transaction` in a `datastore.transaction` span.
 #[derive(Clone)]
 pub struct Client {
     pub(crate) project_name: String,
+    pub(crate) namespace: Option<String>,
+    pub(crate) request_timeout: Option<Duration>,
     pub(crate) service: DatastoreClient<Channel>,
-    pub(crate) token_manager: Arc<Mutex<TokenManager>>,
+    pub(crate) operations: OperationsClient<Channel>,
+    pub(crate) token_provider: Arc<dyn TokenProvider>,
     pub(crate) index_excluded: IndexExcluded,
+    pub(crate) max_decoding_message_size: usize,
+    pub(crate) max_encoding_message_size: usize,
+    pub(crate) lookup_batch_size: Arc<AtomicUsize>,
+    pub(crate) queue_expired: bool,
+    pub(crate) commit_concurrency: usize,
+    pub(crate) transaction_attempts: usize,
+    pub(crate) slow_query_log: Option<SlowQueryLog>,
+    pub(crate) yield_interval: usize,
+    pub(crate) id_allocator: Option<Arc<dyn IdAllocator>>,
+    pub(crate) lookup_progress: Option<LookupProgressCallback>,
+    pub(crate) metrics: Option<Arc<dyn MetricsSink>>,
+    pub(crate) audit: Option<Arc<dyn AuditSink>>,
+    pub(crate) non_finite_policy: NonFinitePolicy,
+    pub(crate) actor: Option<String>,
+    pub(crate) expired_keys: Arc<SyncMutex<Vec<Key>>>,
 }
 
 /// Opciones para el modo de crear la trx
@@ -34,52 +69,126 @@ pub struct Client {
 pub enum TrxOption {
     /// modo solo lectura
     ReadOnly,
+    /// modo solo lectura, leyendo las entidades tal y como estaban en el momento indicado
+    ReadOnlyAt(DateTime<Utc>),
     /// modo de escritura y lectura
     ReadWrite,
     /// modo por defecto
     Default,
 }
 
-/// Optiones para el tipo se Agregación
-#[derive(Debug, Clone, PartialEq)]
-pub enum Aggregation {
-    ///
-    Count(String),
-    ///
-    Sum(String, String),
-    ///
-    Avg(String, String),
+/// How the entities of a write are turned into mutations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WriteMode {
+    /// Insert entities with an incomplete or new key, upsert the others.
+    Auto,
+    /// The entity must not exist yet.
+    Insert,
+    /// The entity must already exist.
+    Update,
+    /// The entity is created or replaced.
+    Upsert,
 }
 
 impl Client {
-    pub(crate) const DOMAIN_NAME: &'static str = "datastore.googleapis.com";
     pub(crate) const ENDPOINT: &'static str = "https://datastore.googleapis.com";
     pub(crate) const SCOPES: [&'static str; 2] = [
         "https://www.googleapis.com/auth/cloud-platform",
         "https://www.googleapis.com/auth/datastore",
     ];
-
-    pub(crate) async fn construct_request<T: IntoRequest<T>>(
-        &mut self,
+    /// Maximum number of keys accepted by a single lookup.
+    pub(crate) const MAX_LOOKUP_KEYS: usize = 1000;
+    /// Maximum number of mutations accepted by a single commit.
+    pub(crate) const MAX_COMMIT_MUTATIONS: usize = 500;
+    /// Bytes reserved in a commit for everything but the mutations.
+    const COMMIT_OVERHEAD: usize = 1024;
+    /// Bytes taken by the tag and length prefix of each mutation.
+    const COMMIT_OVERHEAD_PER_MUTATION: usize = 8;
+
+    pub(crate) async fn construct_request<T: IntoRequest<T> + Message>(
+        &self,
         request: T,
     ) -> Result<Request<T>, Error> {
+        let size = request.encoded_len();
+        if size > self.max_encoding_message_size {
+            return Err(Status::out_of_range(format!(
+                "message length too large: found {} bytes, the limit is: {} bytes",
+                size, self.max_encoding_message_size
+            ))
+            .into());
+        }
         let mut request = request.into_request();
-        let token = self.token_manager.lock().await.token().await?;
-        let metadata = request.metadata_mut();
-        metadata.insert("authorization", token.parse().unwrap());
+        if let Some(timeout) = self.request_timeout {
+            request.set_timeout(timeout);
+        }
+        authorize_request(self.token_provider.as_ref(), &mut request).await?;
         Ok(request)
     }
 
+    /// Returns a client scoped to a namespace: the keys, queries and GQL queries without
+    /// an explicit namespace are sent in this one instead of the default namespace.
+    ///
+    /// An explicit namespace always wins, use `Key::namespace("")` to target the default
+    /// namespace from a scoped client.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Client, Key};
+    /// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+    /// let tenant = client.with_namespace("tenant-a");
+    /// //? Looks `users/1` up in the `tenant-a` namespace.
+    /// let user: Option<String> = tenant.get(Key::new("users").id(1)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_namespace(&self, namespace: impl Into<String>) -> Client {
+        Client { namespace: Some(namespace.into()), ..self.clone() }
+    }
+
+    /// Returns a client acting on behalf of `actor` (a user, a service...), who is named
+    /// in the audit events of the changes it commits (see `ClientBuilder::audit_sink`).
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::Client;
+    /// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+    /// let client = client.with_actor("alice@example.com");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_actor(&self, actor: impl Into<String>) -> Client {
+        Client { actor: Some(actor.into()), ..self.clone() }
+    }
+
+    /// Gets the actor the client acts on behalf of, if any.
+    pub fn get_actor(&self) -> Option<&str> {
+        self.actor.as_deref()
+    }
+
+    /// Gets the default namespace of the client, if it is scoped to one.
+    pub fn get_namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    pub(crate) fn partition(&self) -> Partition<'_> {
+        Partition { project_name: &self.project_name, namespace: self.namespace.as_deref() }
+    }
+
+    /// Resolves the namespace of a request, falling back to the namespace of the client.
+    pub(crate) fn namespace_of(&self, namespace: Option<&str>) -> String {
+        namespace.or(self.namespace.as_deref()).map(String::from).unwrap_or_default()
+    }
+
+    /// Resolves the namespace of a key as Datastore does, to match the keys it sends back.
+    pub(crate) fn scoped_key(&self, key: &Key) -> Key {
+        let namespace = Some(self.namespace_of(key.get_namespace())).filter(|ns| !ns.is_empty());
+        key.clone().in_namespace(namespace)
+    }
+
     /// Creates a new client for the specified project.
     ///
-    /// Credentials are looked up in the `GOOGLE_APPLICATION_CREDENTIALS` environment variable.
+    /// Credentials are looked up in the environment, as described in the
+    /// [`authorize`](crate::authorize) module.
     pub async fn new(project_name: impl Into<String>) -> Result<Client, Error> {
-        let path = env::var("GOOGLE_APPLICATION_CREDENTIALS")?;
-        let path = Path::new(&path);
-        let file = File::open(path)?;
-        let creds = json::from_reader(file)?;
-
-        Client::from_credentials(project_name, creds).await
+        ClientBuilder::new(project_name).build().await
     }
 
     /// Creates a new client for the specified project with custom credentials.
@@ -87,26 +196,35 @@ impl Client {
         project_name: impl Into<String>,
         creds: ApplicationCredentials,
     ) -> Result<Client, Error> {
-        let tls_config = ClientTlsConfig::new()
-            .ca_certificate(Certificate::from_pem(TLS_CERTS))
-            .domain_name(Client::DOMAIN_NAME);
-
-        let channel =
-            Channel::from_static(Client::ENDPOINT).tls_config(tls_config)?.connect().await?;
-
-        Ok(Client {
-            project_name: project_name.into(),
-            service: DatastoreClient::new(channel),
-            token_manager: Arc::new(Mutex::new(TokenManager::new(creds, Client::SCOPES.as_ref()))),
-            index_excluded: IndexExcluded::new()?,
-        })
+        ClientBuilder::new(project_name).build_with_credentials(creds).await
+    }
+
+    /// Creates a new client for the specified project, impersonating a service account.
+    pub async fn from_impersonated_credentials(
+        project_name: impl Into<String>,
+        creds: ImpersonatedCredentials,
+    ) -> Result<Client, Error> {
+        ClientBuilder::new(project_name).build_with_impersonated_credentials(creds).await
+    }
+
+    /// Creates a new client for the specified project, authenticated by a custom token provider.
+    pub async fn from_token_provider(
+        project_name: impl Into<String>,
+        token_provider: impl TokenProvider + 'static,
+    ) -> Result<Client, Error> {
+        ClientBuilder::new(project_name).build_with_token_provider(token_provider).await
+    }
+
+    /// Creates a builder to configure the client before connecting.
+    pub fn builder(project_name: impl Into<String>) -> ClientBuilder {
+        ClientBuilder::new(project_name)
     }
 
     /// Create a new transaction
     ///     - option_mode: Option for the transaction
     ///     - trx_id: Clave de la transacción anterior y que por algún motivo fallo y se ejecuto el rollback
     pub async fn new_transaction(
-        &mut self,
+        &self,
         option_mode: TrxOption,
         trx_id: Option<Vec<u8>>,
     ) -> Result<Transaction, Error> {
@@ -114,14 +232,16 @@ impl Client {
             TrxOption::ReadOnly => Some(api::TransactionOptions {
                 mode: Some(api::transaction_options::Mode::ReadOnly(ReadOnly { read_time: None })),
             }),
-            TrxOption::ReadWrite => match trx_id {
-                Some(trx) => Some(api::TransactionOptions {
-                    mode: Some(api::transaction_options::Mode::ReadWrite(ReadWrite {
-                        previous_transaction: trx,
-                    })),
-                }),
-                None => None,
-            },
+            TrxOption::ReadOnlyAt(time) => Some(api::TransactionOptions {
+                mode: Some(api::transaction_options::Mode::ReadOnly(ReadOnly {
+                    read_time: Some(convert_timestamp(time.naive_utc())),
+                })),
+            }),
+            TrxOption::ReadWrite => trx_id.map(|trx| api::TransactionOptions {
+                mode: Some(api::transaction_options::Mode::ReadWrite(ReadWrite {
+                    previous_transaction: trx,
+                })),
+            }),
             TrxOption::Default => None,
         };
 
@@ -132,16 +252,85 @@ impl Client {
         };
 
         let request = self.construct_request(request).await?;
-        let response = self.service.begin_transaction(request).await?;
+        let rpc = self.rpc(Rpc::BeginTransaction, &request);
+        let mut service = self.service.clone();
+        let response = rpc.run(service.begin_transaction(request)).await?;
         let response = response.into_inner();
 
         Ok(Transaction::new(self.to_owned(), response.transaction))
     }
 
+    /// Runs `operation` in a read-write transaction and commits it.
+    ///
+    /// The operation gets the transaction by value and hands it back with its output once its
+    /// mutations are added. If the transaction is aborted because of contention, the whole
+    /// operation runs again in a new transaction (linked to the aborted one so that it gets
+    /// priority), up to `ClientBuilder::transaction_attempts` times. On any other error,
+    /// the transaction is rolled back and the error is returned.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Client, Key};
+    /// # async fn run(client: Client, key: Key) -> Result<(), google_cloud::datastore::Error> {
+    /// let (count, _) = client
+    ///     .run_in_transaction(|mut tx| {
+    ///         let key = key.clone();
+    ///         async move {
+    ///             let count = tx.get::<i64, _>(&key).await?.unwrap_or(0) + 1;
+    ///             tx.put((key, count)).await?;
+    ///             Ok((tx, count))
+    ///         }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn run_in_transaction<F, Fut, R>(
+        &self,
+        mut operation: F,
+    ) -> Result<(R, Vec<MutationOutcome>), Error>
+    where
+        F: FnMut(Transaction) -> Fut,
+        Fut: Future<Output = Result<(Transaction, R), Error>>,
+    {
+        let mut previous_transaction = None;
+        let mut attempt = 1;
+        loop {
+            let (tx_key, outcome) = traced_transaction(&self.project_name, attempt, async {
+                let tx =
+                    self.new_transaction(TrxOption::ReadWrite, previous_transaction.take()).await?;
+                let tx_key = tx.tx_key.clone();
+
+                let outcome = match operation(tx).await {
+                    Ok((mut tx, output)) => tx.commit().await.map(|results| (output, results)),
+                    Err(error) => {
+                        if !is_aborted(&error) {
+                            //? The commit did not happen, the transaction must be released.
+                            let mut tx = Transaction::new(self.clone(), tx_key.clone());
+                            let _ = tx.rollback().await;
+                        }
+                        Err(error)
+                    }
+                };
+                Ok::<_, Error>((tx_key, outcome))
+            })
+            .await?;
+
+            match outcome {
+                Err(error) if is_aborted(&error) && attempt < self.transaction_attempts => {
+                    tokio::time::sleep(transaction_backoff(attempt)).await;
+                    previous_transaction = Some(tx_key);
+                    attempt += 1;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+
     /// Reserve the ID of an entity before creating it
     /// We can use it for transactions with related entities
-    pub async fn allocate_tx(&mut self, keys: Vec<Key>) -> Result<Vec<Key>, Error> {
-        let ks = keys.iter().map(|key| convert_key(self.project_name.as_str(), key)).collect();
+    pub async fn allocate_tx(&self, keys: Vec<Key>) -> Result<Vec<Key>, Error> {
+        let ks: Vec<_> = keys.iter().map(|key| convert_key(self.partition(), key)).collect();
+        let kind = keys_kind(&ks).to_string();
 
         let request = api::AllocateIdsRequest {
             database_id: "".to_string(),
@@ -150,16 +339,18 @@ impl Client {
         };
 
         let request = self.construct_request(request).await?;
-        let response = self.service.allocate_ids(request).await?;
+        let rpc = self.rpc(Rpc::AllocateIds, &request).kind(&kind);
+        let mut service = self.service.clone();
+        let response = rpc.run(service.allocate_ids(request)).await?;
 
         let response = response.into_inner();
-        let keys = response.keys.into_iter().map(|f| api::Key::into(f)).collect::<Vec<Key>>();
+        let keys = response.keys.into_iter().map(Key::from).collect::<Vec<Key>>();
 
         Ok(keys)
     }
 
     /// Gets an entity from a key.
-    pub async fn get<T, K>(&mut self, key: K) -> Result<Option<T>, Error>
+    pub async fn get<T, K>(&self, key: K) -> Result<Option<T>, Error>
     where
         K: Borrow<Key>,
         T: FromValue,
@@ -169,336 +360,1024 @@ impl Client {
     }
 
     /// Gets multiple entities from multiple keys.
-    pub async fn get_all<T, K, I>(&mut self, keys: I) -> Result<Vec<T>, Error>
+    pub async fn get_all<T, K, I>(&self, keys: I) -> Result<Vec<T>, Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+        T: FromValue,
+    {
+        self.get_all_run(keys, None, None).await
+    }
+
+    /// Gets multiple entities from multiple keys, reporting the progress of the lookup
+    /// to `progress` after each of its rounds (see `lookup_progress_stream` to get a stream).
+    pub async fn get_all_with_progress<T, K, I>(
+        &self,
+        keys: I,
+        progress: impl Fn(&LookupProgress) + Send + Sync,
+    ) -> Result<Vec<T>, Error>
     where
         I: IntoIterator<Item = K>,
         K: Borrow<Key>,
         T: FromValue,
     {
-        Ok(self.get_all_run(keys, None).await?)
+        self.get_all_run(keys, None, Some(&progress)).await
     }
 
     /// Gets multiple entities from multiple keys associated with a transaction
     pub(crate) async fn get_all_run<T, K, I>(
-        &mut self,
+        &self,
         keys: I,
         tx_id: Option<Vec<u8>>,
+        progress: Option<&LookupProgressFn<'_>>,
     ) -> Result<Vec<T>, Error>
     where
         I: IntoIterator<Item = K>,
         K: Borrow<Key>,
         T: FromValue,
+    {
+        let entities = self.lookup_entities(keys, tx_id, progress).await?;
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
+        let mut values = Vec::with_capacity(entities.len());
+        for entity in entities {
+            values.push(T::from_value(entity.properties)?);
+            cooperative.tick().await;
+        }
+
+        Ok(values)
+    }
+
+    /// Looks entities up and returns the ones that were found, in the order of their keys.
+    pub(crate) async fn lookup_entities<K, I>(
+        &self,
+        keys: I,
+        tx_id: Option<Vec<u8>>,
+        progress: Option<&LookupProgressFn<'_>>,
+    ) -> Result<Vec<Entity>, Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+    {
+        let found = self.lookup_raw(keys, tx_id, progress).await?;
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
+        let mut entities = Vec::with_capacity(found.len());
+        for (key, entity) in found {
+            entities.push(Entity { key, properties: entity_properties(entity) });
+            cooperative.tick().await;
+        }
+
+        Ok(entities)
+    }
+
+    /// Looks entities up and returns the ones that were found, still encoded,
+    /// in the order of their keys.
+    async fn lookup_raw<K, I>(
+        &self,
+        keys: I,
+        tx_id: Option<Vec<u8>>,
+        progress: Option<&LookupProgressFn<'_>>,
+    ) -> Result<Vec<(Key, api::Entity)>, Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
     {
         let og_keys: Vec<K> = keys.into_iter().collect();
-        let mut keys: Vec<_> = og_keys
-            .iter()
-            .map(|key| convert_key(self.project_name.as_str(), key.borrow()))
-            .collect();
+        let mut keys: Vec<_> =
+            og_keys.iter().map(|key| convert_key(self.partition(), key.borrow())).collect();
+        let mut tracker = LookupTracker::new(keys.len(), self.lookup_progress.as_ref(), progress);
         let mut found = HashMap::new();
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
+        let read_options = tx_id.map(|tx| api::ReadOptions {
+            consistency_type: Some(api::read_options::ConsistencyType::Transaction(tx)),
+        });
 
+        let mut attempt = 1;
         while !keys.is_empty() {
-            let request = match tx_id.to_owned() {
-                Some(tx) => api::LookupRequest {
-                    keys,
-                    database_id: "".to_string(),
-                    project_id: self.project_name.clone(),
-                    read_options: Some(api::ReadOptions {
-                        consistency_type: Some(api::read_options::ConsistencyType::Transaction(tx)),
-                    }),
-                },
-                None => api::LookupRequest {
-                    keys,
-                    database_id: "".to_string(),
-                    project_id: self.project_name.clone(),
-                    read_options: None,
-                },
+            let batch_size = self.lookup_batch_size.load(Ordering::Relaxed).clamp(1, keys.len());
+            let rest = keys.split_off(batch_size);
+            let batch = mem::replace(&mut keys, rest);
+
+            let kind = keys_kind(&batch).to_string();
+            let request = api::LookupRequest {
+                keys: batch.clone(),
+                database_id: "".to_string(),
+                project_id: self.project_name.clone(),
+                read_options: read_options.clone(),
             };
 
             let request = self.construct_request(request).await?;
-            let response = self.service.lookup(request).await?;
+            let rpc = self.rpc(Rpc::Lookup, &request).kind(&kind).attempt(attempt);
+            let mut service = self.service.clone();
+            let response = match rpc.run(service.lookup(request)).await {
+                Ok(response) => response.into_inner(),
+                Err(status) if batch_size > 1 && is_message_too_large(&status) => {
+                    //? Retry the same keys with a smaller batch.
+                    self.lookup_batch_size.store(batch_size / 2, Ordering::Relaxed);
+                    keys.splice(0..0, batch);
+                    attempt += 1;
+                    continue;
+                }
+                Err(status) => return Err(status.into()),
+            };
+            attempt = 1;
 
-            let response = response.into_inner();
-            found.extend(
-                response
-                    .found
-                    .into_iter()
-                    .map(|val| val.entity.unwrap())
-                    .map(Entity::from)
-                    .map(|entity| (entity.key, entity.properties)),
+            let next_size = next_lookup_batch_size(
+                batch_size,
+                response.encoded_len(),
+                self.max_decoding_message_size,
             );
-            keys = response.deferred;
+            self.lookup_batch_size.store(next_size, Ordering::Relaxed);
+
+            let (found_count, missing_count) = (response.found.len(), response.missing.len());
+            let deferred_count = response.deferred.len();
+            for result in response.found {
+                let entity = result.entity.unwrap();
+                found.insert(Key::from(entity.key.clone().unwrap()), entity);
+                cooperative.tick().await;
+            }
+            keys.extend(response.deferred);
+            tracker.round(found_count, missing_count, deferred_count, keys.len());
         }
 
-        let values: Vec<T> = og_keys
+        let entities = og_keys
             .into_iter()
-            .flat_map(|key| found.remove(key.borrow()))
-            .map(FromValue::from_value)
-            .collect::<Result<Vec<_>, _>>()?;
+            .flat_map(|key| found.remove_entry(&self.scoped_key(key.borrow())))
+            .collect();
+
+        Ok(entities)
+    }
+
+    /// Gets an entity from a key, unless it has expired.
+    pub async fn get_unexpired<T, K>(&self, key: K) -> Result<Option<T>, Error>
+    where
+        K: Borrow<Key>,
+        T: FromValue + Expiring,
+    {
+        let results = self.get_all_unexpired(Some(key.borrow())).await?;
+        Ok(results.into_iter().next())
+    }
+
+    /// Gets multiple entities from multiple keys, leaving the expired ones out.
+    pub async fn get_all_unexpired<T, K, I>(&self, keys: I) -> Result<Vec<T>, Error>
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<Key>,
+        T: FromValue + Expiring,
+    {
+        let entities = self.lookup_entities(keys, None, None).await?;
+        self.skip_expired(entities).await
+    }
+
+    /// Runs a query and converts the results, leaving the expired entities out.
+    pub async fn query_unexpired<T>(&self, query: Query) -> Result<(Vec<T>, Vec<u8>), Error>
+    where
+        T: FromValue + Expiring,
+    {
+        let (entities, cursor) = self.query_run(query, None).await?;
+        Ok((self.skip_expired(entities).await?, cursor))
+    }
+
+    /// Deletes the expired entities queued by the `*_unexpired` reads
+    /// (see `ClientBuilder::queue_expired`) and returns how many were deleted.
+    pub async fn purge_expired(&self) -> Result<usize, Error> {
+        let keys = mem::take(&mut *self.expired_keys.lock().unwrap());
+        let count = keys.len();
+        self.delete_all(keys).await?;
+        Ok(count)
+    }
+
+    async fn skip_expired<T>(&self, entities: Vec<Entity>) -> Result<Vec<T>, Error>
+    where
+        T: FromValue + Expiring,
+    {
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
+        let mut values = Vec::with_capacity(entities.len());
+        let mut expired = Vec::new();
+        for entity in entities {
+            let value = T::from_value(entity.properties)?;
+            if value.is_expired() {
+                expired.push(entity.key);
+            } else {
+                values.push(value);
+            }
+            cooperative.tick().await;
+        }
+        if self.queue_expired && !expired.is_empty() {
+            self.expired_keys.lock().unwrap().extend(expired);
+        }
 
         Ok(values)
     }
 
-    /// Inserts a new entity and returns its key.
-    /// If the entity's key is incomplete, the returned key will be one generated by the store for this entity.
-    pub async fn put(&mut self, entity: impl IntoEntity) -> Result<Option<Key>, Error> {
-        let entity = entity.into_entity()?;
-        let result = self.put_all(Some(entity)).await?;
-        Ok(result.into_iter().next().flatten())
+    /// Inserts a new entity and returns the outcome of its mutation.
+    /// If the entity's key is incomplete, the outcome is a `MutationOutcome::InsertedKey`
+    /// holding the key generated by the store for this entity.
+    ///
+    /// Entities with a complete key are upserted: use `insert` or `update` for strict semantics.
+    pub async fn put(&self, entity: impl IntoEntity) -> Result<MutationOutcome, Error> {
+        let outcomes = self.put_all(Some(entity)).await?;
+        single_outcome(outcomes)
+    }
+
+    /// Inserts new entities and returns the outcomes of their mutations, in input order.
+    /// If an entity's key is incomplete, its outcome is a `MutationOutcome::InsertedKey`
+    /// holding the key generated by the store for this entity.
+    ///
+    /// Large inputs are sent in several commits (500 mutations or 10 MiB each), which are not atomic together.
+    pub async fn put_all<T, I>(&self, entities: I) -> Result<Vec<MutationOutcome>, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Auto).await
+    }
+
+    /// Inserts an entity that must not exist yet and returns the outcome of its mutation.
+    ///
+    /// Fails with `Error::AlreadyExists` if an entity with the same key is already stored.
+    pub async fn insert(&self, entity: impl IntoEntity) -> Result<MutationOutcome, Error> {
+        let outcomes = self.insert_all(Some(entity)).await?;
+        single_outcome(outcomes)
+    }
+
+    /// Inserts entities that must not exist yet and returns the outcomes of their mutations, in input order.
+    ///
+    /// Large inputs are sent in several commits (500 mutations or 10 MiB each), which are not atomic together.
+    pub async fn insert_all<T, I>(&self, entities: I) -> Result<Vec<MutationOutcome>, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Insert).await
+    }
+
+    /// Updates an entity that must already exist and returns the outcome of its mutation.
+    ///
+    /// Fails with `Error::NotFound` if no entity with this key is stored.
+    pub async fn update(&self, entity: impl IntoEntity) -> Result<MutationOutcome, Error> {
+        let outcomes = self.update_all(Some(entity)).await?;
+        single_outcome(outcomes)
+    }
+
+    /// Updates entities that must already exist and returns the outcomes of their mutations, in input order.
+    ///
+    /// Large inputs are sent in several commits (500 mutations or 10 MiB each), which are not atomic together.
+    pub async fn update_all<T, I>(&self, entities: I) -> Result<Vec<MutationOutcome>, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Update).await
+    }
+
+    /// Creates or replaces an entity and returns the outcome of its mutation.
+    pub async fn upsert(&self, entity: impl IntoEntity) -> Result<MutationOutcome, Error> {
+        let outcomes = self.upsert_all(Some(entity)).await?;
+        single_outcome(outcomes)
     }
 
-    /// Inserts new entities and returns their keys.
-    /// If an entity's key is incomplete, its returned key will be one generated by the store for this entity.
-    pub async fn put_all<T, I>(&mut self, entities: I) -> Result<Vec<Option<Key>>, Error>
+    /// Creates or replaces entities and returns the outcomes of their mutations, in input order.
+    ///
+    /// Large inputs are sent in several commits (500 mutations or 10 MiB each), which are not atomic together.
+    pub async fn upsert_all<T, I>(&self, entities: I) -> Result<Vec<MutationOutcome>, Error>
     where
         I: IntoIterator<Item = T>,
         T: IntoEntity,
     {
-        let entities: Vec<Entity> =
+        self.write_all(entities, WriteMode::Upsert).await
+    }
+
+    /// Writes an entity only if its stored version is still `base_version`,
+    /// and returns the outcome of its mutation.
+    ///
+    /// The version comes from a previous `MutationOutcome`: if the entity was modified since,
+    /// nothing is written and this fails with `Error::ConflictDetected`.
+    pub async fn put_if_version(
+        &self,
+        entity: impl IntoEntity,
+        base_version: i64,
+    ) -> Result<MutationOutcome, Error> {
+        let strategy = ConflictDetectionStrategy::BaseVersion(base_version);
+        self.put_conditionally(entity, strategy).await
+    }
+
+    /// Writes an entity only if its stored update time is still `update_time`,
+    /// and returns the outcome of its mutation.
+    ///
+    /// The time comes from a previous `MutationOutcome`: if the entity was modified since,
+    /// nothing is written and this fails with `Error::ConflictDetected`.
+    pub async fn put_if_unchanged_since(
+        &self,
+        entity: impl IntoEntity,
+        update_time: NaiveDateTime,
+    ) -> Result<MutationOutcome, Error> {
+        let strategy = ConflictDetectionStrategy::UpdateTime(convert_timestamp(update_time));
+        self.put_conditionally(entity, strategy).await
+    }
+
+    async fn put_conditionally(
+        &self,
+        entity: impl IntoEntity,
+        strategy: ConflictDetectionStrategy,
+    ) -> Result<MutationOutcome, Error> {
+        let (mut mutations, targets) = self.entity_mutations(Some(entity), WriteMode::Auto)?;
+        for mutation in mutations.iter_mut() {
+            mutation.conflict_detection_strategy = Some(strategy.clone());
+        }
+        let outcome = single_outcome(self.commit_outcomes(mutations, targets).await?)?;
+        if outcome.is_conflict() {
+            return Err(Error::ConflictDetected);
+        }
+
+        Ok(outcome)
+    }
+
+    async fn write_all<T, I>(
+        &self,
+        entities: I,
+        mode: WriteMode,
+    ) -> Result<Vec<MutationOutcome>, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        let (mutations, targets) = self.entity_mutations(entities, mode)?;
+
+        self.commit_outcomes(mutations, targets).await
+    }
+
+    /// Converts entities into the mutations writing them with the given mode,
+    /// along with what they are about.
+    ///
+    /// Incomplete keys get their ID from the `IdAllocator` of the client, if it has one.
+    pub(crate) fn entity_mutations<T, I>(
+        &self,
+        entities: I,
+        mode: WriteMode,
+    ) -> Result<(Vec<api::Mutation>, Vec<MutationTarget>), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        let mut entities: Vec<Entity> =
             entities.into_iter().map(IntoEntity::into_entity).collect::<Result<_, _>>()?;
+        for entity in entities.iter_mut() {
+            entity.key.validate()?;
+            self.non_finite_policy.apply(&mut entity.properties)?;
+        }
 
+        let mut targets = Vec::with_capacity(entities.len());
         let mutations = entities
             .into_iter()
-            .map(|entity| {
+            .map(|mut entity| {
+                let allocated = match &self.id_allocator {
+                    Some(allocator) if entity.key.is_incomplete() => {
+                        let id = allocator.allocate(&entity.key);
+                        entity.key = entity.key.new_id(id);
+                        true
+                    }
+                    _ => false,
+                };
                 let is_incomplete = entity.key.is_new || entity.key.is_incomplete();
-                let entity = convert_entity(
-                    self.project_name.as_str(),
-                    entity,
-                    self.index_excluded.to_owned(),
-                );
-                api::Mutation {
-                    operation: if is_incomplete {
-                        Some(api::mutation::Operation::Insert(entity))
-                    } else {
-                        Some(api::mutation::Operation::Upsert(entity))
-                    },
-                    conflict_detection_strategy: None,
-                }
+                let entity =
+                    convert_entity(self.partition(), entity, self.index_excluded.to_owned());
+                let operation = match mode {
+                    WriteMode::Auto if is_incomplete => api::mutation::Operation::Insert(entity),
+                    WriteMode::Auto => api::mutation::Operation::Upsert(entity),
+                    WriteMode::Insert => api::mutation::Operation::Insert(entity),
+                    WriteMode::Update => api::mutation::Operation::Update(entity),
+                    WriteMode::Upsert => api::mutation::Operation::Upsert(entity),
+                };
+                let mutation =
+                    api::Mutation { operation: Some(operation), conflict_detection_strategy: None };
+                targets.push(MutationTarget::of(&mutation).allocated(allocated));
+                mutation
             })
             .collect();
 
-        let request = api::CommitRequest {
-            mutations,
-            mode: api::commit_request::Mode::NonTransactional as i32,
-            transaction_selector: None,
-            database_id: "".to_string(),
-            project_id: self.project_name.clone(),
-        };
-        let request = self.construct_request(request).await?;
-        let response = self.service.commit(request).await?;
-        let response = response.into_inner();
-        let keys =
-            response.mutation_results.into_iter().map(|result| result.key.map(Key::from)).collect();
-
-        Ok(keys)
+        Ok((mutations, targets))
     }
 
-    /// Deletes an entity identified by a key.
-    pub async fn delete(&mut self, key: impl Borrow<Key>) -> Result<(), Error> {
-        self.delete_all(Some(key.borrow())).await
+    /// Deletes an entity identified by a key and returns the outcome of its mutation.
+    pub async fn delete(&self, key: impl Borrow<Key>) -> Result<MutationOutcome, Error> {
+        let outcomes = self.delete_all(Some(key.borrow())).await?;
+        single_outcome(outcomes)
     }
 
-    /// Deletes multiple entities identified by multiple keys.
-    pub async fn delete_all<T, I>(&mut self, keys: I) -> Result<(), Error>
+    /// Deletes multiple entities identified by multiple keys
+    /// and returns the outcomes of their mutations, in input order.
+    ///
+    /// Large inputs are sent in several commits (500 mutations or 10 MiB each), which are not atomic together.
+    pub async fn delete_all<T, I>(&self, keys: I) -> Result<Vec<MutationOutcome>, Error>
     where
         I: IntoIterator<Item = T>,
         T: Borrow<Key>,
     {
-        let mutations = keys
+        let mutations: Vec<api::Mutation> = keys
             .into_iter()
-            .map(|key| convert_key(self.project_name.as_str(), key.borrow()))
+            .map(|key| convert_key(self.partition(), key.borrow()))
             .map(|key| api::Mutation {
                 operation: Some(api::mutation::Operation::Delete(key)),
                 conflict_detection_strategy: None,
             })
             .collect();
+        let targets = mutations.iter().map(MutationTarget::of).collect();
 
-        let request = api::CommitRequest {
-            mutations,
-            mode: api::commit_request::Mode::NonTransactional as i32,
-            transaction_selector: None,
-            database_id: "".to_string(),
-            project_id: self.project_name.clone(),
-        };
-        let request = self.construct_request(request).await?;
-        self.service.commit(request).await?;
+        self.commit_outcomes(mutations, targets).await
+    }
+
+    /// Commits mutations non-transactionally (see `commit_mutations`)
+    /// and pairs their results with the keys they are about.
+    pub(crate) async fn commit_outcomes(
+        &self,
+        mutations: Vec<api::Mutation>,
+        targets: Vec<MutationTarget>,
+    ) -> Result<Vec<MutationOutcome>, Error> {
+        let results = self.commit_mutations(mutations).await?;
+
+        mutation_outcomes(targets, results)
+    }
+
+    /// Commits mutations non-transactionally, split in as many commits as needed to respect
+    /// the mutation count and request size limits, and returns the results in input order.
+    ///
+    /// Every commit is applied on its own: if one of them fails, the previous ones are kept.
+    /// Up to `commit_concurrency` commits are kept in flight at once (see `ClientBuilder`),
+    /// their results are still reported in order and the first failure stops the remaining ones,
+    /// although commits already in flight may still be applied.
+    pub(crate) async fn commit_mutations(
+        &self,
+        mutations: Vec<api::Mutation>,
+    ) -> Result<Vec<api::MutationResult>, Error> {
+        //? Leave some room for the fields of the request other than the mutations.
+        let max_size = self.max_encoding_message_size.saturating_sub(Client::COMMIT_OVERHEAD);
+        let chunks = chunk_by_size(mutations, Client::MAX_COMMIT_MUTATIONS, max_size, |m| {
+            m.encoded_len() + Client::COMMIT_OVERHEAD_PER_MUTATION
+        });
+
+        let commits = chunks.into_iter().map(|mutations| {
+            let client = self;
+            async move {
+                let kind = mutations_kind(&mutations).to_string();
+                let audit = client.audit_log(&mutations);
+                let request = api::CommitRequest {
+                    mutations,
+                    mode: api::commit_request::Mode::NonTransactional as i32,
+                    transaction_selector: None,
+                    database_id: "".to_string(),
+                    project_id: client.project_name.clone(),
+                };
+                let request = client.construct_request(request).await?;
+                let rpc = client.rpc(Rpc::Commit, &request).kind(&kind);
+                let mut service = client.service.clone();
+                let results = rpc.run(service.commit(request)).await?.into_inner().mutation_results;
+                client.report_audit(audit, false, &results);
+                Ok::<_, Error>(results)
+            }
+        });
+
+        let mut commits = stream::iter(commits).buffered(self.commit_concurrency.max(1));
+        let mut results = Vec::new();
+        while let Some(batch) = commits.try_next().await? {
+            results.extend(batch);
+        }
 
-        Ok(())
+        Ok(results)
     }
 
     /// Runs a (potentially) complex query againt Datastore and returns the results.
-    pub async fn query(&mut self, query: Query) -> Result<(Vec<Entity>, Vec<u8>), Error> {
-        Ok(self.query_run(query, None).await?)
+    ///
+    /// Queries that only select entities by their keys can run as lookups of these keys
+    /// instead (see `Query::key_lookup`).
+    pub async fn query(&self, query: Query) -> Result<(Vec<Entity>, Vec<u8>), Error> {
+        self.query_run(query, None).await
+    }
+
+    /// Runs a (potentially) complex query againt Datastore and converts the results as they arrive.
+    ///
+    /// The entities are decoded straight from each response batch, without building an
+    /// intermediate `Entity` for each of them, which keeps the memory usage of large scans low.
+    pub async fn query_as<T>(&self, query: Query) -> Result<(Vec<T>, Vec<u8>), Error>
+    where
+        T: FromValue,
+    {
+        self.query_as_run(query, None).await
+    }
+
+    pub(crate) async fn query_as_run<T>(
+        &self,
+        query: Query,
+        tx_id: Option<Vec<u8>>,
+    ) -> Result<(Vec<T>, Vec<u8>), Error>
+    where
+        T: FromValue,
+    {
+        self.query_run_with(query, tx_id, |entity| Ok(T::from_value(entity_properties(entity))?))
+            .await
     }
 
     /// Runs a (potentially) complex query againt Datastore and returns the results and associated with a transaction
     pub(crate) async fn query_run(
-        &mut self,
+        &self,
         query: Query,
         tx_id: Option<Vec<u8>>,
     ) -> Result<(Vec<Entity>, Vec<u8>), Error> {
+        self.query_run_with(query, tx_id, |entity| Ok(Entity::from(entity))).await
+    }
+
+    /// Runs a query through all its batches, converting each entity as soon as its batch arrives.
+    async fn query_run_with<T>(
+        &self,
+        query: Query,
+        tx_id: Option<Vec<u8>>,
+        mut convert: impl FnMut(api::Entity) -> Result<T, Error>,
+    ) -> Result<(Vec<T>, Vec<u8>), Error> {
+        let started = Instant::now();
         let mut output = Vec::new();
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
 
-        let mut cur_query = query.clone();
+        if let Some(keys) = query.lookup_keys() {
+            //? Keys of other namespaces cannot match, and each entity is only yielded once.
+            let namespace = self.namespace_of(query.namespace.as_deref());
+            let mut seen = HashSet::new();
+            let keys: Vec<Key> = keys
+                .into_iter()
+                .filter(|key| self.namespace_of(key.get_namespace()) == namespace)
+                .filter(|key| seen.insert(self.scoped_key(key)))
+                .collect();
 
-        let mut cursor = match query.cursor.to_owned() {
-            Some(c) => c,
-            None => Vec::new(),
-        };
+            for (_, entity) in self.lookup_raw(keys, tx_id, None).await? {
+                output.push(convert(entity)?);
+                cooperative.tick().await;
+            }
+            return Ok((output, Vec::new()));
+        }
+
+        let mut cursor = query.cursor.to_owned().unwrap_or_default();
 
         loop {
-            let api_query = convert_query(&self.project_name, cur_query.to_owned(), cursor);
+            let results = self.query_batch(&query, cursor, tx_id.to_owned()).await?;
 
+            for result in results.entity_results {
+                output.push(convert(result.entity.unwrap())?);
+                cooperative.tick().await;
+            }
+
+            if results.more_results
+                != (api::query_result_batch::MoreResultsType::NotFinished as i32)
+            {
+                if let Some(log) = &self.slow_query_log {
+                    let fingerprint = query.fingerprint();
+                    log.report(
+                        started,
+                        fingerprint,
+                        || query.shape(),
+                        output.len(),
+                        results.more_results,
+                    );
+                }
+                break Ok((output, results.end_cursor));
+            }
+
+            cursor = results.end_cursor;
+        }
+    }
+
+    /// Runs a query and yields its results as its batches arrive, without buffering them all.
+    ///
+    /// Only one batch is held in memory at a time, which keeps large scans cheap.
+    /// Unlike `Client::query`, the query always runs as such, even when it only selects keys.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Client, Query};
+    /// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+    /// use futures::TryStreamExt;
+    ///
+    /// let mut users = Box::pin(client.query_stream(Query::new("users")));
+    /// while let Some(user) = users.try_next().await? {
+    ///     println!("{:?}", user.key());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn query_stream(
+        &self,
+        query: Query,
+    ) -> impl Stream<Item = Result<Entity, Error>> + Send + 'static {
+        self.query_stream_run(query, None)
+    }
+
+    /// Runs a query batch by batch, yielding each result as soon as its batch arrives,
+    /// associated with a transaction.
+    pub(crate) fn query_stream_run(
+        &self,
+        query: Query,
+        tx_id: Option<Vec<u8>>,
+    ) -> impl Stream<Item = Result<Entity, Error>> + Send + 'static {
+        let cursor = query.cursor.to_owned().unwrap_or_default();
+        let state = Some((self.clone(), query, tx_id, cursor));
+
+        let batches = stream::try_unfold(state, |state| async move {
+            let Some((client, query, tx_id, cursor)) = state else {
+                return Ok(None);
+            };
+            let results = client.query_batch(&query, cursor, tx_id.clone()).await?;
+
+            let entities: Vec<_> = results
+                .entity_results
+                .into_iter()
+                .map(|result| Ok(Entity::from(result.entity.unwrap())))
+                .collect();
+            let next = (results.more_results
+                == (api::query_result_batch::MoreResultsType::NotFinished as i32))
+                .then_some((client, query, tx_id, results.end_cursor));
+            Ok::<_, Error>(Some((stream::iter(entities), next)))
+        });
+
+        batches.try_flatten()
+    }
+
+    /// Runs a single RunQuery call, starting at the given cursor, and returns the raw batch.
+    pub(crate) async fn query_batch(
+        &self,
+        query: &Query,
+        cursor: Vec<u8>,
+        tx_id: Option<Vec<u8>>,
+    ) -> Result<api::QueryResultBatch, Error> {
+        let api_query = convert_query(self.partition(), query.to_owned(), cursor);
+
+        let request = api::RunQueryRequest {
+            partition_id: Some(api::PartitionId {
+                database_id: "".to_string(),
+                project_id: self.project_name.clone(),
+                namespace_id: self.namespace_of(query.namespace.as_deref()),
+            }),
+            query_type: Some(api::run_query_request::QueryType::Query(api_query)),
+            read_options: Some(query_read_options(query, tx_id)),
+            database_id: "".to_string(),
+            project_id: self.project_name.clone(),
+        };
+
+        let request = self.construct_request(request).await?;
+        let rpc = self.rpc(Rpc::RunQuery, &request).kind(&query.kind);
+        let mut service = self.service.clone();
+        let results = rpc.run(service.run_query(request)).await?;
+
+        Ok(results.into_inner().batch.unwrap())
+    }
+
+    /// Runs a GQL query against Datastore and returns the results, like `Client::query`.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Client, GqlQuery};
+    /// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+    /// let query = GqlQuery::new("SELECT * FROM users WHERE age > @age").bind("age", 10);
+    /// let (entities, cursor) = client.gql(query).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn gql(&self, query: GqlQuery) -> Result<(Vec<Entity>, Vec<u8>), Error> {
+        self.gql_run(query, None).await
+    }
+
+    /// Runs a GQL query through all its batches.
+    ///
+    /// The first request sends the GQL query itself, the next ones the structured query
+    /// parsed by Datastore, starting at the cursor of the previous batch.
+    pub(crate) async fn gql_run(
+        &self,
+        query: GqlQuery,
+        tx_id: Option<Vec<u8>>,
+    ) -> Result<(Vec<Entity>, Vec<u8>), Error> {
+        let started = Instant::now();
+        let query_string = query.query_string.clone();
+        let partition_id = api::PartitionId {
+            database_id: "".to_string(),
+            project_id: self.project_name.clone(),
+            namespace_id: self.namespace_of(query.namespace.as_deref()),
+        };
+        let read_options = tx_id.map(|tx| api::ReadOptions {
+            consistency_type: Some(api::read_options::ConsistencyType::Transaction(tx)),
+        });
+        let mut query_type =
+            api::run_query_request::QueryType::GqlQuery(convert_gql_query(self.partition(), query));
+        let mut parsed: Option<api::Query> = None;
+        let mut output = Vec::new();
+        let mut cooperative = CooperativeYield::new(self.yield_interval);
+
+        loop {
             let request = api::RunQueryRequest {
-                partition_id: Some(api::PartitionId {
-                    database_id: "".to_string(),
-                    project_id: self.project_name.clone(),
-                    namespace_id: cur_query.namespace.unwrap_or_else(String::new),
-                }),
-                query_type: Some(api::run_query_request::QueryType::Query(api_query)),
-                read_options: Some({
-                    use api::read_options::{ConsistencyType, ReadConsistency};
-                    api::ReadOptions {
-                        consistency_type: Some(match tx_id.to_owned() {
-                            Some(tx) => ConsistencyType::Transaction(tx),
-                            None => ConsistencyType::ReadConsistency(if cur_query.eventual {
-                                ReadConsistency::Eventual as i32
-                            } else {
-                                ReadConsistency::Strong as i32
-                            }),
-                        }),
-                    }
-                }),
+                partition_id: Some(partition_id.clone()),
+                query_type: Some(query_type),
+                read_options: read_options.clone(),
                 database_id: "".to_string(),
                 project_id: self.project_name.clone(),
             };
-
             let request = self.construct_request(request).await?;
-            let results = self.service.run_query(request).await?;
-            let results = results.into_inner().batch.unwrap();
+            let rpc = self.rpc(Rpc::RunQuery, &request);
+            let mut service = self.service.clone();
+            let response = rpc.run(service.run_query(request)).await?.into_inner();
+            let results = response.batch.unwrap();
+
+            for result in results.entity_results {
+                output.push(Entity::from(result.entity.unwrap()));
+                cooperative.tick().await;
+            }
 
-            output.extend(
-                results.entity_results.into_iter().map(|el| Entity::from(el.entity.unwrap())),
-            );
+            //? Only the answer to the GQL request carries the parsed query, the next
+            //? pages are requested with it as a structured query.
+            parsed = response.query.or(parsed);
+            if results.more_results
+                == (api::query_result_batch::MoreResultsType::NotFinished as i32)
+            {
+                let parsed = parsed.clone().ok_or_else(|| {
+                    Status::internal("the server did not return the parsed GQL query")
+                })?;
+                let parsed = api::Query { start_cursor: results.end_cursor, ..parsed };
+                query_type = api::run_query_request::QueryType::Query(parsed);
+                continue;
+            }
+
+            if let Some(log) = &self.slow_query_log {
+                let fingerprint = fnv1a(&query_string);
+                log.report(
+                    started,
+                    fingerprint,
+                    || query_string,
+                    output.len(),
+                    results.more_results,
+                );
+            }
+            break Ok((output, results.end_cursor));
+        }
+    }
+
+    /// Deletes all the entities matching a query and returns how many were deleted.
+    ///
+    /// The matching keys are fetched page by page with a keys-only query, and every page is
+    /// deleted with `delete_all`.
+    pub async fn delete_by_query(&self, query: Query) -> Result<usize, Error> {
+        let query = query.keys_only();
+        let mut cursor = query.cursor.to_owned().unwrap_or_default();
+        let mut deleted = 0;
+
+        loop {
+            let results = self.query_batch(&query, cursor, None).await?;
+
+            let keys: Vec<Key> = results
+                .entity_results
+                .into_iter()
+                .map(|el| Key::from(el.entity.unwrap().key.unwrap()))
+                .collect();
+            deleted += keys.len();
+            self.delete_all(keys).await?;
 
             if results.more_results
                 != (api::query_result_batch::MoreResultsType::NotFinished as i32)
             {
-                break Ok((output, results.end_cursor));
+                break Ok(deleted);
             }
 
-            cur_query = query.clone();
             cursor = results.end_cursor;
         }
     }
 
-    /// Runs a (potentially) complex query againt Datastore and returns the results.
+    /// Runs an aggregation query againt Datastore and returns the results by alias.
+    ///
+    /// ```no_run
+    /// # use google_cloud::datastore::{Aggregation, Client, Query};
+    /// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+    /// let aggregations = vec![
+    ///     Aggregation::CountUpTo("total".into(), 10_000),
+    ///     Aggregation::Avg("mean_age".into(), "age".into()),
+    /// ];
+    /// let results = client.aggregation_query(aggregations, Query::new("users")).await?;
+    /// let total = results.count("total");
+    /// let mean_age = results.avg("mean_age");
+    /// # Ok(())
+    /// # }
+    /// ```
     pub async fn aggregation_query(
-        &mut self,
+        &self,
         aggregations: Vec<Aggregation>,
         query: Query,
-    ) -> Result<Vec<Value>, Error> {
-        Ok(self.aggregation_query_run(aggregations, query, None).await?)
+    ) -> Result<AggregationResults, Error> {
+        self.aggregation_query_run(aggregations, query, None).await
     }
 
-    /// Runs a (potentially) complex query againt Datastore and returns the results and associated with a transaction
+    /// Runs an aggregation query againt Datastore and returns the results, associated with a transaction.
     pub(crate) async fn aggregation_query_run(
-        &mut self,
+        &self,
         aggregations: Vec<Aggregation>,
         query: Query,
         tx_id: Option<Vec<u8>>,
-    ) -> Result<Vec<Value>, Error> {
-        let cur_query = query.clone();
-
-        let cursor = match query.cursor.to_owned() {
-            Some(c) => c,
-            None => Vec::new(),
+    ) -> Result<AggregationResults, Error> {
+        let cursor = query.cursor.to_owned().unwrap_or_default();
+        let namespace = query.namespace.to_owned();
+        let read_options = query_read_options(&query, tx_id);
+        let api_query = convert_query(self.partition(), query, cursor);
+
+        let aggregation_query = api::AggregationQuery {
+            aggregations: aggregations.into_iter().map(convert_aggregation).collect(),
+            query_type: Some(api::aggregation_query::QueryType::NestedQuery(api_query)),
         };
+        let query_type =
+            api::run_aggregation_query_request::QueryType::AggregationQuery(aggregation_query);
 
-        let api_query = convert_query(&self.project_name, cur_query.to_owned(), cursor);
+        self.aggregation_run(query_type, namespace, Some(read_options)).await
+    }
 
-        let aggregations = aggregations
-            .to_vec()
-            .into_iter()
-            .map(|aggr| match aggr {
-                super::Aggregation::Count(alias) => {
-                    let operator = api::aggregation_query::aggregation::Operator::Count(Count {
-                        up_to: Some(1000),
-                    });
-                    api::aggregation_query::Aggregation { operator: Some(operator), alias }
-                }
-                super::Aggregation::Sum(alias, property) => {
-                    let operator = api::aggregation_query::aggregation::Operator::Sum(Sum {
-                        property: Some(api::PropertyReference { name: property }),
-                    });
-                    api::aggregation_query::Aggregation { operator: Some(operator), alias }
-                }
-                super::Aggregation::Avg(alias, property) => {
-                    let operator = api::aggregation_query::aggregation::Operator::Avg(
-                        api::aggregation_query::aggregation::Avg {
-                            property: Some(api::PropertyReference { name: property }),
-                        },
-                    );
-                    api::aggregation_query::Aggregation { operator: Some(operator), alias }
-                }
-            })
-            .collect::<Vec<api::aggregation_query::Aggregation>>();
+    /// Runs a GQL aggregation query (such as `SELECT COUNT(*) AS total FROM users`) againt Datastore
+    /// and returns the results by alias.
+    pub async fn gql_aggregation(&self, query: GqlQuery) -> Result<AggregationResults, Error> {
+        self.gql_aggregation_run(query, None).await
+    }
 
-        let aggregation_query: api::AggregationQuery = api::AggregationQuery {
-            aggregations,
-            query_type: Some(api::aggregation_query::QueryType::NestedQuery(api_query.clone())),
-        };
+    /// Runs a GQL aggregation query againt Datastore and returns the results, associated with a transaction.
+    pub(crate) async fn gql_aggregation_run(
+        &self,
+        query: GqlQuery,
+        tx_id: Option<Vec<u8>>,
+    ) -> Result<AggregationResults, Error> {
+        let namespace = query.namespace.to_owned();
+        let read_options = tx_id.map(|tx| api::ReadOptions {
+            consistency_type: Some(api::read_options::ConsistencyType::Transaction(tx)),
+        });
+        let query_type = api::run_aggregation_query_request::QueryType::GqlQuery(
+            convert_gql_query(self.partition(), query),
+        );
+
+        self.aggregation_run(query_type, namespace, read_options).await
+    }
 
+    async fn aggregation_run(
+        &self,
+        query_type: api::run_aggregation_query_request::QueryType,
+        namespace: Option<String>,
+        read_options: Option<api::ReadOptions>,
+    ) -> Result<AggregationResults, Error> {
+        let kind = match &query_type {
+            api::run_aggregation_query_request::QueryType::AggregationQuery(aggregation) => {
+                match &aggregation.query_type {
+                    Some(api::aggregation_query::QueryType::NestedQuery(query)) => {
+                        query.kind.first().map(|kind| kind.name.clone())
+                    }
+                    None => None,
+                }
+            }
+            api::run_aggregation_query_request::QueryType::GqlQuery(_) => None,
+        };
         let request = api::RunAggregationQueryRequest {
             partition_id: Some(api::PartitionId {
                 database_id: "".to_string(),
                 project_id: self.project_name.clone(),
-                namespace_id: cur_query.namespace.unwrap_or_else(String::new),
-            }),
-            query_type: Some(api::run_aggregation_query_request::QueryType::AggregationQuery(
-                aggregation_query,
-            )),
-            read_options: Some({
-                use api::read_options::{ConsistencyType, ReadConsistency};
-                api::ReadOptions {
-                    consistency_type: Some(match tx_id.to_owned() {
-                        Some(tx) => ConsistencyType::Transaction(tx),
-                        None => ConsistencyType::ReadConsistency(if cur_query.eventual {
-                            ReadConsistency::Eventual as i32
-                        } else {
-                            ReadConsistency::Strong as i32
-                        }),
-                    }),
-                }
+                namespace_id: self.namespace_of(namespace.as_deref()),
             }),
+            query_type: Some(query_type),
+            read_options,
             database_id: "".to_string(),
             project_id: self.project_name.clone(),
         };
         let request = self.construct_request(request).await?;
-        let results = self.service.run_aggregation_query(request).await?;
+        let kind = kind.unwrap_or_default();
+        let rpc = self.rpc(Rpc::RunAggregationQuery, &request).kind(&kind);
+        let mut service = self.service.clone();
+        let results = rpc.run(service.run_aggregation_query(request)).await?;
         let results = results.into_inner().batch.unwrap();
 
-        Ok(results
-            .aggregation_results
-            .into_iter()
-            .map(|el| {
-                let properties = el
-                    .aggregate_properties
-                    .into_iter()
-                    .map(|(k, v)| (k, Value::from(v.value_type.unwrap())))
-                    .collect();
-                Value::EntityValue(properties)
-            })
-            .collect::<Vec<Value>>())
+        Ok(AggregationResults::from(results))
+    }
+}
+
+/// Yields to the runtime every `interval` items (never if `interval` is zero),
+/// so that converting large results does not starve the other tasks of the thread.
+pub(crate) struct CooperativeYield {
+    interval: usize,
+    count: usize,
+}
+
+impl CooperativeYield {
+    pub(crate) fn new(interval: usize) -> CooperativeYield {
+        CooperativeYield { interval, count: 0 }
+    }
+
+    pub(crate) async fn tick(&mut self) {
+        if self.interval == 0 {
+            return;
+        }
+        self.count += 1;
+        if self.count >= self.interval {
+            self.count = 0;
+            tokio::task::yield_now().await;
+        }
+    }
+}
+
+/// Splits items in chunks of at most `max_count` items and `max_size` bytes, keeping their order.
+/// An item bigger than `max_size` gets a chunk of its own.
+pub(crate) fn chunk_by_size<T>(
+    items: Vec<T>,
+    max_count: usize,
+    max_size: usize,
+    size_of: impl Fn(&T) -> usize,
+) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut current_size = 0;
+
+    for item in items {
+        let size = size_of(&item);
+        if !current.is_empty() && (current.len() >= max_count || current_size + size > max_size) {
+            chunks.push(mem::take(&mut current));
+            current_size = 0;
+        }
+        current_size += size;
+        current.push(item);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Was the transaction aborted because of contention ?
+///
+/// Transactions which expired are aborted too, but running them again is bound to fail.
+pub(crate) fn is_aborted(error: &Error) -> bool {
+    matches!(error, Error::Aborted { retryable: true, .. })
+}
+
+/// The delay before the given retry of an aborted transaction.
+pub(crate) fn transaction_backoff(attempt: usize) -> Duration {
+    let factor = 1 << attempt.saturating_sub(1).min(6);
+    Duration::from_millis(100 * factor)
+}
+
+/// Takes the outcome of the only mutation of a commit.
+fn single_outcome(outcomes: Vec<MutationOutcome>) -> Result<MutationOutcome, Error> {
+    outcomes.into_iter().next().ok_or_else(|| Status::internal("missing mutation result").into())
+}
+
+/// Is this status a gRPC message size limit being hit ?
+///
+/// Other `RESOURCE_EXHAUSTED` statuses, such as quota exhaustion, are not size issues
+/// and must not be retried with smaller batches.
+pub(crate) fn is_message_too_large(status: &Status) -> bool {
+    let message = status.message().to_lowercase();
+    match status.code() {
+        Code::ResourceExhausted => message.contains("message larger than max"),
+        Code::OutOfRange => message.contains("message length too large"),
+        _ => false,
     }
 }
 
-fn convert_query(project_name: &str, cur_query: Query, cursor: Vec<u8>) -> api::Query {
-    let projection = cur_query
-        .projections
+/// Computes the size of the next lookup batch from the size of the last response,
+/// aiming at three quarters of the decoding budget and at most doubling at each step.
+pub(crate) fn next_lookup_batch_size(
+    batch_size: usize,
+    response_size: usize,
+    max_message_size: usize,
+) -> usize {
+    let budget = max_message_size / 4 * 3;
+    let per_key = (response_size / batch_size.max(1)).max(1);
+    let upper = batch_size.saturating_mul(2).clamp(1, Client::MAX_LOOKUP_KEYS);
+    (budget / per_key).clamp(1, upper)
+}
+
+fn query_read_options(query: &Query, tx_id: Option<Vec<u8>>) -> api::ReadOptions {
+    use api::read_options::{ConsistencyType, ReadConsistency};
+
+    let consistency_type = match (tx_id, query.read_time) {
+        (Some(tx), _) => ConsistencyType::Transaction(tx),
+        (None, Some(time)) => ConsistencyType::ReadTime(convert_timestamp(time.naive_utc())),
+        (None, None) if query.eventual => {
+            ConsistencyType::ReadConsistency(ReadConsistency::Eventual as i32)
+        }
+        (None, None) => ConsistencyType::ReadConsistency(ReadConsistency::Strong as i32),
+    };
+
+    api::ReadOptions { consistency_type: Some(consistency_type) }
+}
+
+/// The project of the converted keys, and the namespace of the ones without an explicit one.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Partition<'a> {
+    pub(crate) project_name: &'a str,
+    pub(crate) namespace: Option<&'a str>,
+}
+
+fn convert_query(partition: Partition, cur_query: Query, cursor: Vec<u8>) -> api::Query {
+    let projections = if cur_query.keys_only && cur_query.projections.is_empty() {
+        vec![String::from("__key__")]
+    } else {
+        cur_query.projections
+    };
+    let projection = projections
         .into_iter()
         .map(|name| api::Projection { property: Some(api::PropertyReference { name }) })
         .collect();
-    let filter = convert_filter(project_name, cur_query.filters, cur_query.composite_filter);
+    let filter = convert_filter(partition, cur_query.filters, cur_query.composite_filter);
     let order = cur_query
         .ordering
         .into_iter()
@@ -531,12 +1410,16 @@ fn convert_query(project_name: &str, cur_query: Query, cursor: Vec<u8>) -> api::
     }
 }
 
-pub(crate) fn convert_key(project_name: &str, key: &Key) -> api::Key {
+pub(crate) fn convert_key(partition: Partition, key: &Key) -> api::Key {
     api::Key {
         partition_id: Some(api::PartitionId {
             database_id: "".to_string(),
-            project_id: String::from(project_name),
-            namespace_id: key.get_namespace().map(String::from).unwrap_or_default(),
+            project_id: String::from(partition.project_name),
+            namespace_id: key
+                .get_namespace()
+                .or(partition.namespace)
+                .map(String::from)
+                .unwrap_or_default(),
         }),
         path: {
             let mut key = Some(key);
@@ -561,11 +1444,11 @@ pub(crate) fn convert_key(project_name: &str, key: &Key) -> api::Key {
 }
 
 pub(crate) fn convert_entity(
-    project_name: &str,
+    partition: Partition,
     entity: Entity,
     index_excluded: IndexExcluded,
 ) -> api::Entity {
-    let key = convert_key(project_name, &entity.key);
+    let key = convert_key(partition, &entity.key);
     let properties = match entity.clone().properties {
         Value::EntityValue(properties) => properties,
         _ => panic!("unexpected non-entity datastore value"),
@@ -581,7 +1464,7 @@ pub(crate) fn convert_entity(
             (
                 k,
                 convert_value(
-                    project_name,
+                    partition,
                     v,
                     path_excluded.to_vec(),
                     check_exclude_from_indexes(path_excluded),
@@ -592,50 +1475,49 @@ pub(crate) fn convert_entity(
     api::Entity { key: Some(key), properties }
 }
 
+pub(crate) fn convert_timestamp(time: NaiveDateTime) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.and_utc().timestamp(),
+        nanos: time.and_utc().timestamp_subsec_nanos() as i32,
+    }
+}
+
 pub(crate) fn convert_value(
-    project_name: &str,
+    partition: Partition,
     value: Value,
     path_excluded: Vec<String>,
     index_excluded: bool,
 ) -> api::Value {
     api::Value {
         meaning: 0,
-        exclude_from_indexes: match value.to_owned() {
-            Value::OptionValue(val) => match val {
-                Some(v) => match *v {
-                    Value::ArrayValue(_) => false,
-                    _ => index_excluded,
-                },
-                None => index_excluded,
+        exclude_from_indexes: match &value {
+            Value::OptionValue(Some(v)) => match **v {
+                Value::ArrayValue(_) => false,
+                _ => index_excluded,
             },
             Value::ArrayValue(_) => false,
             _ => index_excluded,
         },
-        value_type: Some(convert_value_type(project_name, value, path_excluded, index_excluded)),
+        value_type: Some(convert_value_type(partition, value, path_excluded, index_excluded)),
     }
 }
 
 fn convert_value_type(
-    project_name: &str,
+    partition: Partition,
     value: Value,
     path_excluded: Vec<String>,
     index_excluded: bool,
 ) -> api::value::ValueType {
     match value {
         Value::OptionValue(val) => match val {
-            Some(v) => convert_value_type(project_name, *v, path_excluded, index_excluded),
+            Some(v) => convert_value_type(partition, *v, path_excluded, index_excluded),
             None => api::value::ValueType::NullValue(0),
         },
         Value::BooleanValue(val) => api::value::ValueType::BooleanValue(val),
         Value::IntegerValue(val) => api::value::ValueType::IntegerValue(val),
         Value::DoubleValue(val) => api::value::ValueType::DoubleValue(val),
-        Value::TimestampValue(val) => {
-            api::value::ValueType::TimestampValue(prost_types::Timestamp {
-                seconds: val.and_utc().timestamp(),
-                nanos: val.and_utc().timestamp_subsec_nanos() as i32,
-            })
-        }
-        Value::KeyValue(key) => api::value::ValueType::KeyValue(convert_key(project_name, &key)),
+        Value::TimestampValue(val) => api::value::ValueType::TimestampValue(convert_timestamp(val)),
+        Value::KeyValue(key) => api::value::ValueType::KeyValue(convert_key(partition, &key)),
         Value::StringValue(val) => api::value::ValueType::StringValue(val),
         Value::BlobValue(val) => api::value::ValueType::BlobValue(val),
         Value::GeoPointValue(latitude, longitude) => {
@@ -652,7 +1534,7 @@ fn convert_value_type(
                         (
                             k.to_owned(),
                             convert_value(
-                                project_name,
+                                partition,
                                 v,
                                 new_list_excluded.to_vec(),
                                 check_exclude_from_indexes(new_list_excluded.to_vec()),
@@ -666,7 +1548,7 @@ fn convert_value_type(
             values: values
                 .into_iter()
                 .map(|value| {
-                    convert_value(project_name, value, path_excluded.to_vec(), index_excluded)
+                    convert_value(partition, value, path_excluded.to_vec(), index_excluded)
                 })
                 .collect(),
         }),
@@ -675,13 +1557,12 @@ fn convert_value_type(
 
 fn get_exclude_from_indexes(list_excluded: Vec<String>, property: String) -> Vec<String> {
     list_excluded
-        .to_vec()
         .into_iter()
         .filter_map(|element| {
             element
                 .split_once(".")
                 .or(Some((element.as_str(), "")))
-                .filter(|(first, _)| first.to_string() == property)
+                .filter(|(first, _)| *first == property)
                 .map(|(_, rest)| rest.to_string())
         })
         .collect::<Vec<String>>()
@@ -695,7 +1576,7 @@ fn check_exclude_from_indexes(list_excluded: Vec<String>) -> bool {
 }
 
 pub(crate) fn convert_filter(
-    project_name: &str,
+    partition: Partition,
     filters: Vec<Filter>,
     composite_filter: CompositeFilter,
 ) -> Option<api::Filter> {
@@ -728,7 +1609,7 @@ pub(crate) fn convert_filter(
                     filter_type: Some(FilterType::PropertyFilter(api::PropertyFilter {
                         op: op as i32,
                         property: Some(api::PropertyReference { name }),
-                        value: Some(convert_value(project_name, value, vec![], false)),
+                        value: Some(convert_value(partition, value, vec![], false)),
                     })),
                 }
             })
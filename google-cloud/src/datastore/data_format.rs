@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::SecondsFormat;
+use serde::de::value::{MapDeserializer, SeqDeserializer, StringDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize};
+
+use crate::datastore::{Key, KeyID, Value};
+use crate::error::ConvertError;
+
+/// The name of the newtype keys (de)serialize through, for this format to store them as keys.
+pub(crate) const KEY_TOKEN: &str = "$google_cloud::datastore::Key";
+
+/// Converts any `Serialize` type into a value (behind the `serde` feature),
+/// for the types the derive macros cannot handle.
+///
+/// Structs and maps become entities (maps keyed by strings, integers, booleans or unit
+/// variants, which name the properties), sequences and tuples become arrays,
+/// `None` and units become nulls, unit variants become strings and the other variants
+/// become entities with a single property named after them, as in JSON.
+/// Keys stay keys, other types are converted according to their serde representation.
+///
+/// ```
+/// # use google_cloud::datastore::{self, Value};
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct User {
+///     name: String,
+///     #[serde(rename = "emailAddress")]
+///     email: Option<String>,
+/// }
+///
+/// let user = User { name: String::from("john"), email: None };
+/// let value = datastore::to_value(&user).unwrap();
+/// assert_eq!(value.at_path("emailAddress").unwrap(), &Value::OptionValue(None));
+/// assert_eq!(datastore::from_value::<User>(value).unwrap(), user);
+/// ```
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, ConvertError> {
+    value.serialize(ValueSerializer)
+}
+
+/// Converts a value into any `Deserialize` type (behind the `serde` feature),
+/// the reverse of `to_value`.
+///
+/// Integers convert to any numeric type they fit in, timestamps to RFC 3339 strings
+/// and blobs to byte buffers.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, ConvertError> {
+    T::deserialize(ValueDeserializer(value))
+}
+
+impl ser::Error for ConvertError {
+    fn custom<T: fmt::Display>(message: T) -> ConvertError {
+        ConvertError::Serde(message.to_string())
+    }
+}
+
+impl de::Error for ConvertError {
+    fn custom<T: fmt::Display>(message: T) -> ConvertError {
+        ConvertError::Serde(message.to_string())
+    }
+}
+
+fn out_of_range(value: impl fmt::Display) -> ConvertError {
+    ConvertError::OutOfRange(format!("{} as a 64 bits integer", value))
+}
+
+fn invalid_key() -> ConvertError {
+    ConvertError::InvalidKey(String::from("malformed serialized key"))
+}
+
+/// Builds a key back from the properties it serialized into.
+fn key_from_properties(mut properties: HashMap<String, Value>) -> Result<Key, ConvertError> {
+    let kind = match properties.remove("kind") {
+        Some(Value::StringValue(kind)) => kind,
+        _ => return Err(invalid_key()),
+    };
+    let id = match (properties.remove("id"), properties.remove("name")) {
+        (Some(Value::IntegerValue(id)), None) => KeyID::IntID(id),
+        (None, Some(Value::StringValue(name))) => KeyID::StringID(name),
+        (None, None) => KeyID::Incomplete,
+        _ => return Err(invalid_key()),
+    };
+    let namespace = match properties.remove("namespace") {
+        Some(Value::StringValue(namespace)) => Some(namespace),
+        None => None,
+        _ => return Err(invalid_key()),
+    };
+    let parent = match properties.remove("parent") {
+        Some(Value::KeyValue(parent)) => Some(Box::new(parent)),
+        None => None,
+        _ => return Err(invalid_key()),
+    };
+    Ok(Key { kind, id, is_new: false, parent, namespace })
+}
+
+/// The properties of a key, as its `Deserialize` implementation expects them.
+fn key_properties(key: Key) -> HashMap<String, Value> {
+    let mut properties = HashMap::from([(String::from("kind"), Value::StringValue(key.kind))]);
+    match key.id {
+        KeyID::IntID(id) => properties.insert(String::from("id"), Value::IntegerValue(id)),
+        KeyID::StringID(name) => properties.insert(String::from("name"), Value::StringValue(name)),
+        KeyID::Incomplete => None,
+    };
+    if let Some(namespace) = key.namespace {
+        properties.insert(String::from("namespace"), Value::StringValue(namespace));
+    }
+    if let Some(parent) = key.parent {
+        properties.insert(String::from("parent"), Value::KeyValue(*parent));
+    }
+    properties
+}
+
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = ConvertError;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeProperties;
+    type SerializeStruct = SerializeProperties;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn serialize_bool(self, value: bool) -> Result<Value, ConvertError> {
+        Ok(Value::BooleanValue(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value))
+    }
+
+    fn serialize_i128(self, value: i128) -> Result<Value, ConvertError> {
+        i64::try_from(value).map(Value::IntegerValue).map_err(|_| out_of_range(value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value, ConvertError> {
+        Ok(Value::IntegerValue(value.into()))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value, ConvertError> {
+        i64::try_from(value).map(Value::IntegerValue).map_err(|_| out_of_range(value))
+    }
+
+    fn serialize_u128(self, value: u128) -> Result<Value, ConvertError> {
+        i64::try_from(value).map(Value::IntegerValue).map_err(|_| out_of_range(value))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value, ConvertError> {
+        Ok(Value::DoubleValue(value.into()))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value, ConvertError> {
+        Ok(Value::DoubleValue(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value, ConvertError> {
+        Ok(Value::StringValue(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value, ConvertError> {
+        Ok(Value::StringValue(String::from(value)))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value, ConvertError> {
+        Ok(Value::BlobValue(value.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value, ConvertError> {
+        Ok(Value::OptionValue(None))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, ConvertError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, ConvertError> {
+        Ok(Value::OptionValue(None))
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Value, ConvertError> {
+        Ok(Value::OptionValue(None))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<Value, ConvertError> {
+        Ok(Value::StringValue(String::from(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value, ConvertError> {
+        match (name, value.serialize(self)?) {
+            (KEY_TOKEN, Value::EntityValue(properties)) => {
+                key_from_properties(properties).map(Value::KeyValue)
+            }
+            (KEY_TOKEN, _) => Err(invalid_key()),
+            (_, value) => Ok(value),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, ConvertError> {
+        let properties = HashMap::from([(String::from(variant), value.serialize(self)?)]);
+        Ok(Value::EntityValue(properties))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, ConvertError> {
+        Ok(SerializeArray { values: Vec::with_capacity(len.unwrap_or_default()) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, ConvertError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeTupleVariant, ConvertError> {
+        Ok(SerializeTupleVariant { variant, values: Vec::with_capacity(len) })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeProperties, ConvertError> {
+        let properties = HashMap::with_capacity(len.unwrap_or_default());
+        Ok(SerializeProperties { properties, name: None })
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<SerializeProperties, ConvertError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeStructVariant, ConvertError> {
+        Ok(SerializeStructVariant { variant, properties: HashMap::with_capacity(len) })
+    }
+}
+
+struct SerializeArray {
+    values: Vec<Value>,
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.values.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        Ok(Value::ArrayValue(self.values))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeTupleVariant {
+    variant: &'static str,
+    values: Vec<Value>,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        self.values.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        let properties =
+            HashMap::from([(String::from(self.variant), Value::ArrayValue(self.values))]);
+        Ok(Value::EntityValue(properties))
+    }
+}
+
+struct SerializeProperties {
+    properties: HashMap<String, Value>,
+    /// The name of the property whose value comes next, for maps.
+    name: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeProperties {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ConvertError> {
+        //? Property names are strings, but maps keyed by integers or booleans are common enough.
+        let name = match key.serialize(ValueSerializer)? {
+            Value::StringValue(name) => name,
+            Value::IntegerValue(name) => name.to_string(),
+            Value::BooleanValue(name) => name.to_string(),
+            other => {
+                return Err(ConvertError::UnexpectedPropertyType {
+                    expected: String::from("string"),
+                    got: String::from(other.type_name()),
+                })
+            }
+        };
+        self.name = Some(name);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ConvertError> {
+        let name = self.name.take().expect("`serialize_key` is called before `serialize_value`");
+        self.properties.insert(name, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        Ok(Value::EntityValue(self.properties))
+    }
+}
+
+impl ser::SerializeStruct for SerializeProperties {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.properties.insert(String::from(name), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        Ok(Value::EntityValue(self.properties))
+    }
+}
+
+struct SerializeStructVariant {
+    variant: &'static str,
+    properties: HashMap<String, Value>,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = Value;
+    type Error = ConvertError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), ConvertError> {
+        self.properties.insert(String::from(name), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, ConvertError> {
+        let properties =
+            HashMap::from([(String::from(self.variant), Value::EntityValue(self.properties))]);
+        Ok(Value::EntityValue(properties))
+    }
+}
+
+struct ValueDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, ConvertError> for ValueDeserializer {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> ValueDeserializer {
+        self
+    }
+}
+
+fn visit_properties<'de, V: Visitor<'de>>(
+    properties: HashMap<String, Value>,
+    visitor: V,
+) -> Result<V::Value, ConvertError> {
+    let mut properties = MapDeserializer::new(
+        properties
+            .into_iter()
+            .map(|(name, value)| (PropertyNameDeserializer(name), ValueDeserializer(value))),
+    );
+    let value = visitor.visit_map(&mut properties)?;
+    properties.end()?;
+    Ok(value)
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::OptionValue(None) => visitor.visit_unit(),
+            Value::OptionValue(Some(value)) => ValueDeserializer(*value).deserialize_any(visitor),
+            Value::BooleanValue(value) => visitor.visit_bool(value),
+            Value::IntegerValue(value) => visitor.visit_i64(value),
+            Value::DoubleValue(value) => visitor.visit_f64(value),
+            Value::TimestampValue(value) => {
+                visitor.visit_string(value.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            Value::KeyValue(key) => visit_properties(key_properties(key), visitor),
+            Value::StringValue(value) => visitor.visit_string(value),
+            Value::BlobValue(value) => visitor.visit_byte_buf(value),
+            Value::GeoPointValue(latitude, longitude) => {
+                let properties = HashMap::from([
+                    (String::from("latitude"), Value::DoubleValue(latitude)),
+                    (String::from("longitude"), Value::DoubleValue(longitude)),
+                ]);
+                visit_properties(properties, visitor)
+            }
+            Value::EntityValue(properties) => visit_properties(properties, visitor),
+            Value::ArrayValue(values) => {
+                let mut values = SeqDeserializer::new(values.into_iter().map(ValueDeserializer));
+                let value = visitor.visit_seq(&mut values)?;
+                values.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::OptionValue(None) => visitor.visit_none(),
+            Value::OptionValue(Some(value)) => visitor.visit_some(ValueDeserializer(*value)),
+            value => visitor.visit_some(ValueDeserializer(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        match (name, self.0) {
+            (KEY_TOKEN, Value::KeyValue(key)) => visitor
+                .visit_newtype_struct(ValueDeserializer(Value::EntityValue(key_properties(key)))),
+            (_, value) => visitor.visit_newtype_struct(ValueDeserializer(value)),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        match self.0 {
+            Value::OptionValue(Some(value)) => {
+                ValueDeserializer(*value).deserialize_enum(name, variants, visitor)
+            }
+            Value::StringValue(variant) => visitor.visit_enum(StringDeserializer::new(variant)),
+            Value::EntityValue(properties) if properties.len() == 1 => {
+                let (variant, value) = properties.into_iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            other => Err(ConvertError::UnexpectedPropertyType {
+                expected: String::from("string or entity with a single property"),
+                got: String::from(other.type_name()),
+            }),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// The name of a property, parsed back into the integer or boolean it was serialized from
+/// when a map keyed by them asks for one.
+struct PropertyNameDeserializer(String);
+
+impl<'de> IntoDeserializer<'de, ConvertError> for PropertyNameDeserializer {
+    type Deserializer = PropertyNameDeserializer;
+
+    fn into_deserializer(self) -> PropertyNameDeserializer {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed_name {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::invalid_type(de::Unexpected::Str(&self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for PropertyNameDeserializer {
+    type Error = ConvertError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        visitor.visit_string(self.0)
+    }
+
+    deserialize_parsed_name!(
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ConvertError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        visitor.visit_enum(StringDeserializer::new(self.0))
+    }
+
+    serde::forward_to_deserialize_any! {
+        f32 f64 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+/// A variant other than a unit one: an entity with a single property named after it.
+struct EnumDeserializer {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = ConvertError;
+    type Variant = ValueDeserializer;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, ValueDeserializer), ConvertError> {
+        let variant = seed.deserialize(StringDeserializer::new(self.variant))?;
+        Ok((variant, ValueDeserializer(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueDeserializer {
+    type Error = ConvertError;
+
+    fn unit_variant(self) -> Result<(), ConvertError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<S::Value, ConvertError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ConvertError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
@@ -0,0 +1,134 @@
+use crate::datastore::{FromValue, IntoValue, Value};
+use crate::error::ConvertError;
+
+/// Duration types storable in Datastore, as an integer count of some unit.
+///
+/// Durations are stored in microseconds by default; the `Nanos`, `Millis` and `Secs`
+/// adapters store them in other units.
+/// Durations too long for the unit saturate at `i64::MIN` or `i64::MAX`, and the
+/// sub-unit parts are truncated.
+pub trait DurationValue: Sized {
+    /// The name of the type, for the conversion errors.
+    const TYPE_NAME: &'static str;
+
+    /// Gets the duration in nanoseconds.
+    fn to_nanos(&self) -> i128;
+
+    /// Builds the duration from nanoseconds, if it fits the type.
+    fn from_nanos(nanos: i128) -> Option<Self>;
+}
+
+impl DurationValue for std::time::Duration {
+    const TYPE_NAME: &'static str = "std::time::Duration";
+
+    fn to_nanos(&self) -> i128 {
+        self.as_nanos() as i128
+    }
+
+    fn from_nanos(nanos: i128) -> Option<std::time::Duration> {
+        let secs = u64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+        Some(std::time::Duration::new(secs, nanos.rem_euclid(1_000_000_000) as u32))
+    }
+}
+
+impl DurationValue for chrono::Duration {
+    const TYPE_NAME: &'static str = "chrono::Duration";
+
+    fn to_nanos(&self) -> i128 {
+        i128::from(self.num_seconds()) * 1_000_000_000 + i128::from(self.subsec_nanos())
+    }
+
+    fn from_nanos(nanos: i128) -> Option<chrono::Duration> {
+        let secs = i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+        chrono::Duration::new(secs, nanos.rem_euclid(1_000_000_000) as u32)
+    }
+}
+
+fn duration_into_value<D: DurationValue>(duration: &D, unit: i128) -> Value {
+    let count = duration.to_nanos() / unit;
+    Value::IntegerValue(count.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64)
+}
+
+fn duration_from_value<D: DurationValue>(value: Value, unit: i128) -> Result<D, ConvertError> {
+    let count = i64::from_value(value)?;
+    D::from_nanos(i128::from(count) * unit).ok_or_else(|| {
+        ConvertError::OutOfRange(format!(
+            "{} nanoseconds as a `{}`",
+            i128::from(count) * unit,
+            D::TYPE_NAME
+        ))
+    })
+}
+
+macro_rules! duration_unit {
+    ($(#[$attr:meta])* $name:ident, $unit:expr) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name<D>(pub D);
+
+        impl<D> $name<D> {
+            /// Unwraps the duration.
+            pub fn into_inner(self) -> D {
+                self.0
+            }
+        }
+
+        impl<D: DurationValue> IntoValue for $name<D> {
+            fn into_value(self) -> Value {
+                duration_into_value(&self.0, $unit)
+            }
+        }
+
+        impl<D: DurationValue> FromValue for $name<D> {
+            fn from_value(value: Value) -> Result<$name<D>, ConvertError> {
+                duration_from_value(value, $unit).map($name)
+            }
+        }
+    };
+}
+
+duration_unit!(
+    /// Duration adapter storing the duration in nanoseconds (up to 292 years).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{IntoValue, Nanos, Value};
+    /// # use std::time::Duration;
+    /// assert_eq!(Nanos(Duration::from_micros(3)).into_value(), Value::IntegerValue(3000));
+    /// ```
+    Nanos,
+    1
+);
+duration_unit!(
+    /// Duration adapter storing the duration in milliseconds.
+    Millis,
+    1_000_000
+);
+duration_unit!(
+    /// Duration adapter storing the duration in seconds.
+    Secs,
+    1_000_000_000
+);
+
+impl IntoValue for std::time::Duration {
+    fn into_value(self) -> Value {
+        duration_into_value(&self, 1_000)
+    }
+}
+
+impl FromValue for std::time::Duration {
+    fn from_value(value: Value) -> Result<std::time::Duration, ConvertError> {
+        duration_from_value(value, 1_000)
+    }
+}
+
+impl IntoValue for chrono::Duration {
+    fn into_value(self) -> Value {
+        duration_into_value(&self, 1_000)
+    }
+}
+
+impl FromValue for chrono::Duration {
+    fn from_value(value: Value) -> Result<chrono::Duration, ConvertError> {
+        duration_from_value(value, 1_000)
+    }
+}
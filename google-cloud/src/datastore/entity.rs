@@ -1,5 +1,5 @@
 use crate::datastore::api;
-use crate::datastore::{IntoValue, Key, Value};
+use crate::datastore::{FromValue, Key, TryIntoValue, Value};
 use crate::error::ConvertError;
 
 /// Represents a Datastore entity.
@@ -11,8 +11,9 @@ pub struct Entity {
 
 impl Entity {
     /// Constructs a new Entity.
-    pub fn new(key: Key, value: impl IntoValue) -> Result<Entity, ConvertError> {
-        let properties = value.into_value();
+    /// Fails if the value does not convert to a `Value::EntityValue`.
+    pub fn new(key: Key, value: impl TryIntoValue) -> Result<Entity, ConvertError> {
+        let properties = value.try_into_value()?;
         match properties {
             Value::EntityValue(_) => Ok(Entity { key, properties }),
             _ => Err(ConvertError::UnexpectedPropertyType {
@@ -46,6 +47,25 @@ impl Entity {
     pub fn properties_mut(&mut self) -> &mut Value {
         &mut self.properties
     }
+
+    /// Gets and converts a nested property from its path, such as `customer.address.city`
+    /// or `items[0].sku` (see `Value::at_path`).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{Entity, IntoValue, Key};
+    /// # use std::collections::HashMap;
+    /// let address = HashMap::from([("city".to_string(), "Lyon".into_value())]);
+    /// let customer = HashMap::from([("address".to_string(), address.into_value())]);
+    /// let properties = HashMap::from([("customer".to_string(), customer.into_value())]);
+    /// let entity = Entity::new(Key::new("orders").id(1), properties).unwrap();
+    ///
+    /// let city: String = entity.get_path("customer.address.city").unwrap();
+    /// assert_eq!(city, "Lyon");
+    /// assert!(entity.get_path::<String>("customer.phone").is_err());
+    /// ```
+    pub fn get_path<T: FromValue>(&self, path: &str) -> Result<T, ConvertError> {
+        self.properties.get_path(path)
+    }
 }
 
 /// Trait for converting a type to a Datastore entity (key + value).
@@ -53,6 +73,40 @@ pub trait IntoEntity {
     /// Attempts to convert the type to a Datastore entity.
     /// Fails if the top level value is not a `Value::EntityValue`.
     fn into_entity(self) -> Result<Entity, ConvertError>;
+
+    /// Routes the entity to a namespace chosen at runtime (a tenant for instance),
+    /// replacing the namespace of its key and of the key's ancestors.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{IntoEntity, IntoValue, Key};
+    /// # use std::collections::HashMap;
+    /// let user = (Key::new("users").id(1), HashMap::from([("name".to_string(), "john".into_value())]));
+    /// let entity = user.in_namespace("tenant-a").into_entity().unwrap();
+    /// assert_eq!(entity.key().get_namespace(), Some("tenant-a"));
+    /// ```
+    fn in_namespace(self, namespace: impl Into<String>) -> InNamespace<Self>
+    where
+        Self: Sized,
+    {
+        InNamespace { namespace: namespace.into(), entity: self }
+    }
+}
+
+/// An entity routed to a namespace, see `IntoEntity::in_namespace`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InNamespace<E> {
+    namespace: String,
+    entity: E,
+}
+
+impl<E> IntoEntity for InNamespace<E>
+where
+    E: IntoEntity,
+{
+    fn into_entity(self) -> Result<Entity, ConvertError> {
+        let Entity { key, properties } = self.entity.into_entity()?;
+        Ok(Entity { key: key.in_namespace(Some(self.namespace)), properties })
+    }
 }
 
 impl IntoEntity for Entity {
@@ -63,7 +117,7 @@ impl IntoEntity for Entity {
 
 impl<V> IntoEntity for (Key, V)
 where
-    V: IntoValue,
+    V: TryIntoValue,
 {
     fn into_entity(self) -> Result<Entity, ConvertError> {
         let (k, v) = self;
@@ -72,16 +126,17 @@ where
 }
 
 impl From<api::Entity> for Entity {
-    fn from(entity: api::Entity) -> Entity {
-        let key = Key::from(entity.key.unwrap());
-        let properties = entity.properties;
-
-        let properties = properties
-            .into_iter()
-            .map(|(k, v)| (k, Value::from(v.value_type.unwrap())))
-            .collect();
-        let properties = Value::EntityValue(properties);
+    fn from(mut entity: api::Entity) -> Entity {
+        let key = Key::from(entity.key.take().unwrap());
+        let properties = entity_properties(entity);
 
         Entity { key, properties }
     }
 }
+
+/// Converts the properties of an entity, leaving its key out.
+pub(crate) fn entity_properties(entity: api::Entity) -> Value {
+    let properties = entity.properties.into_iter().map(|(k, v)| (k, Value::from(v))).collect();
+
+    Value::EntityValue(properties)
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use crate::datastore::api;
+use crate::datastore::{convert_value, IntoValue, Partition, Value};
+
+/// A parameter bound to a GQL query.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GqlBinding {
+    Value(Value),
+    Cursor(Vec<u8>),
+}
+
+/// Represents a GQL query, with its bindings.
+///
+/// ```
+/// # use google_cloud::datastore::GqlQuery;
+/// let query = GqlQuery::new("SELECT * FROM users WHERE age > @age AND firstname = @1")
+///     .bind("age", 10)
+///     .bind_positional("john");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct GqlQuery {
+    pub(crate) query_string: String,
+    pub(crate) allow_literals: bool,
+    pub(crate) named_bindings: HashMap<String, GqlBinding>,
+    pub(crate) positional_bindings: Vec<GqlBinding>,
+    pub(crate) namespace: Option<String>,
+}
+
+impl GqlQuery {
+    /// Construct a new GQL query from its query string.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// let query = GqlQuery::new("SELECT * FROM users");
+    /// ```
+    pub fn new(query_string: impl Into<String>) -> GqlQuery {
+        GqlQuery {
+            query_string: query_string.into(),
+            allow_literals: false,
+            named_bindings: HashMap::new(),
+            positional_bindings: Vec::new(),
+            namespace: None,
+        }
+    }
+
+    /// Allow literal values in the query string, instead of bindings only.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// let query = GqlQuery::new("SELECT * FROM users WHERE age > 10").allow_literals();
+    /// ```
+    pub fn allow_literals(mut self) -> GqlQuery {
+        self.allow_literals = true;
+        self
+    }
+
+    /// Bind a value to a named parameter (`@name` in the query string).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// let query = GqlQuery::new("SELECT * FROM users WHERE age > @age").bind("age", 10);
+    /// ```
+    pub fn bind(mut self, name: impl Into<String>, value: impl IntoValue) -> GqlQuery {
+        self.named_bindings.insert(name.into(), GqlBinding::Value(value.into_value()));
+        self
+    }
+
+    /// Bind a cursor to a named parameter (`@name` in the query string).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// # let cursor = Vec::new();
+    /// let query = GqlQuery::new("SELECT * FROM users OFFSET @start").bind_cursor("start", cursor);
+    /// ```
+    pub fn bind_cursor(mut self, name: impl Into<String>, cursor: Vec<u8>) -> GqlQuery {
+        self.named_bindings.insert(name.into(), GqlBinding::Cursor(cursor));
+        self
+    }
+
+    /// Bind a value to the next positional parameter (`@1`, `@2`, ... in the query string).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// let query = GqlQuery::new("SELECT * FROM users WHERE firstname = @1").bind_positional("john");
+    /// ```
+    pub fn bind_positional(mut self, value: impl IntoValue) -> GqlQuery {
+        self.positional_bindings.push(GqlBinding::Value(value.into_value()));
+        self
+    }
+
+    /// Bind a cursor to the next positional parameter (`@1`, `@2`, ... in the query string).
+    pub fn bind_positional_cursor(mut self, cursor: Vec<u8>) -> GqlQuery {
+        self.positional_bindings.push(GqlBinding::Cursor(cursor));
+        self
+    }
+
+    /// Associates the query with a namespace.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::GqlQuery;
+    /// let query = GqlQuery::new("SELECT * FROM users").namespace("dev");
+    /// ```
+    pub fn namespace(mut self, namespace: impl Into<String>) -> GqlQuery {
+        self.namespace = Some(namespace.into());
+        self
+    }
+}
+
+pub(crate) fn convert_gql_query(partition: Partition, query: GqlQuery) -> api::GqlQuery {
+    let convert = |binding: GqlBinding| {
+        use api::gql_query_parameter::ParameterType;
+        let parameter_type = match binding {
+            GqlBinding::Value(value) => {
+                ParameterType::Value(convert_value(partition, value, Vec::new(), false))
+            }
+            GqlBinding::Cursor(cursor) => ParameterType::Cursor(cursor),
+        };
+        api::GqlQueryParameter { parameter_type: Some(parameter_type) }
+    };
+
+    api::GqlQuery {
+        query_string: query.query_string,
+        allow_literals: query.allow_literals,
+        named_bindings: query
+            .named_bindings
+            .into_iter()
+            .map(|(name, binding)| (name, convert(binding)))
+            .collect(),
+        positional_bindings: query.positional_bindings.into_iter().map(convert).collect(),
+    }
+}
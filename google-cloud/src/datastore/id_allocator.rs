@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::datastore::Key;
+
+/// Allocates the IDs of incomplete keys on the client side, instead of letting the store do it.
+///
+/// Meant for tests and emulator runs, whose snapshots need predictable IDs
+/// (see `ClientBuilder::id_allocator`).
+pub trait IdAllocator: fmt::Debug + Send + Sync {
+    /// Returns the ID to give to the entity with this incomplete key.
+    fn allocate(&self, key: &Key) -> i64;
+}
+
+/// Allocates sequential IDs for each kind, starting from one.
+///
+/// ```
+/// # use google_cloud::datastore::{IdAllocator, Key, SequentialIdAllocator};
+/// let allocator = SequentialIdAllocator::new();
+/// assert_eq!(allocator.allocate(&Key::new("users")), 1);
+/// assert_eq!(allocator.allocate(&Key::new("users")), 2);
+/// assert_eq!(allocator.allocate(&Key::new("posts")), 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct SequentialIdAllocator {
+    start: i64,
+    next: Mutex<HashMap<String, i64>>,
+}
+
+impl SequentialIdAllocator {
+    /// Creates an allocator starting from one for every kind.
+    pub fn new() -> SequentialIdAllocator {
+        SequentialIdAllocator::starting_at(1)
+    }
+
+    /// Creates an allocator starting from `start` for every kind.
+    pub fn starting_at(start: i64) -> SequentialIdAllocator {
+        SequentialIdAllocator { start, next: Mutex::new(HashMap::new()) }
+    }
+
+    /// Starts over, as if no ID had been allocated yet.
+    pub fn reset(&self) {
+        self.next.lock().unwrap().clear();
+    }
+}
+
+impl IdAllocator for SequentialIdAllocator {
+    fn allocate(&self, key: &Key) -> i64 {
+        let mut next = self.next.lock().unwrap();
+        let id = next.entry(key.get_kind().to_string()).or_insert(self.start);
+        let allocated = *id;
+        *id += 1;
+        allocated
+    }
+}
@@ -19,15 +19,15 @@ use std::{collections::HashMap, env, fs::File, path::Path};
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct IndexExcluded {
-    ///
+    /// The excluded properties, by kind.
     pub kind: HashMap<String, PropertyExcluded>,
 }
 
-///
+/// The properties of a kind that are excluded from indexes.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct PropertyExcluded {
-    ///
+    /// Property paths, nested ones are separated by dots (`address.street`).
     pub property: Vec<String>,
 }
 
@@ -49,7 +49,8 @@ impl IndexExcluded {
         Ok(deserialized_yaml)
     }
 
-    ///
+    /// Returns the excluded paths below `property` for the given kind.
+    /// An empty path means the property itself is excluded.
     pub(crate) fn ckeck_value(self, kind: String, property: String) -> Vec<String> {
         let property_excluded = match self.kind.get(&kind) {
             Some(p) => p.to_owned(),
@@ -63,7 +64,7 @@ impl IndexExcluded {
                 element
                     .split_once(".")
                     .or(Some((element.as_str(), "")))
-                    .filter(|(first, _)| first.to_string() == property)
+                    .filter(|(first, _)| *first == property)
                     .map(|(_, rest)| rest.to_string())
             })
             .collect::<Vec<String>>()
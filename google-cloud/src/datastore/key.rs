@@ -1,11 +1,18 @@
 use std::borrow::Borrow;
 
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use prost::Message;
+
 use crate::datastore::api;
 use crate::datastore::api::key::path_element::IdType;
+use crate::error::ConvertError;
 
 /// Represents a key's ID.
 ///
 /// It can either be a integer key, a string/named key or an incomplete key.
+///
+/// With the `serde` feature, IDs are (de)serialized as integers, strings or `null` (incomplete).
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum KeyID {
     /// A string/named key ID.
@@ -27,10 +34,7 @@ impl KeyID {
     /// assert!(!id2.is_incomplete());
     /// ```
     pub fn is_incomplete(&self) -> bool {
-        match self {
-            KeyID::Incomplete => true,
-            _ => false,
-        }
+        matches!(self, KeyID::Incomplete)
     }
 }
 
@@ -67,6 +71,11 @@ impl From<IdType> for KeyID {
 /// # use google_cloud::datastore::Key;
 /// let key = Key::new("kind").id("entity-name");
 /// ```
+///
+/// With the `serde` feature, keys are (de)serialized as maps of their kind, their ID
+/// (`id` for an integer ID, `name` for a string one, none for an incomplete key),
+/// their namespace and their parent, the last two being left out when unset:
+/// `{"kind": "users", "id": 1, "namespace": "tenant-a", "parent": {"kind": "orgs", "name": "acme"}}`.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Key {
     pub(crate) kind: String,
@@ -95,6 +104,177 @@ impl Key {
         }
     }
 
+    /// Create a new incomplete key, to be completed fluently.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Key;
+    /// let key = Key::kind("Parent").id(1).child("Child").name("abc").namespace("tenant-a");
+    /// assert_eq!(key.get_parent(), Some(&Key::new("Parent").id(1)));
+    /// ```
+    pub fn kind(kind: impl Into<String>) -> Key {
+        Key::new(kind)
+    }
+
+    /// Create a key from its path, from the root ancestor to the entity itself.
+    ///
+    /// Only the last element may be incomplete.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{Key, KeyID};
+    /// let key = Key::from_path(&[("Parent", KeyID::from(1)), ("Child", KeyID::from("abc"))]).unwrap();
+    /// assert_eq!(key, Key::kind("Parent").id(1).child("Child").name("abc"));
+    ///
+    /// assert!(Key::from_path(&[("Parent", KeyID::Incomplete), ("Child", KeyID::from(1))]).is_err());
+    /// ```
+    pub fn from_path<K>(path: &[(K, KeyID)]) -> Result<Key, ConvertError>
+    where
+        K: AsRef<str>,
+    {
+        let (leaf, ancestors) = path
+            .split_last()
+            .ok_or_else(|| ConvertError::InvalidKey(String::from("the key path is empty")))?;
+
+        let parent = ancestors.iter().fold(None, |parent: Option<Key>, (kind, id)| {
+            let key = Key::new(kind.as_ref()).id(id.clone());
+            Some(match parent {
+                Some(parent) => key.parent(parent),
+                None => key,
+            })
+        });
+        let key = Key::new(leaf.0.as_ref()).id(leaf.1.clone());
+        let key = match parent {
+            Some(parent) => key.parent(parent),
+            None => key,
+        };
+        key.validate()?;
+
+        Ok(key)
+    }
+
+    /// Create an incomplete child key of this key, in the same namespace.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Key;
+    /// let parent = Key::new("Parent").namespace("dev").id(1);
+    /// let child = parent.clone().child("Child").id(2);
+    /// assert_eq!(child.get_parent(), Some(&parent));
+    /// assert_eq!(child.get_namespace(), Some("dev"));
+    /// ```
+    pub fn child(self, kind: impl Into<String>) -> Key {
+        let namespace = self.namespace.clone();
+        Key { namespace, ..Key::new(kind).parent(self) }
+    }
+
+    /// Attach a string ID (a name) to the key.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{Key, KeyID};
+    /// let key = Key::new("kind").name("entity-name");
+    /// assert_eq!(key.get_id(), &KeyID::StringID(String::from("entity-name")));
+    /// ```
+    pub fn name(self, name: impl Into<String>) -> Key {
+        self.id(KeyID::StringID(name.into()))
+    }
+
+    /// Check that the key is valid: only the key itself may be incomplete, not its ancestors.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Key;
+    /// assert!(Key::kind("Parent").id(1).child("Child").validate().is_ok());
+    /// assert!(Key::kind("Parent").child("Child").id(1).validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), ConvertError> {
+        let mut ancestor = self.get_parent();
+        while let Some(key) = ancestor {
+            if key.is_incomplete() {
+                return Err(ConvertError::InvalidKey(format!(
+                    "the ancestor of kind `{}` is incomplete",
+                    key.get_kind()
+                )));
+            }
+            ancestor = key.get_parent();
+        }
+
+        Ok(())
+    }
+
+    /// Encode the key as a URL-safe string, in the legacy `Reference` format of the
+    /// App Engine SDKs (readable by `Key.from_legacy_urlsafe` in Python, for instance).
+    ///
+    /// The format includes the project the key belongs to.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Key;
+    /// let key = Key::kind("Parent").namespace("dev").id(1).child("Child").name("abc");
+    /// let encoded = key.to_urlsafe("my-project");
+    /// assert_eq!(Key::from_urlsafe(&encoded).unwrap(), key);
+    /// ```
+    pub fn to_urlsafe(&self, project_id: &str) -> String {
+        let mut element = Vec::new();
+        let mut current = Some(self);
+        while let Some(key) = current {
+            let (id, name) = match key.get_id() {
+                KeyID::IntID(id) => (Some(*id), None),
+                KeyID::StringID(name) => (None, Some(name.clone())),
+                KeyID::Incomplete => (None, None),
+            };
+            element.push(reference::Element { r#type: key.kind.clone(), id, name });
+            current = key.get_parent();
+        }
+        element.reverse();
+
+        let reference = reference::Reference {
+            app: String::from(project_id),
+            name_space: self.namespace.clone(),
+            path: reference::Path { element },
+        };
+
+        URLSAFE.encode(reference.encode_to_vec())
+    }
+
+    /// Decode a key encoded as a URL-safe string by `Key::to_urlsafe` or another SDK.
+    ///
+    /// The project the key belongs to is left out, see `Key::urlsafe_project`.
+    pub fn from_urlsafe(encoded: &str) -> Result<Key, ConvertError> {
+        let reference = decode_reference(encoded)?;
+        let namespace = reference.name_space.filter(|namespace| !namespace.is_empty());
+
+        let key = reference.path.element.into_iter().fold(None, |parent: Option<Key>, element| {
+            let id = match (element.id, element.name) {
+                (Some(id), _) => KeyID::IntID(id),
+                (None, Some(name)) => KeyID::StringID(name),
+                (None, None) => KeyID::Incomplete,
+            };
+            let key = Key { namespace: namespace.clone(), ..Key::new(element.r#type).id(id) };
+            Some(match parent {
+                Some(parent) => key.parent(parent),
+                None => key,
+            })
+        });
+        let key =
+            key.ok_or_else(|| ConvertError::InvalidKey(String::from("the key path is empty")))?;
+        key.validate()?;
+
+        Ok(key)
+    }
+
+    /// Get the project of a key encoded as a URL-safe string.
+    ///
+    /// The partition prefix some SDKs add (like `s~`) is removed.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Key;
+    /// let encoded = Key::new("kind").id(10).to_urlsafe("my-project");
+    /// assert_eq!(Key::urlsafe_project(&encoded).unwrap(), "my-project");
+    /// ```
+    pub fn urlsafe_project(encoded: &str) -> Result<String, ConvertError> {
+        let app = decode_reference(encoded)?.app;
+        match app.split_once('~') {
+            Some((_, project)) => Ok(String::from(project)),
+            None => Ok(app),
+        }
+    }
+
     /// Get the key's kind.
     ///
     /// ```
@@ -135,7 +315,7 @@ impl Key {
     ///
     /// ```
     /// # use google_cloud::datastore::Key;
-    /// let key = key.mark_new_entity();
+    /// let key = Key::new("kind").id(10).mark_new_entity();
     /// ```
     pub fn mark_new_entity(mut self) -> Key {
         self.is_new = true;
@@ -200,6 +380,13 @@ impl Key {
         self
     }
 
+    /// Moves the whole path to a namespace, or back to the default namespace.
+    pub(crate) fn in_namespace(mut self, namespace: Option<String>) -> Key {
+        self.parent = self.parent.map(|parent| Box::new(parent.in_namespace(namespace.clone())));
+        self.namespace = namespace;
+        self
+    }
+
     /// Get the key's namespace, if any.
     ///
     /// ```
@@ -210,7 +397,7 @@ impl Key {
     /// assert_eq!(key2.get_namespace(), Some("dev"));
     /// ```
     pub fn get_namespace(&self) -> Option<&str> {
-        self.namespace.as_ref().map(|inner| inner.as_str())
+        self.namespace.as_deref()
     }
 
     /// Is the key incomplete (missing an ID) ?
@@ -254,3 +441,48 @@ impl From<api::Key> for Key {
         key.unwrap()
     }
 }
+
+/// URL-safe base64 without padding, accepting padded inputs too.
+const URLSAFE: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+fn decode_reference(encoded: &str) -> Result<reference::Reference, ConvertError> {
+    let bytes = URLSAFE
+        .decode(encoded.trim())
+        .map_err(|error| ConvertError::InvalidKey(format!("invalid base64: {}", error)))?;
+    reference::Reference::decode(bytes.as_slice())
+        .map_err(|error| ConvertError::InvalidKey(format!("invalid reference: {}", error)))
+}
+
+/// The `Reference` message of the legacy App Engine key format.
+mod reference {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Reference {
+        #[prost(string, required, tag = "13")]
+        pub(super) app: String,
+        #[prost(string, optional, tag = "20")]
+        pub(super) name_space: Option<String>,
+        #[prost(message, required, tag = "14")]
+        pub(super) path: Path,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Path {
+        #[prost(group, repeated, tag = "1")]
+        pub(super) element: Vec<Element>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Element {
+        #[prost(string, required, tag = "2")]
+        pub(super) r#type: String,
+        #[prost(int64, optional, tag = "3")]
+        pub(super) id: Option<i64>,
+        #[prost(string, optional, tag = "4")]
+        pub(super) name: Option<String>,
+    }
+}
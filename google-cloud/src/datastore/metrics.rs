@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tonic::Code;
+
+/// Receives the metrics of every RPC a client sends to Datastore (see `ClientBuilder::metrics_sink`).
+///
+/// It is called inline once each RPC completes, so it should only update counters
+/// or histograms and return quickly.
+pub trait MetricsSink: fmt::Debug + Send + Sync {
+    /// Records the metrics of a completed RPC.
+    fn record(&self, metrics: &RpcMetrics<'_>);
+}
+
+impl<T: MetricsSink + ?Sized> MetricsSink for Arc<T> {
+    fn record(&self, metrics: &RpcMetrics<'_>) {
+        T::record(self, metrics)
+    }
+}
+
+/// The metrics of an RPC sent to Datastore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcMetrics<'a> {
+    /// The name of the RPC (`lookup`, `run_query`, `commit`...).
+    pub operation: &'static str,
+    /// The project the RPC was sent to.
+    pub project: &'a str,
+    /// The kind of the entities involved, that of the first one when they differ
+    /// (empty when unknown, as for GQL queries and transactions).
+    pub kind: &'a str,
+    /// The status code of the RPC, `Code::Ok` if it succeeded.
+    pub code: Code,
+    /// How long the RPC took.
+    pub duration: Duration,
+    /// The attempt number: retries of the same request start at 2.
+    pub attempt: usize,
+    /// The encoded size of the request, in bytes.
+    pub request_bytes: usize,
+    /// The encoded size of the response, in bytes (zero for failed RPCs).
+    pub response_bytes: usize,
+}
+
+impl RpcMetrics<'_> {
+    /// Whether the RPC failed.
+    pub fn is_error(&self) -> bool {
+        self.code != Code::Ok
+    }
+
+    /// Whether the RPC retried a previous attempt.
+    pub fn is_retry(&self) -> bool {
+        self.attempt > 1
+    }
+}
+
+/// A `MetricsSink` aggregating the metrics of each operation in memory,
+/// for the application to export them periodically.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tonic::Code;
+/// use google_cloud::datastore::{MetricsSink, RpcMetrics, RpcStats};
+///
+/// let stats = RpcStats::new();
+/// let metrics = RpcMetrics {
+///     operation: "lookup",
+///     project: "my-project",
+///     kind: "users",
+///     code: Code::Unavailable,
+///     duration: Duration::from_millis(30),
+///     attempt: 1,
+///     request_bytes: 120,
+///     response_bytes: 0,
+/// };
+/// stats.record(&metrics);
+///
+/// let lookups = &stats.snapshot()["lookup"];
+/// assert_eq!((lookups.requests, lookups.errors), (1, 1));
+/// ```
+#[derive(Debug, Default)]
+pub struct RpcStats {
+    operations: Mutex<HashMap<&'static str, OperationStats>>,
+}
+
+/// The aggregated metrics of an operation, as recorded by `RpcStats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationStats {
+    /// How many RPCs were sent.
+    pub requests: u64,
+    /// How many of them failed.
+    pub errors: u64,
+    /// How many of them retried a previous attempt.
+    pub retries: u64,
+    /// The time taken by all of them.
+    pub total_duration: Duration,
+    /// The time taken by the slowest one.
+    pub max_duration: Duration,
+    /// The bytes sent.
+    pub request_bytes: u64,
+    /// The bytes received.
+    pub response_bytes: u64,
+}
+
+impl RpcStats {
+    /// Creates empty stats.
+    pub fn new() -> RpcStats {
+        RpcStats::default()
+    }
+
+    /// Gets the current stats of each operation which was recorded at least once.
+    pub fn snapshot(&self) -> HashMap<&'static str, OperationStats> {
+        self.operations.lock().unwrap().clone()
+    }
+
+    /// Gets the current stats and starts over from zero, to export the stats by interval.
+    pub fn take(&self) -> HashMap<&'static str, OperationStats> {
+        std::mem::take(&mut *self.operations.lock().unwrap())
+    }
+
+    /// Renders the current stats in the Prometheus text format, for a `/metrics` endpoint.
+    ///
+    /// Each metric is named `datastore_rpc_*` and labelled by operation.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use tonic::Code;
+    /// use google_cloud::datastore::{MetricsSink, RpcMetrics, RpcStats};
+    ///
+    /// let stats = RpcStats::new();
+    /// stats.record(&RpcMetrics {
+    ///     operation: "commit",
+    ///     project: "my-project",
+    ///     kind: "users",
+    ///     code: Code::Ok,
+    ///     duration: Duration::from_millis(250),
+    ///     attempt: 2,
+    ///     request_bytes: 120,
+    ///     response_bytes: 40,
+    /// });
+    ///
+    /// let text = stats.to_prometheus();
+    /// assert!(text.contains("# TYPE datastore_rpc_requests_total counter\n"));
+    /// assert!(text.contains("datastore_rpc_retries_total{operation=\"commit\"} 1\n"));
+    /// assert!(text.contains("datastore_rpc_duration_seconds_total{operation=\"commit\"} 0.25\n"));
+    /// ```
+    pub fn to_prometheus(&self) -> String {
+        type Sample = fn(&OperationStats) -> String;
+        let metrics: [(&str, &str, &str, Sample); 7] = [
+            ("requests_total", "counter", "The RPCs sent.", |stats| stats.requests.to_string()),
+            ("errors_total", "counter", "The RPCs which failed.", |stats| stats.errors.to_string()),
+            ("retries_total", "counter", "The RPCs which retried a previous attempt.", |stats| {
+                stats.retries.to_string()
+            }),
+            ("duration_seconds_total", "counter", "The time taken by the RPCs.", |stats| {
+                stats.total_duration.as_secs_f64().to_string()
+            }),
+            ("duration_seconds_max", "gauge", "The time taken by the slowest RPC.", |stats| {
+                stats.max_duration.as_secs_f64().to_string()
+            }),
+            ("request_bytes_total", "counter", "The bytes sent.", |stats| {
+                stats.request_bytes.to_string()
+            }),
+            ("response_bytes_total", "counter", "The bytes received.", |stats| {
+                stats.response_bytes.to_string()
+            }),
+        ];
+
+        //? Sorted, so that the output is stable between scrapes.
+        let mut operations: Vec<_> = self.snapshot().into_iter().collect();
+        operations.sort_by_key(|(operation, _)| *operation);
+
+        let mut text = String::new();
+        for (name, kind, help, sample) in metrics {
+            text.push_str(&format!("# HELP datastore_rpc_{} {}\n", name, help));
+            text.push_str(&format!("# TYPE datastore_rpc_{} {}\n", name, kind));
+            for (operation, stats) in operations.iter() {
+                text.push_str(&format!(
+                    "datastore_rpc_{}{{operation=\"{}\"}} {}\n",
+                    name,
+                    operation,
+                    sample(stats)
+                ));
+            }
+        }
+        text
+    }
+}
+
+impl MetricsSink for RpcStats {
+    fn record(&self, metrics: &RpcMetrics<'_>) {
+        let mut operations = self.operations.lock().unwrap();
+        let stats = operations.entry(metrics.operation).or_default();
+        stats.requests += 1;
+        stats.errors += u64::from(metrics.is_error());
+        stats.retries += u64::from(metrics.is_retry());
+        stats.total_duration += metrics.duration;
+        stats.max_duration = stats.max_duration.max(metrics.duration);
+        stats.request_bytes += metrics.request_bytes as u64;
+        stats.response_bytes += metrics.response_bytes as u64;
+    }
+}
@@ -1,11 +1,38 @@
+mod aggregation;
+mod array;
+mod audit;
+mod builder;
 mod client;
+#[cfg(feature = "serde")]
+mod data_format;
+mod duration;
 mod entity;
+mod gql;
+mod id_allocator;
 mod index_excluded;
 mod key;
+mod metrics;
+mod mutation;
+mod non_finite;
+mod operation;
+mod progress;
 mod query;
-mod value;
+#[cfg(feature = "serde")]
+mod serialization;
+mod slow_query;
+mod trace;
 mod transaction;
-mod api {
+mod ttl;
+mod value;
+#[allow(clippy::all)]
+pub(crate) mod api {
+    pub mod rpc {
+        include!("api/google.rpc.rs");
+    }
+    #[allow(dead_code)]
+    pub mod longrunning {
+        include!("api/google.longrunning.rs");
+    }
     pub mod r#type {
         include!("api/google.r#type.rs");
     }
@@ -18,13 +45,30 @@ mod api {
     pub use self::r#type::*;
 }
 
+pub use self::aggregation::*;
+pub use self::array::*;
+pub use self::audit::*;
+pub use self::builder::*;
 pub use self::client::*;
+#[cfg(feature = "serde")]
+pub use self::data_format::*;
+pub use self::duration::*;
 pub use self::entity::*;
+pub use self::gql::*;
+pub use self::id_allocator::*;
 pub use self::index_excluded::*;
 pub use self::key::*;
+pub use self::metrics::*;
+pub use self::mutation::*;
+pub use self::non_finite::*;
+pub use self::operation::*;
+pub use self::progress::*;
 pub use self::query::*;
-pub use self::value::*;
+pub use self::slow_query::*;
+pub(crate) use self::trace::*;
 pub use self::transaction::*;
+pub use self::ttl::*;
+pub use self::value::*;
 
 /// The error type for the Datastore module.
 pub type Error = crate::error::Error;
@@ -1,13 +1,153 @@
+use chrono::{DateTime, NaiveDateTime};
+use tonic::Status;
+
 use crate::datastore::api;
-use crate::datastore::{Key, Value};
+use crate::datastore::{Error, Key};
+
+/// The outcome of a single mutation of a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationResult {
+    /// The key allocated by the store, set only when the mutation had an incomplete key.
+    pub key: Option<Key>,
+    /// The version of the entity after the mutation.
+    ///
+    /// Store it to make conditional writes later on.
+    pub version: i64,
+    /// The creation time of the entity (not set after a delete).
+    pub create_time: Option<NaiveDateTime>,
+    /// The time of the last update of the entity (not set after a delete).
+    pub update_time: Option<NaiveDateTime>,
+    /// Whether a conflict was detected for this mutation.
+    /// Always false when the mutation has no conflict detection strategy.
+    pub conflict_detected: bool,
+}
+
+impl From<api::MutationResult> for MutationResult {
+    fn from(result: api::MutationResult) -> MutationResult {
+        MutationResult {
+            key: result.key.map(Key::from),
+            version: result.version,
+            create_time: result.create_time.and_then(convert_timestamp),
+            update_time: result.update_time.and_then(convert_timestamp),
+            conflict_detected: result.conflict_detected,
+        }
+    }
+}
 
-/// Represents a Datastore mutation operation.
+/// The outcome of a mutation, reported in the order the mutations were given.
+///
+/// Every outcome carries the key of the entity it is about, so that it can be matched
+/// with its input without relying on positions.
 #[derive(Debug, Clone, PartialEq)]
-pub enum Mutation {
-    Insert(Entity),
-    Update(Entity),
-    Upsert(Entity),
-    Delete(Key),
+pub enum MutationOutcome {
+    /// An entity with an incomplete key was inserted, under the key allocated by the store
+    /// (or by the `IdAllocator` of the client).
+    InsertedKey(Key, MutationResult),
+    /// An entity with a complete key was written (inserted, updated or upserted).
+    Upserted(Key, MutationResult),
+    /// The entity was deleted, or did not exist in the first place.
+    Deleted(Key, MutationResult),
+    /// A conditional write was not applied, because the stored entity changed in the meantime.
+    ConflictDetected(Key),
+}
+
+impl MutationOutcome {
+    /// The key of the entity, as allocated by the store for an `InsertedKey`.
+    pub fn key(&self) -> &Key {
+        match self {
+            MutationOutcome::InsertedKey(key, _)
+            | MutationOutcome::Upserted(key, _)
+            | MutationOutcome::Deleted(key, _)
+            | MutationOutcome::ConflictDetected(key) => key,
+        }
+    }
+
+    /// The result of the mutation, if it was applied.
+    pub fn result(&self) -> Option<&MutationResult> {
+        match self {
+            MutationOutcome::InsertedKey(_, result)
+            | MutationOutcome::Upserted(_, result)
+            | MutationOutcome::Deleted(_, result) => Some(result),
+            MutationOutcome::ConflictDetected(_) => None,
+        }
+    }
+
+    /// The result of the mutation, if it was applied.
+    pub fn into_result(self) -> Option<MutationResult> {
+        match self {
+            MutationOutcome::InsertedKey(_, result)
+            | MutationOutcome::Upserted(_, result)
+            | MutationOutcome::Deleted(_, result) => Some(result),
+            MutationOutcome::ConflictDetected(_) => None,
+        }
+    }
+
+    /// The version of the entity after the mutation, if it was applied.
+    pub fn version(&self) -> Option<i64> {
+        self.result().map(|result| result.version)
+    }
+
+    /// Whether the mutation was skipped because of a conflict.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, MutationOutcome::ConflictDetected(_))
+    }
+}
+
+/// What a mutation is about, kept aside to interpret its result once committed.
+#[derive(Debug, Clone)]
+pub(crate) struct MutationTarget {
+    key: Key,
+    is_delete: bool,
+    /// Whether the ID of the key was allocated by the client (see `IdAllocator`).
+    allocated: bool,
 }
 
-impl Mutation {}
+impl MutationTarget {
+    pub(crate) fn of(mutation: &api::Mutation) -> MutationTarget {
+        use api::mutation::Operation;
+
+        let (key, is_delete) = match mutation.operation.as_ref().unwrap() {
+            Operation::Insert(entity) | Operation::Update(entity) | Operation::Upsert(entity) => {
+                (entity.key.clone().unwrap(), false)
+            }
+            Operation::Delete(key) => (key.clone(), true),
+        };
+        MutationTarget { key: Key::from(key), is_delete, allocated: false }
+    }
+
+    pub(crate) fn allocated(mut self, allocated: bool) -> MutationTarget {
+        self.allocated = allocated;
+        self
+    }
+
+    pub(crate) fn outcome(self, result: api::MutationResult) -> MutationOutcome {
+        let conflict_detected = result.conflict_detected;
+        let result = MutationResult::from(result);
+        match result.key.clone() {
+            _ if conflict_detected => MutationOutcome::ConflictDetected(self.key),
+            Some(allocated) => MutationOutcome::InsertedKey(allocated, result),
+            None if self.allocated => MutationOutcome::InsertedKey(self.key, result),
+            None if self.is_delete => MutationOutcome::Deleted(self.key, result),
+            None => MutationOutcome::Upserted(self.key, result),
+        }
+    }
+}
+
+/// Pairs the results of a commit with the mutations they come from.
+///
+/// The store returns exactly one result per mutation, anything else is an internal error
+/// (rather than outcomes silently assigned to the wrong mutations).
+pub(crate) fn mutation_outcomes(
+    targets: Vec<MutationTarget>,
+    results: Vec<api::MutationResult>,
+) -> Result<Vec<MutationOutcome>, Error> {
+    if targets.len() != results.len() {
+        let message = format!("{} mutation results for {} mutations", results.len(), targets.len());
+        return Err(Status::internal(message).into());
+    }
+    Ok(targets.into_iter().zip(results).map(|(target, result)| target.outcome(result)).collect())
+}
+
+fn convert_timestamp(timestamp: prost_types::Timestamp) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32).map(|time| time.naive_utc())
+}
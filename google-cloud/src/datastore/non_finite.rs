@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use crate::datastore::Value;
+use crate::error::ConvertError;
+
+/// What a client does with the NaN and infinite doubles of the entities it writes
+/// (see `ClientBuilder::non_finite_policy`).
+///
+/// Datastore stores them, but they do not compare as other doubles do and many tools
+/// choke on them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NonFinitePolicy {
+    /// Writes them as they are (the default).
+    #[default]
+    AllowNonFinite,
+    /// Fails the write with a `ConvertError::NonFiniteDouble` naming the property path
+    /// (such as `items[0].price`), before any request is sent.
+    RejectNonFinite,
+    /// Writes them as nulls.
+    ClampToNull,
+}
+
+impl NonFinitePolicy {
+    /// Applies the policy to the properties of an entity about to be written.
+    pub(crate) fn apply(self, properties: &mut Value) -> Result<(), ConvertError> {
+        match self {
+            NonFinitePolicy::AllowNonFinite => Ok(()),
+            _ => self.apply_to(properties, &mut String::new()),
+        }
+    }
+
+    fn apply_to(self, value: &mut Value, path: &mut String) -> Result<(), ConvertError> {
+        match value {
+            Value::DoubleValue(double) if !double.is_finite() => match self {
+                NonFinitePolicy::AllowNonFinite => {}
+                NonFinitePolicy::RejectNonFinite => {
+                    return Err(ConvertError::NonFiniteDouble(path.clone()))
+                }
+                NonFinitePolicy::ClampToNull => *value = Value::OptionValue(None),
+            },
+            Value::OptionValue(Some(value)) => self.apply_to(value, path)?,
+            Value::EntityValue(properties) => self.apply_to_properties(properties, path)?,
+            Value::ArrayValue(values) => {
+                for (index, value) in values.iter_mut().enumerate() {
+                    let len = path.len();
+                    path.push_str(&format!("[{}]", index));
+                    self.apply_to(value, path)?;
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn apply_to_properties(
+        self,
+        properties: &mut HashMap<String, Value>,
+        path: &mut String,
+    ) -> Result<(), ConvertError> {
+        for (name, value) in properties.iter_mut() {
+            let len = path.len();
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(name);
+            self.apply_to(value, path)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::datastore::api;
+use crate::datastore::api::longrunning::operation::Result as OperationResult;
+use crate::datastore::{Client, Error, Rpc};
+
+/// A long-running operation (such as an export, an import or an index build),
+/// whose response decodes to `T` once done.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use google_cloud::datastore::{Client, Operation};
+/// # async fn run(client: Client) -> Result<(), google_cloud::datastore::Error> {
+/// //? `()` skips the decoding of the response, for operations returning `google.protobuf.Empty`.
+/// let operation: Operation<()> = client.operation("projects/my-project/operations/ASA4NjEzNDE");
+/// operation.await_done(Duration::from_secs(3600), Duration::from_secs(10)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Operation<T> {
+    pub(crate) client: Client,
+    pub(crate) name: String,
+    pub(crate) state: Option<api::longrunning::Operation>,
+    pub(crate) response: PhantomData<fn() -> T>,
+}
+
+impl<T: Message + Default> Operation<T> {
+    /// Gets the name of the operation, to resume polling it later with `Client::operation`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the operation was done when last polled.
+    pub fn is_done(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| state.done)
+    }
+
+    /// Decodes the metadata of the operation, as of the last poll.
+    ///
+    /// The metadata carries the progress of the operation, its type depends on the operation:
+    /// `ExportEntitiesMetadata`, `ImportEntitiesMetadata` or `IndexOperationMetadata`
+    /// for the Datastore Admin API.
+    pub fn metadata<M: Message + Default>(&self) -> Result<Option<M>, Error> {
+        let metadata = self.state.as_ref().and_then(|state| state.metadata.as_ref());
+        match metadata {
+            Some(metadata) => Ok(Some(M::decode(metadata.value.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetches the latest state of the operation, returning its response if it is done.
+    pub async fn poll(&mut self) -> Result<Option<T>, Error> {
+        let request = api::longrunning::GetOperationRequest { name: self.name.clone() };
+        let request = self.client.construct_request(request).await?;
+        let rpc = self.client.rpc(Rpc::GetOperation, &request);
+        let mut service = self.client.operations.clone();
+        let state = rpc.run(service.get_operation(request)).await?;
+        let state = state.into_inner();
+        self.state = Some(state);
+        self.response()
+    }
+
+    /// Polls the operation every `poll_interval` until it is done, for at most `timeout`.
+    ///
+    /// Fails with a `DEADLINE_EXCEEDED` status if the operation is still running after `timeout`:
+    /// the operation itself keeps running, it can be resumed with `Client::operation`.
+    pub async fn await_done(
+        mut self,
+        timeout: Duration,
+        poll_interval: Duration,
+    ) -> Result<T, Error> {
+        let started = Instant::now();
+        loop {
+            if let Some(response) = self.poll().await? {
+                return Ok(response);
+            }
+            if started.elapsed() + poll_interval > timeout {
+                return Err(Status::deadline_exceeded(format!(
+                    "operation `{}` still running after {:?}",
+                    self.name, timeout
+                ))
+                .into());
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Asks the server to cancel the operation.
+    ///
+    /// The cancellation is asynchronous and not guaranteed: cancelled operations end up done,
+    /// with a `CANCELLED` status.
+    pub async fn cancel(&mut self) -> Result<(), Error> {
+        let request = api::longrunning::CancelOperationRequest { name: self.name.clone() };
+        let request = self.client.construct_request(request).await?;
+        let rpc = self.client.rpc(Rpc::CancelOperation, &request);
+        let mut service = self.client.operations.clone();
+        rpc.run(service.cancel_operation(request)).await?;
+        Ok(())
+    }
+
+    fn response(&self) -> Result<Option<T>, Error> {
+        let state = match &self.state {
+            Some(state) if state.done => state,
+            _ => return Ok(None),
+        };
+        match &state.result {
+            Some(OperationResult::Response(response)) => {
+                Ok(Some(T::decode(response.value.as_slice())?))
+            }
+            Some(OperationResult::Error(status)) => {
+                Err(Status::new(Code::from_i32(status.code), status.message.clone()).into())
+            }
+            None => Ok(Some(T::default())),
+        }
+    }
+}
+
+impl Client {
+    /// Gets a handle to a long-running operation of the project, from its name.
+    pub fn operation<T: Message + Default>(&self, name: impl Into<String>) -> Operation<T> {
+        Operation { client: self.clone(), name: name.into(), state: None, response: PhantomData }
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::Stream;
+
+/// Progress of a lookup, reported after each of its rounds.
+///
+/// Large lookups are split in batches, and Datastore may defer some of their keys
+/// to later rounds: these events let long jobs render progress and budget their time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupProgress {
+    /// How many keys were requested in total.
+    pub requested: usize,
+    /// How many entities were found so far.
+    pub found: usize,
+    /// How many keys were found to be missing so far.
+    pub missing: usize,
+    /// How many keys are still to be looked up, deferred ones included.
+    pub pending: usize,
+    /// How many keys were deferred by the last round.
+    pub deferred: usize,
+    /// How many rounds (Lookup RPCs) were made so far.
+    pub rounds: usize,
+    /// How many of these rounds had keys deferred.
+    pub deferred_rounds: usize,
+    /// How long the lookup has been running.
+    pub elapsed: Duration,
+}
+
+impl LookupProgress {
+    /// Whether the lookup is over.
+    pub fn is_done(&self) -> bool {
+        self.pending == 0
+    }
+}
+
+/// Function receiving the progress of lookups.
+pub(crate) type LookupProgressFn<'a> = dyn Fn(&LookupProgress) + Send + Sync + 'a;
+
+/// Callback receiving the progress of lookups.
+#[derive(Clone)]
+pub(crate) struct LookupProgressCallback(pub(crate) Arc<LookupProgressFn<'static>>);
+
+impl fmt::Debug for LookupProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("LookupProgressCallback(...)")
+    }
+}
+
+/// Creates a progress callback, for `Client::get_all_with_progress`,
+/// along with the stream of the events it receives.
+///
+/// The stream ends once the callback is dropped, which happens when the lookup is over.
+///
+/// ```no_run
+/// # use futures::StreamExt;
+/// # use google_cloud::datastore::{lookup_progress_stream, Client, Key};
+/// # async fn run(client: Client, keys: Vec<Key>) -> Result<(), google_cloud::datastore::Error> {
+/// let (progress, mut events) = lookup_progress_stream();
+/// let render = async move {
+///     while let Some(event) = events.next().await {
+///         eprintln!("{}/{} keys", event.found + event.missing, event.requested);
+///     }
+/// };
+/// let (users, _) = futures::join!(client.get_all_with_progress::<i64, _, _>(keys, progress), render);
+/// # Ok(())
+/// # }
+/// ```
+pub fn lookup_progress_stream(
+) -> (impl Fn(&LookupProgress) + Send + Sync + 'static, impl Stream<Item = LookupProgress>) {
+    let (sender, receiver) = mpsc::unbounded();
+    let callback = move |progress: &LookupProgress| {
+        //? The receiver may be gone already, when nobody cares about the progress anymore.
+        let _ = sender.unbounded_send(progress.clone());
+    };
+    (callback, receiver)
+}
+
+/// Tracks the progress of a lookup, and reports it to the global and per-call callbacks.
+pub(crate) struct LookupTracker<'a> {
+    started: Instant,
+    progress: LookupProgress,
+    callbacks: [Option<&'a LookupProgressFn<'a>>; 2],
+}
+
+impl<'a> LookupTracker<'a> {
+    pub(crate) fn new(
+        requested: usize,
+        global: Option<&'a LookupProgressCallback>,
+        call: Option<&'a LookupProgressFn<'a>>,
+    ) -> LookupTracker<'a> {
+        let progress = LookupProgress {
+            requested,
+            found: 0,
+            missing: 0,
+            pending: requested,
+            deferred: 0,
+            rounds: 0,
+            deferred_rounds: 0,
+            elapsed: Duration::ZERO,
+        };
+        LookupTracker {
+            started: Instant::now(),
+            progress,
+            callbacks: [global.map(|c| &*c.0), call],
+        }
+    }
+
+    /// Records a round of the lookup.
+    pub(crate) fn round(&mut self, found: usize, missing: usize, deferred: usize, pending: usize) {
+        let progress = &mut self.progress;
+        progress.found += found;
+        progress.missing += missing;
+        progress.deferred = deferred;
+        progress.pending = pending;
+        progress.rounds += 1;
+        if deferred > 0 {
+            progress.deferred_rounds += 1;
+        }
+        progress.elapsed = self.started.elapsed();
+
+        for callback in self.callbacks.iter().flatten() {
+            callback(&self.progress);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use super::{IntoValue, Key};
+use chrono::{DateTime, Utc};
+
+use super::{fnv1a, FromValue, IntoValue, Key};
 use crate::datastore::Value;
 
 /// Represents Datastore query result orderings.
@@ -42,11 +44,24 @@ pub enum CompositeFilter {
     Or,
 }
 
+/// A type the results of a projection query decode as, naming the properties to project
+/// (see `Query::project_as`).
+///
+/// `#[derive(Projection)]` implements it for a struct with the same property names as
+/// `#[derive(FromValue)]`. Projected timestamps are decoded as timestamps, although Datastore
+/// returns them as microseconds.
+pub trait Projection: FromValue {
+    /// The names of the projected properties, at least one (an empty projection would
+    /// query the full entities).
+    const PROPERTIES: &'static [&'static str];
+}
+
 /// Represents a Datastore query.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Query {
     pub(crate) kind: String,
     pub(crate) eventual: bool,
+    pub(crate) read_time: Option<DateTime<Utc>>,
     pub(crate) keys_only: bool,
     pub(crate) offset: i32,
     pub(crate) limit: Option<i32>,
@@ -57,6 +72,7 @@ pub struct Query {
     pub(crate) filters: Vec<Filter>,
     pub(crate) cursor: Option<Vec<u8>>,
     pub(crate) composite_filter: CompositeFilter,
+    pub(crate) key_lookup: bool,
 }
 
 impl Query {
@@ -70,6 +86,7 @@ impl Query {
         Query {
             kind: kind.into(),
             eventual: false,
+            read_time: None,
             keys_only: false,
             offset: 0,
             limit: None,
@@ -80,6 +97,7 @@ impl Query {
             filters: Vec::new(),
             cursor: None,
             composite_filter: CompositeFilter::And,
+            key_lookup: false,
         }
     }
 
@@ -96,6 +114,23 @@ impl Query {
         self
     }
 
+    /// Ask to read the entities as they were at the given time.
+    /// It has no effect on queries run in a transaction.
+    ///
+    /// The time must be within the past hour or, if Point-in-Time Recovery is enabled,
+    /// a whole minute within the past 7 days.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Query;
+    /// # use chrono::{TimeDelta, Utc};
+    /// let query = Query::new("users")
+    ///     .read_time(Utc::now() - TimeDelta::try_minutes(5).unwrap());
+    /// ```
+    pub fn read_time(mut self, time: DateTime<Utc>) -> Query {
+        self.read_time = Some(time);
+        self
+    }
+
     /// Ask to yield only yield keys, without the entity values.
     /// It has no effects on projected queries.
     ///
@@ -176,6 +211,32 @@ impl Query {
         self
     }
 
+    /// Projects the properties of `T`, to decode the results as `T` (see `Client::query_as`).
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{FromValue, Projection, Query, Value};
+    /// # use google_cloud::error::ConvertError;
+    /// struct UserSummary {
+    ///     name: String,
+    /// }
+    ///
+    /// impl FromValue for UserSummary {
+    ///     fn from_value(value: Value) -> Result<UserSummary, ConvertError> {
+    ///         Ok(UserSummary { name: value.get_path("name")? })
+    ///     }
+    /// }
+    ///
+    /// //? Usually derived, along with `FromValue`.
+    /// impl Projection for UserSummary {
+    ///     const PROPERTIES: &'static [&'static str] = &["name"];
+    /// }
+    ///
+    /// let query = Query::new("users").project_as::<UserSummary>();
+    /// ```
+    pub fn project_as<T: Projection>(self) -> Query {
+        self.project(T::PROPERTIES.iter().copied())
+    }
+
     /// Ask to yield de-duplicated results.
     ///
     /// ```
@@ -229,10 +290,11 @@ impl Query {
     /// implement a pagination system
     ///
     /// ```
+    /// # use google_cloud::datastore::Query;
+    /// # let cursor = Vec::new();
     /// let query = Query::new("users")
     ///     .cursor(cursor);
     /// ```
-    ///
     pub fn cursor(mut self, cursor: Vec<u8>) -> Query {
         self.cursor = Some(cursor);
         self
@@ -242,7 +304,7 @@ impl Query {
     ///
     /// ```
     /// # use google_cloud::datastore::Query;
-    /// use google_cloud::datastore::{Filter, Value, IntoValue};
+    /// use google_cloud::datastore::{CompositeFilter, Filter, Value, IntoValue};
     ///
     /// let query = Query::new("users")
     ///     .filter(Filter::GreaterThan("age".into(), 10.into_value()))
@@ -253,4 +315,162 @@ impl Query {
         self.composite_filter = composite_filter;
         self
     }
+
+    /// Run the query as a lookup of its keys, when it only selects entities by their keys.
+    ///
+    /// This applies to a query whose filters are only `__key__ IN [...]` or an 'OR' of
+    /// `__key__ = ...` filters, without any ordering, projection, offset, limit, cursor,
+    /// read time or eventual consistency, which does not ask for keys only. The lookup is
+    /// strongly consistent and not bound by the limit on the number of `IN` values, but it
+    /// yields the entities in the order of the keys, and an empty end cursor.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Query;
+    /// use google_cloud::datastore::{Filter, IntoValue, Key};
+    ///
+    /// let keys = vec![Key::new("users").id(1), Key::new("users").id(2)];
+    /// let query = Query::new("users")
+    ///     .filter(Filter::In("__key__".into(), keys.into_value()))
+    ///     .key_lookup();
+    /// ```
+    pub fn key_lookup(mut self) -> Query {
+        self.key_lookup = true;
+        self
+    }
+
+    /// The keys selected by the query, if it can run as a lookup of these keys.
+    ///
+    /// Only the keys of the queried kind are kept, since the others cannot match.
+    pub(crate) fn lookup_keys(&self) -> Option<Vec<Key>> {
+        let plain = self.key_lookup
+            && !self.keys_only
+            && !self.eventual
+            && self.projections.is_empty()
+            && self.distinct_on.is_empty()
+            && self.ordering.is_empty()
+            && self.offset == 0
+            && self.limit.is_none()
+            && self.cursor.is_none()
+            && self.read_time.is_none();
+        let combinable = match self.composite_filter {
+            CompositeFilter::And => self.filters.len() == 1,
+            CompositeFilter::Or => !self.filters.is_empty(),
+        };
+        if !plain || !combinable {
+            return None;
+        }
+
+        let mut keys = Vec::new();
+        for filter in self.filters.iter() {
+            match filter {
+                Filter::Equal(property, Value::KeyValue(key)) if property == "__key__" => {
+                    keys.push(key);
+                }
+                Filter::In(property, Value::ArrayValue(values)) if property == "__key__" => {
+                    for value in values {
+                        match value {
+                            Value::KeyValue(key) => keys.push(key),
+                            _ => return None,
+                        }
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        let keys = keys
+            .into_iter()
+            .filter(|key| key.get_kind() == self.kind && !key.is_incomplete())
+            .cloned()
+            .collect();
+        Some(keys)
+    }
+
+    /// Describe the shape of the query: its kind, the structure and properties of its filters,
+    /// its orderings and projections, leaving out the literal values, the namespace,
+    /// the limits and the cursor.
+    ///
+    /// Queries that only differ by their values share the same shape:
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Query;
+    /// use google_cloud::datastore::{Filter, IntoValue, Order};
+    ///
+    /// let query = Query::new("users")
+    ///     .filter(Filter::GreaterThan("age".into(), 10.into_value()))
+    ///     .filter(Filter::Equal("firstname".into(), "john".into_value()))
+    ///     .order(Order::Desc("age".into()));
+    /// assert_eq!(query.shape(), "kind=users;filter=and(eq(firstname),gt(age));order=desc(age)");
+    /// ```
+    pub fn shape(&self) -> String {
+        let mut filters: Vec<String> = self.filters.iter().map(filter_shape).collect();
+        //? The filters are combined with the same operator, their order does not matter.
+        filters.sort();
+        let operator = match self.composite_filter {
+            CompositeFilter::And => "and",
+            CompositeFilter::Or => "or",
+        };
+
+        let mut shape = format!("kind={}", self.kind);
+        if !filters.is_empty() {
+            shape.push_str(&format!(";filter={}({})", operator, filters.join(",")));
+        }
+        if !self.ordering.is_empty() {
+            let ordering: Vec<String> = self
+                .ordering
+                .iter()
+                .map(|order| match order {
+                    Order::Asc(property) => format!("asc({})", property),
+                    Order::Desc(property) => format!("desc({})", property),
+                })
+                .collect();
+            shape.push_str(&format!(";order={}", ordering.join(",")));
+        }
+        if !self.projections.is_empty() {
+            let mut projections = self.projections.clone();
+            projections.sort();
+            shape.push_str(&format!(";project={}", projections.join(",")));
+        }
+        if !self.distinct_on.is_empty() {
+            let mut distinct_on = self.distinct_on.clone();
+            distinct_on.sort();
+            shape.push_str(&format!(";distinct_on={}", distinct_on.join(",")));
+        }
+        if self.keys_only {
+            shape.push_str(";keys_only");
+        }
+
+        shape
+    }
+
+    /// Hash the shape of the query (see `Query::shape`), to aggregate queries in logs and metrics.
+    ///
+    /// The hash (64 bits FNV-1a) is stable across builds and platforms.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::Query;
+    /// use google_cloud::datastore::{Filter, IntoValue};
+    ///
+    /// let query1 = Query::new("users").filter(Filter::Equal("firstname".into(), "john".into_value()));
+    /// let query2 = Query::new("users").filter(Filter::Equal("firstname".into(), "jane".into_value()));
+    /// assert_eq!(query1.fingerprint(), query2.fingerprint());
+    /// assert_ne!(query1.fingerprint(), Query::new("users").fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(&self.shape())
+    }
+}
+
+fn filter_shape(filter: &Filter) -> String {
+    match filter {
+        Filter::Equal(property, _) => format!("eq({})", property),
+        Filter::GreaterThan(property, _) => format!("gt({})", property),
+        Filter::LessThan(property, _) => format!("lt({})", property),
+        Filter::GreaterThanOrEqual(property, _) => format!("gte({})", property),
+        Filter::LessThanOrEqual(property, _) => format!("lte({})", property),
+        Filter::HasAncestor(_) => String::from("ancestor"),
+        Filter::In(property, _) => format!("in({})", property),
+        Filter::NotIn(property, _) => format!("not_in({})", property),
+        Filter::NotEqual(property, _) => format!("neq({})", property),
+    }
 }
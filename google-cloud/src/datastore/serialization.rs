@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error as _, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::datastore::{Key, KeyID, Value, KEY_TOKEN};
+
+#[derive(Serialize)]
+struct KeyRef<'a> {
+    kind: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<&'a Key>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyRepr {
+    kind: String,
+    #[serde(default)]
+    id: Option<i64>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    namespace: Option<String>,
+    #[serde(default)]
+    parent: Option<Box<Key>>,
+}
+
+//? Keys go through a newtype, transparent to other formats, for `to_value` to tell them apart.
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (id, name) = match &self.id {
+            KeyID::IntID(id) => (Some(*id), None),
+            KeyID::StringID(name) => (None, Some(name.as_str())),
+            KeyID::Incomplete => (None, None),
+        };
+        let key = KeyRef {
+            kind: &self.kind,
+            id,
+            name,
+            namespace: self.namespace.as_deref(),
+            parent: self.parent.as_deref(),
+        };
+        serializer.serialize_newtype_struct(KEY_TOKEN, &key)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        deserializer.deserialize_newtype_struct(KEY_TOKEN, KeyVisitor)
+    }
+}
+
+struct KeyVisitor;
+
+impl<'de> Visitor<'de> for KeyVisitor {
+    type Value = Key;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a key")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Key, D::Error> {
+        KeyRepr::deserialize(deserializer)?.into_key()
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Key, A::Error> {
+        KeyRepr::deserialize(MapAccessDeserializer::new(map))?.into_key()
+    }
+}
+
+impl KeyRepr {
+    fn into_key<E: serde::de::Error>(self) -> Result<Key, E> {
+        let id = match (self.id, self.name) {
+            (Some(_), Some(_)) => return Err(E::custom("a key has either an `id` or a `name`")),
+            (Some(id), None) => KeyID::IntID(id),
+            (None, Some(name)) => KeyID::StringID(name),
+            (None, None) => KeyID::Incomplete,
+        };
+        Ok(Key {
+            kind: self.kind,
+            id,
+            is_new: false,
+            parent: self.parent,
+            namespace: self.namespace,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyIDRepr {
+    Int(i64),
+    Name(String),
+}
+
+impl Serialize for KeyID {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            KeyID::IntID(id) => serializer.serialize_i64(*id),
+            KeyID::StringID(name) => serializer.serialize_str(name),
+            KeyID::Incomplete => serializer.serialize_none(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyID {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeyID, D::Error> {
+        let id = match Option::<KeyIDRepr>::deserialize(deserializer)? {
+            Some(KeyIDRepr::Int(id)) => KeyID::IntID(id),
+            Some(KeyIDRepr::Name(name)) => KeyID::StringID(name),
+            None => KeyID::Incomplete,
+        };
+        Ok(id)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeoPoint {
+    latitude: f64,
+    longitude: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ValueRepr {
+    Null(()),
+    Boolean(bool),
+    Integer(i64),
+    Double(f64),
+    Timestamp(String),
+    Key(Key),
+    String(String),
+    Blob(String),
+    GeoPoint(GeoPoint),
+    Entity(HashMap<String, Value>),
+    Array(Vec<Value>),
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        //? The variant indices follow `ValueRepr`.
+        match self {
+            Value::OptionValue(None) => {
+                serializer.serialize_newtype_variant("Value", 0, "null", &())
+            }
+            Value::OptionValue(Some(value)) => value.serialize(serializer),
+            Value::BooleanValue(value) => {
+                serializer.serialize_newtype_variant("Value", 1, "boolean", value)
+            }
+            Value::IntegerValue(value) => {
+                serializer.serialize_newtype_variant("Value", 2, "integer", value)
+            }
+            Value::DoubleValue(value) => {
+                serializer.serialize_newtype_variant("Value", 3, "double", value)
+            }
+            Value::TimestampValue(value) => {
+                let value = value.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                serializer.serialize_newtype_variant("Value", 4, "timestamp", &value)
+            }
+            Value::KeyValue(value) => {
+                serializer.serialize_newtype_variant("Value", 5, "key", value)
+            }
+            Value::StringValue(value) => {
+                serializer.serialize_newtype_variant("Value", 6, "string", value)
+            }
+            Value::BlobValue(value) => {
+                serializer.serialize_newtype_variant("Value", 7, "blob", &STANDARD.encode(value))
+            }
+            Value::GeoPointValue(latitude, longitude) => {
+                let value = GeoPoint { latitude: *latitude, longitude: *longitude };
+                serializer.serialize_newtype_variant("Value", 8, "geo_point", &value)
+            }
+            Value::EntityValue(value) => {
+                serializer.serialize_newtype_variant("Value", 9, "entity", value)
+            }
+            Value::ArrayValue(value) => {
+                serializer.serialize_newtype_variant("Value", 10, "array", value)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        let value = match ValueRepr::deserialize(deserializer)? {
+            ValueRepr::Null(()) => Value::OptionValue(None),
+            ValueRepr::Boolean(value) => Value::BooleanValue(value),
+            ValueRepr::Integer(value) => Value::IntegerValue(value),
+            ValueRepr::Double(value) => Value::DoubleValue(value),
+            ValueRepr::Timestamp(value) => Value::TimestampValue(parse_timestamp(&value)?),
+            ValueRepr::Key(value) => Value::KeyValue(value),
+            ValueRepr::String(value) => Value::StringValue(value),
+            ValueRepr::Blob(value) => {
+                Value::BlobValue(STANDARD.decode(value).map_err(D::Error::custom)?)
+            }
+            ValueRepr::GeoPoint(GeoPoint { latitude, longitude }) => {
+                Value::GeoPointValue(latitude, longitude)
+            }
+            ValueRepr::Entity(value) => Value::EntityValue(value),
+            ValueRepr::Array(value) => Value::ArrayValue(value),
+        };
+        Ok(value)
+    }
+}
+
+fn parse_timestamp<E: serde::de::Error>(value: &str) -> Result<NaiveDateTime, E> {
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|error| E::custom(format!("invalid timestamp `{}`: {}", value, error)))?;
+    Ok(time.naive_utc())
+}
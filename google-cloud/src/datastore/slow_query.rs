@@ -0,0 +1,84 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::datastore::api;
+
+/// Report of a query which took longer than the slow query threshold of the client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    /// The fingerprint of the query (see `Query::fingerprint`).
+    pub fingerprint: u64,
+    /// The shape of the query (see `Query::shape`), or the query string of GQL queries.
+    pub shape: String,
+    /// How long the query took, all its batches included.
+    pub duration: Duration,
+    /// How many entities the query returned.
+    pub entity_count: usize,
+    /// Whether more results are available after the returned cursor.
+    pub more_results: bool,
+}
+
+/// Callback receiving the slow query reports.
+pub(crate) type SlowQueryCallback = Arc<dyn Fn(&SlowQuery) + Send + Sync>;
+
+/// The slow query log configuration of a client.
+#[derive(Clone)]
+pub(crate) struct SlowQueryLog {
+    pub(crate) threshold: Duration,
+    pub(crate) callback: Option<SlowQueryCallback>,
+}
+
+impl fmt::Debug for SlowQueryLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SlowQueryLog")
+            .field("threshold", &self.threshold)
+            .field("callback", &self.callback.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+impl SlowQueryLog {
+    /// Reports the query if it started long enough ago.
+    pub(crate) fn report(
+        &self,
+        started: Instant,
+        fingerprint: u64,
+        shape: impl FnOnce() -> String,
+        entity_count: usize,
+        more_results: i32,
+    ) {
+        let duration = started.elapsed();
+        if duration < self.threshold {
+            return;
+        }
+
+        let report = SlowQuery {
+            fingerprint,
+            shape: shape(),
+            duration,
+            entity_count,
+            more_results: more_results
+                != api::query_result_batch::MoreResultsType::NoMoreResults as i32,
+        };
+        match &self.callback {
+            Some(callback) => callback(&report),
+            None => log::warn!(
+                "slow datastore query {:016x} ({}): {:?}, {} entities, more results: {}",
+                report.fingerprint,
+                report.shape,
+                report.duration,
+                report.entity_count,
+                report.more_results,
+            ),
+        }
+    }
+}
+
+/// Hashes a string with 64 bits FNV-1a, which is stable across builds and platforms.
+pub(crate) fn fnv1a(input: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    input.bytes().fold(OFFSET_BASIS, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
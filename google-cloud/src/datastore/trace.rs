@@ -0,0 +1,195 @@
+use std::future::Future;
+use std::time::Instant;
+
+use prost::Message;
+use tonic::{Code, Request, Response, Status};
+
+use crate::datastore::{api, Client, MetricsSink, RpcMetrics};
+
+/// The Datastore RPCs, traced in spans named after them (`datastore.lookup`, `datastore.commit`...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rpc {
+    Lookup,
+    RunQuery,
+    RunAggregationQuery,
+    BeginTransaction,
+    Commit,
+    Rollback,
+    AllocateIds,
+    GetOperation,
+    CancelOperation,
+}
+
+/// An RPC about to be sent, to trace it and report its metrics.
+///
+/// With the `tracing` feature, the RPC runs in a span carrying the project, the kind of the
+/// entities involved (empty when unknown) and the attempt number, then records its latency
+/// and status code. The metrics sink of the client, if any, gets its `RpcMetrics`.
+pub(crate) struct RpcCall<'a> {
+    rpc: Rpc,
+    project: &'a str,
+    kind: &'a str,
+    attempt: usize,
+    request_bytes: usize,
+    metrics: Option<&'a dyn MetricsSink>,
+}
+
+impl Client {
+    /// Prepares the tracing and the metrics of an RPC, before sending `request`.
+    pub(crate) fn rpc<R: Message>(&self, rpc: Rpc, request: &Request<R>) -> RpcCall<'_> {
+        let metrics = self.metrics.as_deref();
+        //? Sizes are only measured for the metrics, encoding them is not free.
+        let request_bytes = metrics.map_or(0, |_| request.get_ref().encoded_len());
+        RpcCall { rpc, project: &self.project_name, kind: "", attempt: 1, request_bytes, metrics }
+    }
+}
+
+impl<'a> RpcCall<'a> {
+    /// Sets the kind of the entities involved.
+    pub(crate) fn kind(mut self, kind: &'a str) -> RpcCall<'a> {
+        self.kind = kind;
+        self
+    }
+
+    /// Sets the attempt number, when the request is retried.
+    pub(crate) fn attempt(mut self, attempt: usize) -> RpcCall<'a> {
+        self.attempt = attempt;
+        self
+    }
+
+    /// Sends the RPC.
+    pub(crate) async fn run<T: Message>(
+        self,
+        call: impl Future<Output = Result<Response<T>, Status>>,
+    ) -> Result<Response<T>, Status> {
+        let started = Instant::now();
+        #[cfg(feature = "tracing")]
+        let (result, span) = {
+            use tracing::Instrument;
+
+            let span = self.rpc.span(self.project, self.kind, self.attempt);
+            (call.instrument(span.clone()).await, span)
+        };
+        #[cfg(not(feature = "tracing"))]
+        let result = call.await;
+        let duration = started.elapsed();
+
+        let code = match &result {
+            Ok(_) => Code::Ok,
+            Err(status) => status.code(),
+        };
+        #[cfg(feature = "tracing")]
+        {
+            span.record("latency_ms", duration.as_millis() as u64);
+            span.record("code", tracing::field::debug(code));
+        }
+        if let Some(metrics) = self.metrics {
+            metrics.record(&RpcMetrics {
+                operation: self.rpc.name(),
+                project: self.project,
+                kind: self.kind,
+                code,
+                duration,
+                attempt: self.attempt,
+                request_bytes: self.request_bytes,
+                response_bytes: result
+                    .as_ref()
+                    .map_or(0, |response| response.get_ref().encoded_len()),
+            });
+        }
+        result
+    }
+}
+
+/// Runs an attempt of a transaction in a `datastore.transaction` span, the parent of the spans
+/// of its RPCs, and records its latency.
+pub(crate) async fn traced_transaction<F: Future>(
+    project: &str,
+    attempt: usize,
+    body: F,
+) -> F::Output {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+
+        let span = tracing::info_span!(
+            "datastore.transaction",
+            project,
+            attempt,
+            latency_ms = tracing::field::Empty,
+        );
+        let started = std::time::Instant::now();
+        let output = body.instrument(span.clone()).await;
+        span.record("latency_ms", started.elapsed().as_millis() as u64);
+        output
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = (project, attempt);
+        body.await
+    }
+}
+
+impl Rpc {
+    /// The name of the RPC, as reported in its metrics.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Rpc::Lookup => "lookup",
+            Rpc::RunQuery => "run_query",
+            Rpc::RunAggregationQuery => "run_aggregation_query",
+            Rpc::BeginTransaction => "begin_transaction",
+            Rpc::Commit => "commit",
+            Rpc::Rollback => "rollback",
+            Rpc::AllocateIds => "allocate_ids",
+            Rpc::GetOperation => "get_operation",
+            Rpc::CancelOperation => "cancel_operation",
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn span(self, project: &str, kind: &str, attempt: usize) -> tracing::Span {
+        //? Span names are static, hence one invocation per RPC.
+        macro_rules! span {
+            ($name:literal) => {
+                tracing::info_span!(
+                    $name,
+                    project,
+                    kind,
+                    attempt,
+                    latency_ms = tracing::field::Empty,
+                    code = tracing::field::Empty,
+                )
+            };
+        }
+
+        match self {
+            Rpc::Lookup => span!("datastore.lookup"),
+            Rpc::RunQuery => span!("datastore.run_query"),
+            Rpc::RunAggregationQuery => span!("datastore.run_aggregation_query"),
+            Rpc::BeginTransaction => span!("datastore.begin_transaction"),
+            Rpc::Commit => span!("datastore.commit"),
+            Rpc::Rollback => span!("datastore.rollback"),
+            Rpc::AllocateIds => span!("datastore.allocate_ids"),
+            Rpc::GetOperation => span!("datastore.get_operation"),
+            Rpc::CancelOperation => span!("datastore.cancel_operation"),
+        }
+    }
+}
+
+/// The kind of the first key, to describe a request (empty without keys).
+pub(crate) fn keys_kind(keys: &[api::Key]) -> &str {
+    keys.first().and_then(|key| key.path.last()).map_or("", |element| element.kind.as_str())
+}
+
+/// The kind of the entity targeted by the first mutation, to describe a commit.
+pub(crate) fn mutations_kind(mutations: &[api::Mutation]) -> &str {
+    use api::mutation::Operation;
+
+    let key = mutations.first().and_then(|mutation| match mutation.operation.as_ref()? {
+        Operation::Insert(entity) | Operation::Update(entity) | Operation::Upsert(entity) => {
+            entity.key.as_ref()
+        }
+        Operation::Delete(key) => Some(key),
+    });
+    key.and_then(|key| key.path.last()).map_or("", |element| element.kind.as_str())
+}
@@ -1,9 +1,13 @@
 use super::{
-    api::{self, CommitRequest, Mutation, RollbackRequest},
-    convert_entity, convert_key, Aggregation, Client, FromValue, Key, Query, Value,
+    api::{self, mutation::ConflictDetectionStrategy, CommitRequest, Mutation, RollbackRequest},
+    convert_key, convert_timestamp, mutation_outcomes, Aggregation, AggregationResults, Client,
+    FromValue, Key, MutationOutcome, MutationTarget, Query, WriteMode,
 };
-use crate::datastore::{Entity, Error, IntoEntity};
+use crate::datastore::{mutations_kind, Entity, Error, GqlQuery, IntoEntity, Rpc};
+use chrono::NaiveDateTime;
+use futures::Stream;
 use std::borrow::Borrow;
+use std::ops::{Deref, DerefMut};
 
 /// Structure where the data necessary to manage the transaction is stored
 ///     - client: The Datastore client
@@ -14,6 +18,8 @@ pub struct Transaction {
     pub(crate) client: Client,
     pub(crate) tx_key: Vec<u8>,
     pub(crate) commit_request: CommitRequest,
+    /// What the accumulated mutations are about, in the same order.
+    pub(crate) targets: Vec<MutationTarget>,
 }
 
 impl Transaction {
@@ -27,6 +33,11 @@ impl Transaction {
         self.tx_key
     }
 
+    /// Wraps the transaction in a guard rolling it back if it is dropped without being committed.
+    pub fn into_guard(self) -> TransactionGuard {
+        TransactionGuard { transaction: Some(self) }
+    }
+
     /// Create a new transaction
     pub fn new(client: Client, tx_key: Vec<u8>) -> Transaction {
         let project_name = client.clone().project_name;
@@ -43,6 +54,7 @@ impl Transaction {
                 database_id: "".to_string(),
                 project_id: project_name,
             },
+            targets: Vec::new(),
         }
     }
 
@@ -63,7 +75,7 @@ impl Transaction {
         K: Borrow<Key>,
         T: FromValue,
     {
-        Ok(self.client.get_all_run(keys, Some(self.tx_key.to_vec())).await?)
+        self.client.get_all_run(keys, Some(self.tx_key.to_vec()), None).await
     }
 
     /// Create or Modify entity and returns its key.
@@ -88,28 +100,103 @@ impl Transaction {
         I: IntoIterator<Item = T>,
         T: IntoEntity,
     {
-        let entities: Vec<Entity> =
-            entities.into_iter().map(IntoEntity::into_entity).collect::<Result<_, _>>()?;
+        self.write_all(entities, WriteMode::Auto)
+    }
 
-        let mutations = entities
-            .into_iter()
-            .map(|entity| {
-                let is_incomplete = entity.key.is_new || entity.key.is_incomplete();
-                let entity = convert_entity(
-                    self.client.project_name.as_str(),
-                    entity,
-                    self.client.index_excluded.to_owned(),
-                );
-                let operation = match is_incomplete {
-                    true => Some(api::mutation::Operation::Insert(entity)),
-                    false => Some(api::mutation::Operation::Upsert(entity)),
-                };
-
-                api::Mutation { operation, conflict_detection_strategy: None }
-            })
-            .collect::<Vec<Mutation>>();
+    /// Adds the insert of an entity that must not exist yet.
+    ///
+    /// The commit fails with `Error::AlreadyExists` if an entity with the same key is already stored.
+    pub async fn insert(&mut self, entity: impl IntoEntity) -> Result<(), Error> {
+        self.write_all(Some(entity), WriteMode::Insert)
+    }
 
-        self.commit_request.mutations.append(&mut mutations.to_vec());
+    /// Same operation as the insert method but with multiple entities.
+    pub async fn insert_all<T, I>(&mut self, entities: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Insert)
+    }
+
+    /// Adds the update of an entity that must already exist.
+    ///
+    /// The commit fails with `Error::NotFound` if no entity with this key is stored.
+    pub async fn update(&mut self, entity: impl IntoEntity) -> Result<(), Error> {
+        self.write_all(Some(entity), WriteMode::Update)
+    }
+
+    /// Same operation as the update method but with multiple entities.
+    pub async fn update_all<T, I>(&mut self, entities: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Update)
+    }
+
+    /// Adds the creation or replacement of an entity.
+    pub async fn upsert(&mut self, entity: impl IntoEntity) -> Result<(), Error> {
+        self.write_all(Some(entity), WriteMode::Upsert)
+    }
+
+    /// Same operation as the upsert method but with multiple entities.
+    pub async fn upsert_all<T, I>(&mut self, entities: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        self.write_all(entities, WriteMode::Upsert)
+    }
+
+    /// Adds the write of an entity whose stored version must still be `base_version`.
+    ///
+    /// The commit reports a `MutationOutcome::ConflictDetected` if the entity was modified since.
+    pub async fn put_if_version(
+        &mut self,
+        entity: impl IntoEntity,
+        base_version: i64,
+    ) -> Result<(), Error> {
+        let strategy = ConflictDetectionStrategy::BaseVersion(base_version);
+        self.put_conditionally(entity, strategy)
+    }
+
+    /// Adds the write of an entity whose stored update time must still be `update_time`.
+    ///
+    /// The commit reports a `MutationOutcome::ConflictDetected` if the entity was modified since.
+    pub async fn put_if_unchanged_since(
+        &mut self,
+        entity: impl IntoEntity,
+        update_time: NaiveDateTime,
+    ) -> Result<(), Error> {
+        let strategy = ConflictDetectionStrategy::UpdateTime(convert_timestamp(update_time));
+        self.put_conditionally(entity, strategy)
+    }
+
+    fn put_conditionally(
+        &mut self,
+        entity: impl IntoEntity,
+        strategy: ConflictDetectionStrategy,
+    ) -> Result<(), Error> {
+        let (mut mutations, mut targets) =
+            self.client.entity_mutations(Some(entity), WriteMode::Auto)?;
+        for mutation in mutations.iter_mut() {
+            mutation.conflict_detection_strategy = Some(strategy.clone());
+        }
+        self.commit_request.mutations.append(&mut mutations);
+        self.targets.append(&mut targets);
+
+        Ok(())
+    }
+
+    fn write_all<T, I>(&mut self, entities: I, mode: WriteMode) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoEntity,
+    {
+        let (mut mutations, mut targets) = self.client.entity_mutations(entities, mode)?;
+        self.commit_request.mutations.append(&mut mutations);
+        self.targets.append(&mut targets);
 
         Ok(())
     }
@@ -139,13 +226,14 @@ impl Transaction {
             .into_iter()
             .map(|key| api::Mutation {
                 operation: Some(api::mutation::Operation::Delete(convert_key(
-                    self.client.project_name.as_str(),
+                    self.client.partition(),
                     key.borrow(),
                 ))),
                 conflict_detection_strategy: None,
             })
             .collect::<Vec<Mutation>>();
 
+        self.targets.extend(mutations.iter().map(MutationTarget::of));
         self.commit_request.mutations.append(&mut mutations.to_vec());
 
         Ok(())
@@ -154,32 +242,64 @@ impl Transaction {
     /// Execute a (potentially) complex query against the Datastore
     /// in a transaction and return the results.
     pub async fn query(&mut self, query: Query) -> Result<(Vec<Entity>, Vec<u8>), Error> {
-        Ok(self.client.query_run(query, Some(self.tx_key.to_vec())).await?)
+        self.client.query_run(query, Some(self.tx_key.to_vec())).await
+    }
+
+    /// Execute a (potentially) complex query against the Datastore in a transaction
+    /// and yield its results as its batches arrive (see `Client::query_stream`).
+    ///
+    /// Every batch is read in the transaction, so the results are consistent with its other reads.
+    pub fn query_stream(
+        &self,
+        query: Query,
+    ) -> impl Stream<Item = Result<Entity, Error>> + Send + 'static {
+        self.client.query_stream_run(query, Some(self.tx_key.to_vec()))
     }
 
-    /// Runs a (potentially) complex query againt Datastore and returns the results.
+    /// Execute a (potentially) complex query against the Datastore in a transaction
+    /// and convert the results as they arrive (see `Client::query_as`).
+    pub async fn query_as<T>(&mut self, query: Query) -> Result<(Vec<T>, Vec<u8>), Error>
+    where
+        T: FromValue,
+    {
+        self.client.query_as_run(query, Some(self.tx_key.to_vec())).await
+    }
+
+    /// Execute a GQL query against the Datastore in a transaction and return the results.
+    pub async fn gql(&mut self, query: GqlQuery) -> Result<(Vec<Entity>, Vec<u8>), Error> {
+        self.client.gql_run(query, Some(self.tx_key.to_vec())).await
+    }
+
+    /// Runs an aggregation query againt Datastore in a transaction and returns the results by alias.
     pub async fn aggregation_query(
         &mut self,
         aggregations: Vec<Aggregation>,
         query: Query,
-    ) -> Result<Vec<Value>, Error> {
-        Ok(self
-            .client
-            .aggregation_query_run(aggregations, query, Some(self.tx_key.to_vec()))
-            .await?)
+    ) -> Result<AggregationResults, Error> {
+        self.client.aggregation_query_run(aggregations, query, Some(self.tx_key.to_vec())).await
     }
 
-    /// Execute the transaction with the accumulated information.
-    /// Note that delete mutations do not return anything.
-    pub async fn commit(&mut self) -> Result<Vec<Option<Key>>, Error> {
-        let request = self.client.construct_request(self.commit_request.to_owned()).await?;
-        let response = self.client.service.commit(request).await?;
+    /// Runs a GQL aggregation query againt Datastore in a transaction and returns the results by alias.
+    pub async fn gql_aggregation(&mut self, query: GqlQuery) -> Result<AggregationResults, Error> {
+        self.client.gql_aggregation_run(query, Some(self.tx_key.to_vec())).await
+    }
 
-        let response = response.into_inner();
-        let keys =
-            response.mutation_results.into_iter().map(|result| result.key.map(Key::from)).collect();
+    /// Execute the transaction with the accumulated information
+    /// and return the outcomes of the mutations, in the order they were added.
+    ///
+    /// A conditional write which found a modified entity is not applied, and is reported
+    /// as a `MutationOutcome::ConflictDetected` while the other mutations are committed.
+    pub async fn commit(&mut self) -> Result<Vec<MutationOutcome>, Error> {
+        let targets = self.targets.clone();
+        let kind = mutations_kind(&self.commit_request.mutations).to_string();
+        let audit = self.client.audit_log(&self.commit_request.mutations);
+        let request = self.client.construct_request(self.commit_request.to_owned()).await?;
+        let rpc = self.client.rpc(Rpc::Commit, &request).kind(&kind);
+        let mut service = self.client.service.clone();
+        let results = rpc.run(service.commit(request)).await?.into_inner().mutation_results;
+        self.client.report_audit(audit, true, &results);
 
-        Ok(keys)
+        mutation_outcomes(targets, results)
     }
 
     /// Execute transaction rollback
@@ -192,8 +312,75 @@ impl Transaction {
                 transaction: self.tx_key.to_vec(),
             })
             .await?;
-        self.client.service.rollback(request).await?;
+        let rpc = self.client.rpc(Rpc::Rollback, &request);
+        let mut service = self.client.service.clone();
+        rpc.run(service.rollback(request)).await?;
 
         Ok(())
     }
 }
+
+/// A transaction which is rolled back when dropped without being committed.
+///
+/// It gives access to the methods of the transaction it wraps, so that an early return
+/// (like with `?`) does not leave the transaction open on the server until it expires.
+/// The rollback is sent in the background, which requires a running Tokio runtime.
+///
+/// ```no_run
+/// # use google_cloud::datastore::{Client, Key, TrxOption};
+/// # async fn run(client: Client, key: Key) -> Result<(), google_cloud::datastore::Error> {
+/// let mut tx = client.new_transaction(TrxOption::ReadWrite, None).await?.into_guard();
+/// let count = tx.get::<i64, _>(&key).await?.unwrap_or(0);
+/// tx.put((key, count + 1)).await?;
+/// tx.commit().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TransactionGuard {
+    transaction: Option<Transaction>,
+}
+
+impl TransactionGuard {
+    /// Commits the transaction, which will not be rolled back anymore.
+    pub async fn commit(mut self) -> Result<Vec<MutationOutcome>, Error> {
+        let mut transaction = self.transaction.take().unwrap();
+        transaction.commit().await
+    }
+
+    /// Rolls the transaction back right away.
+    pub async fn rollback(mut self) -> Result<(), Error> {
+        let mut transaction = self.transaction.take().unwrap();
+        transaction.rollback().await
+    }
+
+    /// Releases the transaction, which will not be rolled back on drop anymore.
+    pub fn into_inner(mut self) -> Transaction {
+        self.transaction.take().unwrap()
+    }
+}
+
+impl Deref for TransactionGuard {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        self.transaction.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for TransactionGuard {
+    fn deref_mut(&mut self) -> &mut Transaction {
+        self.transaction.as_mut().unwrap()
+    }
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        if let Some(mut transaction) = self.transaction.take() {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let _ = transaction.rollback().await;
+                });
+            }
+        }
+    }
+}
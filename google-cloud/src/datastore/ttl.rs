@@ -0,0 +1,18 @@
+use chrono::{NaiveDateTime, Utc};
+
+/// Trait for entities holding an expiration time, like the property of a Datastore TTL policy.
+///
+/// Until the server purges them, expired entities are still returned by reads:
+/// the `*_unexpired` methods of the `Client` use this trait to filter them out.
+///
+/// It is implemented by `#[derive(FromValue)]` for structs having a field marked with
+/// `#[datastore(ttl)]`, either a `NaiveDateTime` or an `Option<NaiveDateTime>` (in UTC).
+pub trait Expiring {
+    /// The time at which the entity expires, if any.
+    fn expires_at(&self) -> Option<NaiveDateTime>;
+
+    /// Has the entity expired ?
+    fn is_expired(&self) -> bool {
+        self.expires_at().is_some_and(|at| at <= Utc::now().naive_utc())
+    }
+}
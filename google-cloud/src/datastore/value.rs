@@ -1,20 +1,31 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
 use std::iter::FromIterator;
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
 
 #[cfg(feature = "bytes")]
 use bytes::Bytes;
 
+use crate::datastore::api;
 use crate::datastore::api::value::ValueType;
 use crate::datastore::Key;
 use crate::error::ConvertError;
 
 #[cfg(feature = "datastore-derive")]
 #[doc(hidden)]
-pub use google_cloud_derive::{FromValue, IntoValue};
+pub use google_cloud_derive::{FromValue, IntoValue, Projection, TryIntoValue};
 
 /// A value, as stored in Datastore.
+///
+/// With the `serde` feature, values are (de)serialized as maps with a single entry named after
+/// their type: `{"null": null}`, `{"boolean": true}`, `{"integer": 42}`, `{"double": 1.5}`,
+/// `{"timestamp": "2024-05-01T12:00:00Z"}` (RFC 3339, in UTC), `{"key": {..}}` (see `Key`),
+/// `{"string": "john"}`, `{"blob": "AQID"}` (standard base64),
+/// `{"geo_point": {"latitude": 48.85, "longitude": 2.35}}`,
+/// `{"entity": {"name": {"string": "john"}}}` and `{"array": [{"integer": 1}]}`.
+/// An `OptionValue` holding a value is represented as that value.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     /// NULL
@@ -58,6 +69,74 @@ impl Value {
             Value::ArrayValue(_) => "array",
         }
     }
+
+    /// Gets a nested value from its path: property names separated by dots,
+    /// each one optionally followed by array indices.
+    ///
+    /// ```
+    /// # use google_cloud::datastore::{IntoValue, Value};
+    /// # use std::collections::HashMap;
+    /// let item = HashMap::from([("sku".to_string(), "ZX-42".into_value())]);
+    /// let order = HashMap::from([("items".to_string(), vec![item].into_value())]).into_value();
+    /// assert_eq!(order.at_path("items[0].sku").unwrap(), &"ZX-42".into_value());
+    /// ```
+    pub fn at_path(&self, path: &str) -> Result<&Value, ConvertError> {
+        let invalid = || ConvertError::InvalidPath(String::from(path));
+        let unexpected = |expected: &str, got: &Value| ConvertError::UnexpectedPropertyType {
+            expected: String::from(expected),
+            got: String::from(got.type_name()),
+        };
+
+        let mut current = self;
+        //? The path traversed so far, to report which part is missing.
+        let mut traversed = String::new();
+        for segment in path.split('.') {
+            let (name, mut indices) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            if !traversed.is_empty() {
+                traversed.push('.');
+            }
+            traversed.push_str(name);
+            current = match current.without_option() {
+                Value::EntityValue(properties) => properties
+                    .get(name)
+                    .ok_or_else(|| ConvertError::MissingProperty(traversed.clone()))?,
+                other => return Err(unexpected("entity", other)),
+            };
+
+            while let Some(rest) = indices.strip_prefix('[') {
+                let (index, rest) = rest.split_once(']').ok_or_else(invalid)?;
+                let index: usize = index.parse().map_err(|_| invalid())?;
+                traversed.push_str(&format!("[{}]", index));
+                current = match current.without_option() {
+                    Value::ArrayValue(values) => values
+                        .get(index)
+                        .ok_or_else(|| ConvertError::MissingProperty(traversed.clone()))?,
+                    other => return Err(unexpected("array", other)),
+                };
+                indices = rest;
+            }
+            if !indices.is_empty() {
+                return Err(invalid());
+            }
+        }
+
+        Ok(current)
+    }
+
+    /// Gets and converts a nested value from its path (see `Value::at_path`).
+    pub fn get_path<T: FromValue>(&self, path: &str) -> Result<T, ConvertError> {
+        T::from_value(self.at_path(path)?.clone())
+    }
+
+    fn without_option(&self) -> &Value {
+        match self {
+            Value::OptionValue(Some(value)) => value.without_option(),
+            value => value,
+        }
+    }
 }
 
 /// Trait for converting a type to a Datastore value.
@@ -66,6 +145,94 @@ pub trait IntoValue {
     fn into_value(self) -> Value;
 }
 
+/// Trait for converting a type to a Datastore value, when the conversion can fail
+/// (an integer out of the range of `i64` for instance).
+///
+/// Every `IntoValue` type is also a `TryIntoValue` one, whose conversion never fails:
+/// a type derives one or the other, not both. Containers convert their elements with
+/// `IntoValue`, wrap them in `TryEach` to convert them with `TryIntoValue`.
+/// Writes go through this trait, so failed conversions are reported as `Error::Convert`
+/// before any request is sent.
+///
+/// ```
+/// # use google_cloud::datastore::{IntoValue, TryIntoValue, Value};
+/// assert_eq!(42u64.try_into_value().unwrap(), Value::IntegerValue(42));
+/// assert!(u64::MAX.try_into_value().is_err());
+/// assert_eq!("john".try_into_value().unwrap(), "john".into_value());
+/// ```
+pub trait TryIntoValue {
+    /// Attempts to convert the type to a Datastore value.
+    fn try_into_value(self) -> Result<Value, ConvertError>;
+}
+
+impl<T: IntoValue> TryIntoValue for T {
+    fn try_into_value(self) -> Result<Value, ConvertError> {
+        Ok(self.into_value())
+    }
+}
+
+macro_rules! try_into_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl TryIntoValue for $ty {
+                fn try_into_value(self) -> Result<Value, ConvertError> {
+                    let value = i64::try_from(self).map_err(|_| {
+                        ConvertError::OutOfRange(format!("{} as a 64 bits integer", self))
+                    })?;
+                    Ok(Value::IntegerValue(value))
+                }
+            }
+        )*
+    };
+}
+
+try_into_integer!(u64, usize, u128, i128);
+
+/// Conversion adapter for containers whose elements can fail to convert.
+///
+/// Containers convert through `IntoValue`, so their elements must convert infallibly:
+/// wrapped in `TryEach`, an `Option`, a `Vec` or a map converts each of its elements
+/// with `TryIntoValue` instead, and fails with the first one that fails.
+/// Fields of types deriving `TryIntoValue` are wrapped with `#[datastore(try_each)]`.
+///
+/// ```
+/// # use google_cloud::datastore::{IntoValue, TryEach, TryIntoValue};
+/// assert_eq!(TryEach(vec![1u64, 2]).try_into_value().unwrap(), vec![1, 2].into_value());
+/// assert!(TryEach(Some(u64::MAX)).try_into_value().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryEach<C>(pub C);
+
+impl<T> TryIntoValue for TryEach<Option<T>>
+where
+    T: TryIntoValue,
+{
+    fn try_into_value(self) -> Result<Value, ConvertError> {
+        let value = self.0.map(TryIntoValue::try_into_value).transpose()?;
+        Ok(Value::OptionValue(value.map(Box::new)))
+    }
+}
+
+impl<T> TryIntoValue for TryEach<Vec<T>>
+where
+    T: TryIntoValue,
+{
+    fn try_into_value(self) -> Result<Value, ConvertError> {
+        let values = self.0.into_iter().map(TryIntoValue::try_into_value);
+        Ok(Value::ArrayValue(values.collect::<Result<_, _>>()?))
+    }
+}
+
+impl<T> TryIntoValue for TryEach<HashMap<String, T>>
+where
+    T: TryIntoValue,
+{
+    fn try_into_value(self) -> Result<Value, ConvertError> {
+        let props = self.0.into_iter().map(|(k, v)| Ok((k, v.try_into_value()?)));
+        Ok(Value::EntityValue(props.collect::<Result<_, ConvertError>>()?))
+    }
+}
+
 /// Trait for mapping a Datastore value to a type.
 pub trait FromValue: Sized {
     /// Attempts to construct a value of this type from the passed Datastore value.
@@ -90,6 +257,30 @@ impl IntoValue for &str {
     }
 }
 
+impl IntoValue for Cow<'_, str> {
+    fn into_value(self) -> Value {
+        self.into_owned().into_value()
+    }
+}
+
+impl IntoValue for u8 {
+    fn into_value(self) -> Value {
+        Value::IntegerValue(self as i64)
+    }
+}
+
+impl IntoValue for u16 {
+    fn into_value(self) -> Value {
+        Value::IntegerValue(self as i64)
+    }
+}
+
+impl IntoValue for u32 {
+    fn into_value(self) -> Value {
+        Value::IntegerValue(self as i64)
+    }
+}
+
 impl IntoValue for i8 {
     fn into_value(self) -> Value {
         Value::IntegerValue(self as i64)
@@ -144,15 +335,29 @@ impl IntoValue for NaiveDateTime {
     }
 }
 
+/// Timezone-aware timestamps are stored in UTC, their offset is not kept.
+impl<Tz: TimeZone> IntoValue for DateTime<Tz> {
+    fn into_value(self) -> Value {
+        Value::TimestampValue(self.naive_utc())
+    }
+}
+
+#[cfg(feature = "time")]
+impl IntoValue for time::OffsetDateTime {
+    fn into_value(self) -> Value {
+        //? `time` supports a narrower range of dates than `chrono`.
+        let time = DateTime::from_timestamp(self.unix_timestamp(), self.nanosecond())
+            .expect("`time` dates are within the range of `chrono`");
+        Value::TimestampValue(time.naive_utc())
+    }
+}
+
 impl<T> IntoValue for Option<T>
 where
     T: IntoValue,
 {
     fn into_value(self) -> Value {
-        Value::OptionValue(match self {
-            Some(x) => Some(Box::new(x.into_value())),
-            None => None,
-        })
+        Value::OptionValue(self.map(|x| Box::new(x.into_value())))
     }
 }
 
@@ -181,6 +386,42 @@ where
     }
 }
 
+impl<T> IntoValue for BTreeMap<String, T>
+where
+    T: IntoValue,
+{
+    fn into_value(self) -> Value {
+        Value::EntityValue(self.into_iter().map(|(k, v)| (k, v.into_value())).collect())
+    }
+}
+
+/// Sets are stored as arrays, in the iteration order of the set.
+impl<T> IntoValue for HashSet<T>
+where
+    T: IntoValue,
+{
+    fn into_value(self) -> Value {
+        Value::ArrayValue(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+/// Sets are stored as arrays, in ascending order.
+impl<T> IntoValue for BTreeSet<T>
+where
+    T: IntoValue,
+{
+    fn into_value(self) -> Value {
+        Value::ArrayValue(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+/// Fixed-size byte arrays (hashes, identifiers...) are stored as blobs.
+impl<const N: usize> IntoValue for [u8; N] {
+    fn into_value(self) -> Value {
+        Value::BlobValue(self.to_vec())
+    }
+}
+
 impl<T> FromIterator<T> for Value
 where
     T: IntoValue,
@@ -223,6 +464,24 @@ impl FromValue for i64 {
     }
 }
 
+/// The integer types other than `i64` check that stored values fit their range.
+macro_rules! from_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromValue for $ty {
+                fn from_value(value: Value) -> Result<$ty, ConvertError> {
+                    let value = i64::from_value(value)?;
+                    <$ty>::try_from(value).map_err(|_| {
+                        ConvertError::OutOfRange(format!("{} as a `{}`", value, stringify!($ty)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, i128, u8, u16, u32, u64, u128, usize);
+
 impl FromValue for f64 {
     fn from_value(value: Value) -> Result<f64, ConvertError> {
         match value {
@@ -235,6 +494,18 @@ impl FromValue for f64 {
     }
 }
 
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Result<f32, ConvertError> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl FromValue for Cow<'_, str> {
+    fn from_value(value: Value) -> Result<Self, ConvertError> {
+        String::from_value(value).map(Cow::Owned)
+    }
+}
+
 impl FromValue for bool {
     fn from_value(value: Value) -> Result<bool, ConvertError> {
         match value {
@@ -271,6 +542,30 @@ impl FromValue for NaiveDateTime {
     }
 }
 
+impl FromValue for DateTime<Utc> {
+    fn from_value(value: Value) -> Result<DateTime<Utc>, ConvertError> {
+        NaiveDateTime::from_value(value).map(|time| time.and_utc())
+    }
+}
+
+/// The timestamps are read with a zero offset, as they are stored in UTC.
+impl FromValue for DateTime<FixedOffset> {
+    fn from_value(value: Value) -> Result<DateTime<FixedOffset>, ConvertError> {
+        DateTime::<Utc>::from_value(value).map(|time| time.fixed_offset())
+    }
+}
+
+#[cfg(feature = "time")]
+impl FromValue for time::OffsetDateTime {
+    fn from_value(value: Value) -> Result<time::OffsetDateTime, ConvertError> {
+        let time = DateTime::<Utc>::from_value(value)?;
+        let out_of_range = || ConvertError::OutOfRange(format!("{} as an `OffsetDateTime`", time));
+        time::OffsetDateTime::from_unix_timestamp(time.timestamp())
+            .and_then(|converted| converted.replace_nanosecond(time.timestamp_subsec_nanos()))
+            .map_err(|_| out_of_range())
+    }
+}
+
 impl<T> FromValue for Option<T>
 where
     T: FromValue,
@@ -341,6 +636,75 @@ where
     }
 }
 
+impl<T> FromValue for BTreeMap<String, T>
+where
+    T: FromValue,
+{
+    fn from_value(value: Value) -> Result<BTreeMap<String, T>, ConvertError> {
+        let values: HashMap<String, T> = FromValue::from_value(value)?;
+        Ok(values.into_iter().collect())
+    }
+}
+
+/// Duplicate elements of the stored array are merged.
+impl<T> FromValue for HashSet<T>
+where
+    T: FromValue + Eq + Hash,
+{
+    fn from_value(value: Value) -> Result<HashSet<T>, ConvertError> {
+        let values: Vec<T> = FromValue::from_value(value)?;
+        Ok(values.into_iter().collect())
+    }
+}
+
+/// Duplicate elements of the stored array are merged.
+impl<T> FromValue for BTreeSet<T>
+where
+    T: FromValue + Ord,
+{
+    fn from_value(value: Value) -> Result<BTreeSet<T>, ConvertError> {
+        let values: Vec<T> = FromValue::from_value(value)?;
+        Ok(values.into_iter().collect())
+    }
+}
+
+impl<const N: usize> FromValue for [u8; N] {
+    fn from_value(value: Value) -> Result<[u8; N], ConvertError> {
+        match value {
+            Value::BlobValue(bytes) => <[u8; N]>::try_from(bytes).map_err(|bytes| {
+                ConvertError::OutOfRange(format!(
+                    "a blob of {} bytes as a `[u8; {}]`",
+                    bytes.len(),
+                    N
+                ))
+            }),
+            _ => Err(ConvertError::UnexpectedPropertyType {
+                expected: String::from("blob"),
+                got: String::from(value.type_name()),
+            }),
+        }
+    }
+}
+
+/// The meaning Datastore flags the timestamps of projection results with, as they are
+/// returned as integers (microseconds since the epoch) rather than timestamps.
+const MEANING_GD_WHEN: i32 = 18;
+
+impl From<api::Value> for Value {
+    fn from(value: api::Value) -> Value {
+        match value.value_type {
+            Some(ValueType::IntegerValue(micros)) if value.meaning == MEANING_GD_WHEN => {
+                match DateTime::from_timestamp_micros(micros) {
+                    Some(time) => Value::TimestampValue(time.naive_utc()),
+                    None => Value::IntegerValue(micros),
+                }
+            }
+            Some(value_type) => Value::from(value_type),
+            None => Value::OptionValue(None),
+        }
+    }
+}
+
 impl From<ValueType> for Value {
     fn from(value: ValueType) -> Value {
         match value {
@@ -349,22 +713,18 @@ impl From<ValueType> for Value {
             ValueType::IntegerValue(val) => Value::IntegerValue(val),
             ValueType::DoubleValue(val) => Value::DoubleValue(val),
             ValueType::TimestampValue(val) => Value::TimestampValue(
-                NaiveDateTime::from_timestamp_opt(val.seconds, val.nanos as u32).unwrap(),
+                DateTime::from_timestamp(val.seconds, val.nanos as u32).unwrap().naive_utc(),
             ),
             ValueType::KeyValue(key) => Value::KeyValue(Key::from(key)),
             ValueType::StringValue(val) => Value::StringValue(val),
             ValueType::BlobValue(val) => Value::BlobValue(val),
             ValueType::GeoPointValue(val) => Value::GeoPointValue(val.latitude, val.longitude),
             ValueType::EntityValue(entity) => Value::EntityValue({
-                entity
-                    .properties
-                    .into_iter()
-                    .map(|(k, v)| (k, Value::from(v.value_type.unwrap())))
-                    .collect()
+                entity.properties.into_iter().map(|(k, v)| (k, Value::from(v))).collect()
             }),
-            ValueType::ArrayValue(seq) => Value::ArrayValue(
-                seq.values.into_iter().map(|val| Value::from(val.value_type.unwrap())).collect(),
-            ),
+            ValueType::ArrayValue(seq) => {
+                Value::ArrayValue(seq.values.into_iter().map(Value::from).collect())
+            }
         }
     }
 }
@@ -1,14 +1,19 @@
 use std::env;
 use std::io;
+use std::path::PathBuf;
 
 use thiserror::Error;
+use tonic::Code;
 
 /// The main error-handling type.
+///
+/// The statuses returned by GCP services are classified by code into their own variants
+/// (`NotFound`, `Aborted`, `Unavailable`...), only the others are kept as `Status`.
 #[derive(Debug, Error)]
 pub enum Error {
     /// An unexpected status code was received.
     #[error("unexpected status from GCP: {0}")]
-    Status(#[from] tonic::Status),
+    Status(tonic::Status),
     /// An error with the gRPC transport channel.
     #[error("transport error: {0}")]
     Transport(#[from] tonic::transport::Error),
@@ -31,9 +36,117 @@ pub enum Error {
     /// authentication-related error.
     #[error("authentication error: {0}")]
     Auth(#[from] AuthError),
+    /// The application credentials could not be loaded.
+    #[error("credentials error: {0}")]
+    Credentials(#[from] CredentialsError),
+    /// A protobuf decoding error.
+    #[error("protobuf decoding error: {0}")]
+    Decode(#[from] prost::DecodeError),
     /// A YAML (de)serialization error.
     #[error("YAML error: {0}")]
     YAML(#[from] serde_yaml::Error),
+    /// The resource to create already exists (such as an inserted entity).
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+    /// The resource does not exist (such as an updated entity).
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// The request is not valid, whatever the state of the resources.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// The resources are not in the state the request requires (such as a missing index).
+    #[error("failed precondition: {0}")]
+    FailedPrecondition(String),
+    /// The operation was aborted, typically by a concurrency conflict between transactions.
+    #[error("aborted: {message}")]
+    Aborted {
+        /// Whether retrying the same request may succeed: it does not for
+        /// transactions which expired or are no longer valid.
+        retryable: bool,
+        /// The message of the status.
+        message: String,
+    },
+    /// The caller is not allowed to perform the request.
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    /// The request does not carry valid credentials.
+    #[error("unauthenticated: {0}")]
+    Unauthenticated(String),
+    /// A quota or a limit was exhausted.
+    #[error("resource exhausted: {0}")]
+    ResourceExhausted(String),
+    /// The deadline of the request expired before it completed.
+    #[error("deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+    /// The service is unavailable, usually for a short while.
+    #[error("service unavailable: {0}")]
+    Unavailable(String),
+    /// A conditional mutation was not applied because the stored entity changed.
+    #[error("conflict detected: the entity changed since the expected version")]
+    ConflictDetected,
+}
+
+impl Error {
+    /// The gRPC status code of the error, if it was returned by a GCP service.
+    pub fn code(&self) -> Option<Code> {
+        let code = match self {
+            Error::Status(status) => status.code(),
+            Error::AlreadyExists(_) => Code::AlreadyExists,
+            Error::NotFound(_) => Code::NotFound,
+            Error::InvalidArgument(_) => Code::InvalidArgument,
+            Error::FailedPrecondition(_) => Code::FailedPrecondition,
+            Error::Aborted { .. } => Code::Aborted,
+            Error::PermissionDenied(_) => Code::PermissionDenied,
+            Error::Unauthenticated(_) => Code::Unauthenticated,
+            Error::ResourceExhausted(_) => Code::ResourceExhausted,
+            Error::DeadlineExceeded(_) => Code::DeadlineExceeded,
+            Error::Unavailable(_) => Code::Unavailable,
+            _ => return None,
+        };
+        Some(code)
+    }
+
+    /// Whether the request may succeed if sent again (after a backoff).
+    ///
+    /// These are the transient failures: aborts, unavailability, exceeded deadlines,
+    /// exhausted quotas, internal errors of the service and transport errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Aborted { retryable, .. } => *retryable,
+            Error::Unavailable(_)
+            | Error::DeadlineExceeded(_)
+            | Error::ResourceExhausted(_)
+            | Error::Transport(_) => true,
+            Error::Status(status) => status.code() == Code::Internal,
+            _ => false,
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Error {
+        let message = || status.message().to_string();
+        match status.code() {
+            Code::AlreadyExists => Error::AlreadyExists(message()),
+            Code::NotFound => Error::NotFound(message()),
+            Code::InvalidArgument => Error::InvalidArgument(message()),
+            Code::FailedPrecondition => Error::FailedPrecondition(message()),
+            Code::Aborted => {
+                //? Datastore aborts transactions which expired with this same code, and
+                //? committing them again is bound to fail.
+                let expired = ["expired", "no longer valid"]
+                    .iter()
+                    .any(|pattern| status.message().contains(pattern));
+                Error::Aborted { retryable: !expired, message: message() }
+            }
+            Code::PermissionDenied => Error::PermissionDenied(message()),
+            Code::Unauthenticated => Error::Unauthenticated(message()),
+            Code::ResourceExhausted => Error::ResourceExhausted(message()),
+            Code::DeadlineExceeded => Error::DeadlineExceeded(message()),
+            Code::Unavailable => Error::Unavailable(message()),
+            _ => Error::Status(status),
+        }
+    }
 }
 
 /// The error type for value conversions.
@@ -53,6 +166,69 @@ pub enum ConvertError {
         /// The name of the actual encountered type.
         got: String,
     },
+    /// An enum value named a variant that does not exist.
+    #[error("unknown enum variant `{0}`")]
+    UnknownVariant(String),
+    /// A key path is not valid.
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    /// A value does not fit the range of the target type.
+    #[error("value out of range: {0}")]
+    OutOfRange(String),
+    /// A property path (such as `items[0].sku`) is malformed.
+    #[error("invalid property path `{0}`")]
+    InvalidPath(String),
+    /// A type could not be converted through serde (see `datastore::to_value`).
+    #[error("serde conversion error: {0}")]
+    Serde(String),
+    /// A double is NaN or infinite, and the client rejects them (see `NonFinitePolicy`).
+    #[error("property `{0}` is a non-finite double")]
+    NonFiniteDouble(String),
+}
+
+/// The error type for application credentials loading errors.
+#[derive(Debug, Error)]
+pub enum CredentialsError {
+    /// The environment variable pointing to the credentials is not set.
+    #[error(
+        "`{0}` is not set: point it to a service account key file, \
+         or put its contents in `GOOGLE_APPLICATION_CREDENTIALS_JSON` \
+         (on GKE, use workload identity instead)"
+    )]
+    MissingEnv(String),
+    /// The environment variable pointing to the credentials is not valid unicode.
+    #[error("`{0}` is not valid unicode")]
+    InvalidEnv(String),
+    /// The credentials file does not exist.
+    #[error(
+        "credentials file `{}` does not exist: check `GOOGLE_APPLICATION_CREDENTIALS`",
+        .0.display()
+    )]
+    NotFound(PathBuf),
+    /// The credentials are not a valid service account key or authorized user file.
+    #[error("credentials are not a valid service account key or authorized user file: {0}")]
+    InvalidJson(json::Error),
+    /// The credentials file could not be read.
+    #[error("credentials file `{}` could not be read: {source}", .path.display())]
+    Unreadable {
+        /// The path of the credentials file.
+        path: PathBuf,
+        /// The underlying IO error.
+        source: io::Error,
+    },
+    /// The credentials file is not a valid service account key or authorized user file.
+    #[error(
+        "credentials file `{}` is not a valid service account key or authorized user file \
+         (download a key from the IAM console, or run `gcloud auth application-default login`): \
+         {source}",
+        .path.display()
+    )]
+    Invalid {
+        /// The path of the credentials file.
+        path: PathBuf,
+        /// The underlying JSON error.
+        source: json::Error,
+    },
 }
 
 /// The error type for authentication-related errors.
@@ -70,4 +246,18 @@ pub enum AuthError {
     /// Hyper errors
     #[error("Hyper error: {0}")]
     Hyper(#[from] hyper::Error),
+    /// A custom token provider failed to provide a token.
+    #[error("token provider error: {0}")]
+    Provider(Box<dyn std::error::Error + Send + Sync>),
+    /// The token endpoint rejected the request.
+    #[error("token endpoint responded with `{status}`: {body}")]
+    Endpoint {
+        /// The status of the response.
+        status: http::StatusCode,
+        /// The body of the response.
+        body: String,
+    },
+    /// A value could not be sent in the named header (the value is left out, as it can be a secret).
+    #[error("invalid value for the `{0}` header")]
+    InvalidHeader(&'static str),
 }
@@ -1,10 +1,18 @@
 #![warn(missing_docs)]
+#![allow(clippy::result_large_err)]
 //! Asynchronous Rust bindings for Google Cloud Platform gRPC APIs.
 
 #[cfg(feature = "google-cloud-derive")]
 extern crate google_cloud_derive;
 
 /// Authorization/authentication related utilities.
+///
+/// Clients created without explicit credentials look them up in the environment, in order:
+/// - the credentials file pointed to by `GOOGLE_APPLICATION_CREDENTIALS`,
+/// - the credentials held by `GOOGLE_APPLICATION_CREDENTIALS_JSON`,
+/// - the credentials written by `gcloud auth application-default login`,
+/// - the metadata server, which makes workload identity work on GKE and Cloud Run
+///   without mounting key files.
 pub mod authorize;
 /// Error handling utilities.
 pub mod error;
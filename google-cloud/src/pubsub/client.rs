@@ -1,12 +1,12 @@
-use std::env;
-use std::fs::File;
 use std::sync::Arc;
 
-use tokio::sync::Mutex;
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
 use tonic::{IntoRequest, Request};
 
-use crate::authorize::{ApplicationCredentials, TokenManager, TLS_CERTS};
+use crate::authorize::{
+    authorize_request, ApplicationCredentials, ImpersonatedCredentials, TokenCache, TokenManager,
+    TokenProvider, TokenSource, TLS_CERTS,
+};
 use crate::pubsub::api;
 use crate::pubsub::api::publisher_client::PublisherClient;
 use crate::pubsub::api::subscriber_client::SubscriberClient;
@@ -18,7 +18,7 @@ pub struct Client {
     pub(crate) project_name: String,
     pub(crate) publisher: PublisherClient<Channel>,
     pub(crate) subscriber: SubscriberClient<Channel>,
-    pub(crate) token_manager: Arc<Mutex<TokenManager>>,
+    pub(crate) token_provider: Arc<dyn TokenProvider>,
 }
 
 impl Client {
@@ -34,27 +34,45 @@ impl Client {
         request: T,
     ) -> Result<Request<T>, Error> {
         let mut request = request.into_request();
-        let token = self.token_manager.lock().await.token().await?;
-        let metadata = request.metadata_mut();
-        metadata.insert("authorization", token.parse().unwrap());
+        authorize_request(self.token_provider.as_ref(), &mut request).await?;
         Ok(request)
     }
 
     /// Create a new client for the specified project.
     ///
-    /// Credentials are looked up in the `GOOGLE_APPLICATION_CREDENTIALS` environment variable.
+    /// Credentials are looked up in the environment, as described in the
+    /// [`authorize`](crate::authorize) module.
     pub async fn new(project_name: impl Into<String>) -> Result<Client, Error> {
-        let path = env::var("GOOGLE_APPLICATION_CREDENTIALS")?;
-        let file = File::open(path)?;
-        let creds = json::from_reader(file)?;
+        let token_manager = TokenManager::from_env(Client::SCOPES.as_ref())?;
 
-        Client::from_credentials(project_name, creds).await
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
     }
 
     /// Create a new client for the specified project with custom credentials.
     pub async fn from_credentials(
         project_name: impl Into<String>,
         creds: ApplicationCredentials,
+    ) -> Result<Client, Error> {
+        let token_manager = TokenManager::new(creds, Client::SCOPES.as_ref());
+
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
+    }
+
+    /// Create a new client for the specified project, impersonating a service account.
+    pub async fn from_impersonated_credentials(
+        project_name: impl Into<String>,
+        creds: ImpersonatedCredentials,
+    ) -> Result<Client, Error> {
+        let source = TokenSource::Impersonated(creds);
+        let token_manager = TokenManager::from_source(source, Client::SCOPES.as_ref());
+
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
+    }
+
+    /// Create a new client for the specified project, authenticated by a custom token provider.
+    pub async fn from_token_provider(
+        project_name: impl Into<String>,
+        token_provider: impl TokenProvider + 'static,
     ) -> Result<Client, Error> {
         let tls_config = ClientTlsConfig::new()
             .ca_certificate(Certificate::from_pem(TLS_CERTS))
@@ -69,10 +87,7 @@ impl Client {
             project_name: project_name.into(),
             publisher: PublisherClient::new(channel.clone()),
             subscriber: SubscriberClient::new(channel),
-            token_manager: Arc::new(Mutex::new(TokenManager::new(
-                creds,
-                Client::SCOPES.as_ref(),
-            ))),
+            token_provider: Arc::new(token_provider),
         })
     }
 
@@ -1,5 +1,6 @@
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
+use crate::authorize::authorization;
 use crate::storage::api::object::ObjectResource;
 use crate::storage::{Client, Error, Object};
 
@@ -39,7 +40,7 @@ impl Bucket {
         );
 
         let data = data.into();
-        let token = client.token_manager.lock().await.token().await?;
+        let token = authorization(client.token_provider.as_ref()).await?;
         let request = inner
             .post(uri.as_str())
             .query(&[("uploadType", "media"), ("name", name)])
@@ -70,7 +71,7 @@ impl Bucket {
             utf8_percent_encode(name, NON_ALPHANUMERIC),
         );
 
-        let token = client.token_manager.lock().await.token().await?;
+        let token = authorization(client.token_provider.as_ref()).await?;
         let request = inner
             .get(uri.as_str())
             .header("authorization", token)
@@ -96,7 +97,7 @@ impl Bucket {
             utf8_percent_encode(&self.name, NON_ALPHANUMERIC),
         );
 
-        let token = client.token_manager.lock().await.token().await?;
+        let token = authorization(client.token_provider.as_ref()).await?;
         let request = inner
             .delete(uri.as_str())
             .header("authorization", token)
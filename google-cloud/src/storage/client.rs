@@ -1,12 +1,13 @@
-use std::env;
-use std::fs::File;
 use std::sync::Arc;
 
 use json::json;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
-use tokio::sync::Mutex;
 
-use crate::authorize::{ApplicationCredentials, TokenManager};
+use crate::authorize::{
+    authorization, ApplicationCredentials, ImpersonatedCredentials, TokenCache, TokenManager,
+    TokenProvider, TokenSource, QUOTA_PROJECT_HEADER,
+};
+use crate::error::AuthError;
 use crate::storage::api::bucket::{BucketResource, BucketResources};
 use crate::storage::{Bucket, Error};
 
@@ -15,7 +16,7 @@ use crate::storage::{Bucket, Error};
 pub struct Client {
     pub(crate) project_name: String,
     pub(crate) client: Arc<reqwest::Client>,
-    pub(crate) token_manager: Arc<Mutex<TokenManager>>,
+    pub(crate) token_provider: Arc<dyn TokenProvider>,
 }
 
 impl Client {
@@ -40,13 +41,12 @@ impl Client {
 
     /// Create a new client for the specified project.
     ///
-    /// Credentials are looked up in the `GOOGLE_APPLICATION_CREDENTIALS` environment variable.
+    /// Credentials are looked up in the environment, as described in the
+    /// [`authorize`](crate::authorize) module.
     pub async fn new(project_name: impl Into<String>) -> Result<Client, Error> {
-        let path = env::var("GOOGLE_APPLICATION_CREDENTIALS")?;
-        let file = File::open(path)?;
-        let creds = json::from_reader(file)?;
+        let token_manager = TokenManager::from_env(Client::SCOPES.as_ref())?;
 
-        Client::from_credentials(project_name, creds).await
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
     }
 
     /// Create a new client for the specified project with custom credentials.
@@ -54,18 +54,45 @@ impl Client {
         project_name: impl Into<String>,
         creds: ApplicationCredentials,
     ) -> Result<Client, Error> {
+        let token_manager = TokenManager::new(creds, Client::SCOPES.as_ref());
+
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
+    }
+
+    /// Create a new client for the specified project, impersonating a service account.
+    pub async fn from_impersonated_credentials(
+        project_name: impl Into<String>,
+        creds: ImpersonatedCredentials,
+    ) -> Result<Client, Error> {
+        let source = TokenSource::Impersonated(creds);
+        let token_manager = TokenManager::from_source(source, Client::SCOPES.as_ref());
+
+        Client::from_token_provider(project_name, TokenCache::new(token_manager)).await
+    }
+
+    /// Create a new client for the specified project, authenticated by a custom token provider.
+    pub async fn from_token_provider(
+        project_name: impl Into<String>,
+        token_provider: impl TokenProvider + 'static,
+    ) -> Result<Client, Error> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(project) = token_provider.quota_project() {
+            let value = project
+                .parse()
+                .map_err(|_| AuthError::InvalidHeader(QUOTA_PROJECT_HEADER))?;
+            headers.insert(QUOTA_PROJECT_HEADER, value);
+        }
+
         // let certificate = reqwest::Certificate::from_pem(TLS_CERTS)?;
         let client = reqwest::Client::builder()
             // .add_root_certificate(certificate)
+            .default_headers(headers)
             .build()?;
 
         Ok(Client {
             client: Arc::new(client),
             project_name: project_name.into(),
-            token_manager: Arc::new(Mutex::new(TokenManager::new(
-                creds,
-                Client::SCOPES.as_ref(),
-            ))),
+            token_provider: Arc::new(token_provider),
         })
     }
 
@@ -78,7 +105,7 @@ impl Client {
             utf8_percent_encode(name, NON_ALPHANUMERIC),
         );
 
-        let token = self.token_manager.lock().await.token().await?;
+        let token = authorization(self.token_provider.as_ref()).await?;
         let request = inner
             .get(uri.as_str())
             .header("authorization", token)
@@ -97,7 +124,7 @@ impl Client {
         let inner = &self.client;
         let uri = format!("{}/b", Client::ENDPOINT);
 
-        let token = self.token_manager.lock().await.token().await?;
+        let token = authorization(self.token_provider.as_ref()).await?;
         let request = inner
             .get(uri.as_str())
             .query(&[("project", self.project_name.as_str())])
@@ -127,7 +154,7 @@ impl Client {
             "kind": "storage#bucket",
             "name": name,
         });
-        let token = self.token_manager.lock().await.token().await?;
+        let token = authorization(self.token_provider.as_ref()).await?;
         let request = inner
             .post(uri.as_str())
             .query(&[("project", self.project_name.as_str())])
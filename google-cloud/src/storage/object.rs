@@ -1,5 +1,6 @@
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
+use crate::authorize::authorization;
 use crate::storage::{Client, Error};
 
 /// Represents a Cloud Storage bucket.
@@ -54,7 +55,7 @@ impl Object {
             utf8_percent_encode(&self.name, NON_ALPHANUMERIC),
         );
 
-        let token = client.token_manager.lock().await.token().await?;
+        let token = authorization(client.token_provider.as_ref()).await?;
         let request = inner
             .get(uri.as_str())
             .query(&[("alt", "media")])
@@ -77,7 +78,7 @@ impl Object {
             utf8_percent_encode(&self.name, NON_ALPHANUMERIC),
         );
 
-        let token = client.token_manager.lock().await.token().await?;
+        let token = authorization(client.token_provider.as_ref()).await?;
         let request = inner
             .delete(uri.as_str())
             .header("authorization", token)
@@ -0,0 +1,291 @@
+use std::fs;
+
+use crate::authorize::{
+    metadata_token_uri, ApplicationCredentials, AuthResponse, AuthorizedUserCredentials,
+    ImpersonatedCredentials, ImpersonationResponse, TokenSource,
+};
+use crate::error::CredentialsError;
+
+#[test]
+fn credentials_errors_name_the_offending_file() {
+    let dir = std::env::temp_dir().join(format!("google-cloud-creds-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    //? A missing file is reported as such, with its path.
+    let missing = dir.join("missing.json");
+    let error = ApplicationCredentials::from_file(&missing).unwrap_err();
+    assert!(matches!(&error, CredentialsError::NotFound(path) if *path == missing));
+    assert!(error.to_string().contains("missing.json"));
+
+    //? So is a file which is not a service account key.
+    let invalid = dir.join("invalid.json");
+    fs::write(&invalid, r#"{ "type": "service_account" }"#).unwrap();
+    let error = ApplicationCredentials::from_file(&invalid).unwrap_err();
+    assert!(matches!(&error, CredentialsError::Invalid { path, .. } if *path == invalid));
+    assert!(error.to_string().contains("invalid.json"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn credentials_errors_for_json_contents() {
+    let error = ApplicationCredentials::from_json_str("not json").unwrap_err();
+    assert!(matches!(error, CredentialsError::InvalidJson(_)));
+}
+
+#[test]
+fn metadata_server_tokens() {
+    let uri = metadata_token_uri("https://a.googleapis.com/x https://b.googleapis.com/y");
+    assert!(uri.starts_with("http://"));
+    assert!(uri.ends_with(
+        "/computeMetadata/v1/instance/service-accounts/default/token\
+         ?scopes=https://a.googleapis.com/x,https://b.googleapis.com/y"
+    ));
+
+    //? Tokens are renewed a minute before the metadata server says they expire.
+    let response: AuthResponse =
+        json::from_str(r#"{ "access_token": "t", "expires_in": 3599, "token_type": "Bearer" }"#)
+            .unwrap();
+    let issued_at = chrono::Utc::now();
+    let expiry = response.expiry(issued_at).unwrap();
+    assert_eq!((expiry - issued_at).num_seconds(), 3539);
+
+    let response: AuthResponse = json::from_str(r#"{ "access_token": "t" }"#).unwrap();
+    assert_eq!(response.expiry(issued_at), None);
+}
+
+#[test]
+fn credentials_are_parsed_according_to_their_type() {
+    let contents = r#"{
+        "type": "authorized_user",
+        "client_id": "id.apps.googleusercontent.com",
+        "client_secret": "secret",
+        "refresh_token": "1//refresh",
+        "quota_project_id": "my-project"
+    }"#;
+    let creds = AuthorizedUserCredentials::from_json_str(contents).unwrap();
+    assert_eq!(creds.refresh_token, "1//refresh");
+    assert_eq!(creds.quota_project_id.as_deref(), Some("my-project"));
+    assert_eq!(
+        TokenSource::parse(contents.as_bytes()).unwrap(),
+        TokenSource::AuthorizedUser(creds)
+    );
+
+    let error = TokenSource::parse(br#"{ "type": "service_account" }"#).unwrap_err();
+    assert!(error.to_string().contains("missing field"));
+    assert!(TokenSource::parse(br#"{ "client_id": "id" }"#).is_err());
+
+    //? Unsupported types are rejected, rather than mistaken for service account keys.
+    let error = TokenSource::parse(br#"{ "type": "external_account" }"#).unwrap_err();
+    assert!(error.to_string().contains("unsupported credentials type `external_account`"));
+}
+
+#[tokio::test]
+async fn quota_projects_are_sent_with_the_requests() {
+    use futures::future::BoxFuture;
+
+    use crate::authorize::{authorize_request, TokenCache, TokenManager, TokenProvider};
+    use crate::error::AuthError;
+
+    struct BilledToken(&'static str);
+
+    impl TokenProvider for BilledToken {
+        fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+            Box::pin(async move { Ok("user-token".to_string()) })
+        }
+
+        fn quota_project(&self) -> Option<String> {
+            Some(self.0.to_string())
+        }
+    }
+
+    let mut request = tonic::Request::new(());
+    authorize_request(&BilledToken("my-project"), &mut request).await.unwrap();
+    assert_eq!(request.metadata().get("authorization").unwrap(), "Bearer user-token");
+    assert_eq!(request.metadata().get("x-goog-user-project").unwrap(), "my-project");
+
+    let mut request = tonic::Request::new(());
+    let error = authorize_request(&BilledToken("my\nproject"), &mut request).await.unwrap_err();
+    assert!(matches!(error, AuthError::InvalidHeader("x-goog-user-project")));
+
+    //? The quota project of user credentials is served by the token cache.
+    let creds = AuthorizedUserCredentials {
+        cred_type: AuthorizedUserCredentials::TYPE.to_string(),
+        client_id: "id.apps.googleusercontent.com".to_string(),
+        client_secret: "secret".to_string(),
+        refresh_token: "1//refresh".to_string(),
+        quota_project_id: Some("my-project".to_string()),
+    };
+    let manager = TokenManager::from_source(TokenSource::AuthorizedUser(creds), &[]);
+    assert_eq!(TokenCache::new(manager).quota_project().as_deref(), Some("my-project"));
+    let manager = TokenManager::from_source(TokenSource::MetadataServer, &[]);
+    assert_eq!(TokenCache::new(manager).quota_project(), None);
+}
+
+#[test]
+fn impersonated_credentials_requests() {
+    let creds =
+        ImpersonatedCredentials::from_metadata_server("target@my-project.iam.gserviceaccount.com")
+            .delegates(["delegate@my-project.iam.gserviceaccount.com"])
+            .lifetime(std::time::Duration::from_secs(600));
+    assert_eq!(
+        creds.token_uri(),
+        "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts/\
+         target@my-project.iam.gserviceaccount.com:generateAccessToken"
+    );
+    assert_eq!(
+        creds.request_body("https://a.googleapis.com/x https://b.googleapis.com/y"),
+        json::json!({
+            "delegates": ["projects/-/serviceAccounts/delegate@my-project.iam.gserviceaccount.com"],
+            "scope": ["https://a.googleapis.com/x", "https://b.googleapis.com/y"],
+            "lifetime": "600s",
+        })
+    );
+
+    let response: ImpersonationResponse =
+        json::from_str(r#"{ "accessToken": "t", "expireTime": "2024-05-01T12:00:00Z" }"#).unwrap();
+    assert_eq!(response.expiry().unwrap().to_rfc3339(), "2024-05-01T11:59:00+00:00");
+}
+
+#[test]
+fn impersonated_credentials_files() {
+    let contents = r#"{
+        "type": "impersonated_service_account",
+        "service_account_impersonation_url": "https://iamcredentials.googleapis.com/v1/projects/-/serviceAccounts/target@my-project.iam.gserviceaccount.com:generateAccessToken",
+        "delegates": [],
+        "source_credentials": {
+            "type": "authorized_user",
+            "client_id": "id",
+            "client_secret": "secret",
+            "refresh_token": "refresh"
+        }
+    }"#;
+    let source = AuthorizedUserCredentials::from_json_str(
+        r#"{ "type": "authorized_user", "client_id": "id", "client_secret": "secret", "refresh_token": "refresh" }"#,
+    )
+    .unwrap();
+    let expected = ImpersonatedCredentials::from_authorized_user(
+        source,
+        "target@my-project.iam.gserviceaccount.com",
+    );
+    assert_eq!(
+        TokenSource::parse(contents.as_bytes()).unwrap(),
+        TokenSource::Impersonated(expected)
+    );
+}
+
+#[tokio::test]
+async fn custom_token_providers_authorize_requests() {
+    use std::sync::Arc;
+
+    use futures::future::BoxFuture;
+
+    use crate::authorize::{authorization, authorize_request, TokenProvider};
+    use crate::error::AuthError;
+
+    struct FixedToken(&'static str);
+
+    impl TokenProvider for FixedToken {
+        fn token(&self) -> BoxFuture<'_, Result<String, AuthError>> {
+            Box::pin(async move { Ok(self.0.to_string()) })
+        }
+    }
+
+    let provider: Arc<dyn TokenProvider> = Arc::new(FixedToken("vault-token"));
+    assert_eq!(authorization(provider.as_ref()).await.unwrap(), "Bearer vault-token");
+    //? Shared providers work as well.
+    assert_eq!(authorization(&Arc::clone(&provider)).await.unwrap(), "Bearer vault-token");
+
+    //? Tokens which cannot be sent in a header are errors, not panics.
+    let mut request = tonic::Request::new(());
+    let error = authorize_request(&FixedToken("vault\ntoken"), &mut request).await.unwrap_err();
+    assert!(matches!(error, AuthError::InvalidHeader("authorization")));
+}
+
+/// Hands out numbered tokens, each valid for `lifetime`.
+struct CountingFetcher {
+    fetches: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    lifetime: chrono::TimeDelta,
+}
+
+impl crate::authorize::FetchToken for CountingFetcher {
+    fn fetch(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<crate::authorize::Token, crate::error::AuthError>> + Send
+    {
+        use std::sync::atomic::Ordering;
+
+        use crate::authorize::{Token, TokenValue};
+
+        let count = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+        let expiry = chrono::Utc::now() + self.lifetime;
+        async move {
+            //? Leaves time for concurrent callers to pile up.
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            Ok(Token { value: TokenValue::Bearer(format!("token-{}", count)), expiry })
+        }
+    }
+}
+
+#[tokio::test]
+async fn token_caches_renew_tokens_once_for_concurrent_callers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::authorize::TokenCache;
+
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let fetcher =
+        CountingFetcher { fetches: Arc::clone(&fetches), lifetime: chrono::TimeDelta::hours(1) };
+    let cache = TokenCache::new(fetcher);
+
+    let tokens = futures::future::join_all((0..10).map(|_| cache.token())).await;
+    assert!(tokens.iter().all(|token| token.as_deref().unwrap() == "token-1"));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    //? Later callers are served from the cache.
+    assert_eq!(cache.token().await.unwrap(), "token-1");
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn token_caches_renew_tokens_ahead_of_expiry() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::authorize::TokenCache;
+
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let lifetime = chrono::TimeDelta::milliseconds(2400);
+    let fetcher = CountingFetcher { fetches: Arc::clone(&fetches), lifetime };
+    let cache = TokenCache::new(fetcher);
+    assert_eq!(cache.token().await.unwrap(), "token-1");
+
+    //? Short-lived tokens are renewed halfway through their lifetime, without any caller waiting.
+    tokio::time::sleep(Duration::from_millis(1800)).await;
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.token().await.unwrap(), "token-2");
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn token_caches_wait_between_renewals_of_expiring_tokens() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::authorize::TokenCache;
+
+    //? Tokens obtained within their expiry margin must not make the cache hammer the endpoint.
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let lifetime = chrono::TimeDelta::milliseconds(100);
+    let fetcher = CountingFetcher { fetches: Arc::clone(&fetches), lifetime };
+    let cache = TokenCache::new(fetcher);
+    assert_eq!(cache.token().await.unwrap(), "token-1");
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
@@ -48,3 +48,623 @@
 //     //? Delete that value from Datastore.
 //     assert_ok!(client.delete(key).await);
 // }
+
+use crate::datastore;
+
+#[test]
+fn datastore_lookup_batches_follow_response_sizes() {
+    const MIB: usize = 1024 * 1024;
+
+    //? Small responses let the batch grow, twice as big at most.
+    assert_eq!(datastore::next_lookup_batch_size(10, 10 * 1024, 4 * MIB), 20);
+    //? It never goes over the lookup limit.
+    assert_eq!(datastore::next_lookup_batch_size(800, 800 * 1024, 4 * MIB), 1000);
+    //? Near 1MiB entities shrink it to what fits in the budget.
+    assert_eq!(datastore::next_lookup_batch_size(1000, 1000 * MIB, 4 * MIB), 3);
+    //? And it always asks for at least one key.
+    assert_eq!(datastore::next_lookup_batch_size(4, 40 * MIB, 4 * MIB), 1);
+}
+
+#[test]
+fn datastore_only_size_limits_shrink_lookup_batches() {
+    use tonic::Status;
+
+    let too_large = Status::resource_exhausted("Received message larger than max (5 vs. 4)");
+    assert!(datastore::is_message_too_large(&too_large));
+    let too_large = Status::out_of_range(
+        "Error, message length too large: found 5 bytes, the limit is: 4 bytes",
+    );
+    assert!(datastore::is_message_too_large(&too_large));
+
+    //? Running out of quota is not a size issue.
+    let quota = Status::resource_exhausted("Quota exceeded for quota metric 'Read requests'");
+    assert!(!datastore::is_message_too_large(&quota));
+    let other = Status::internal("entity too large to index");
+    assert!(!datastore::is_message_too_large(&other));
+}
+
+#[test]
+fn datastore_commits_are_chunked_in_order() {
+    let items: Vec<usize> = (0..1200).collect();
+    let chunks = datastore::chunk_by_size(items, 500, usize::MAX, |_| 1);
+    let sizes: Vec<usize> = chunks.iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![500, 500, 200]);
+    assert_eq!(chunks.concat(), (0..1200).collect::<Vec<_>>());
+
+    //? The size budget splits too, and an oversized item stands alone.
+    let chunks = datastore::chunk_by_size(vec![4, 4, 4, 20, 1], 500, 10, |size| *size);
+    assert_eq!(chunks, vec![vec![4, 4], vec![4], vec![20], vec![1]]);
+}
+
+#[test]
+fn datastore_statuses_are_classified_by_code() {
+    use crate::error::Error;
+    use tonic::{Code, Status};
+
+    let error = Error::from(Status::already_exists("entity already exists"));
+    assert!(matches!(&error, Error::AlreadyExists(message) if message == "entity already exists"));
+    assert_eq!(error.code(), Some(Code::AlreadyExists));
+    assert!(!error.is_retryable());
+    let error = Error::from(Status::not_found("no entity to update"));
+    assert!(matches!(error, Error::NotFound(message) if message == "no entity to update"));
+
+    //? Contention aborts are worth retrying, expired transactions are not.
+    let error = Error::from(Status::aborted("too much contention on these datastore entities"));
+    assert!(matches!(error, Error::Aborted { retryable: true, .. }));
+    assert!(error.is_retryable());
+    let error = Error::from(Status::aborted("The referenced transaction has expired"));
+    assert!(matches!(error, Error::Aborted { retryable: false, .. }));
+    assert!(!error.is_retryable());
+
+    assert!(Error::from(Status::unavailable("try again")).is_retryable());
+    assert!(Error::from(Status::internal("oops")).is_retryable());
+    let error = Error::from(Status::invalid_argument("bad key"));
+    assert!(matches!(&error, Error::InvalidArgument(message) if message == "bad key"));
+    assert!(!error.is_retryable());
+
+    //? The other codes keep their status.
+    let error = Error::from(Status::data_loss("lost"));
+    assert!(matches!(error, Error::Status(_)));
+    assert_eq!(error.code(), Some(Code::DataLoss));
+    assert_eq!(Error::ConflictDetected.code(), None);
+}
+
+#[test]
+fn datastore_mutation_results_must_match_the_mutations() {
+    use crate::error::Error;
+
+    //? A result without its mutation is not dropped silently.
+    let error = datastore::mutation_outcomes(Vec::new(), vec![Default::default()]).unwrap_err();
+    assert!(matches!(error, Error::Status(status) if status.code() == tonic::Code::Internal));
+    assert!(datastore::mutation_outcomes(Vec::new(), Vec::new()).unwrap().is_empty());
+}
+
+#[test]
+fn datastore_preconditions_keep_the_exact_update_time() {
+    let time = chrono::DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap().naive_utc();
+    let timestamp = datastore::convert_timestamp(time);
+    assert_eq!(timestamp, prost_types::Timestamp { seconds: 1_700_000_000, nanos: 123_456_789 });
+}
+
+#[test]
+fn datastore_transaction_retries_back_off() {
+    use std::time::Duration;
+
+    assert_eq!(datastore::transaction_backoff(1), Duration::from_millis(100));
+    assert_eq!(datastore::transaction_backoff(3), Duration::from_millis(400));
+    //? The delay stops growing after a few attempts.
+    assert_eq!(datastore::transaction_backoff(50), Duration::from_millis(6400));
+}
+
+#[test]
+fn datastore_expired_transactions_are_not_retried() {
+    use crate::error::Error;
+    use tonic::Status;
+
+    let contention = Error::from(Status::aborted("too much contention on these entities"));
+    assert!(datastore::is_aborted(&contention));
+    //? An expired transaction is returned at once (and rolled back), instead of running again.
+    let expired = Error::from(Status::aborted("The referenced transaction has expired"));
+    assert!(!datastore::is_aborted(&expired));
+}
+
+#[test]
+fn datastore_urlsafe_keys_match_other_sdks() {
+    //? The encoding of `Key("Kind", 1337, project="example")` by the Python client.
+    let encoded = "agdleGFtcGxlcgsLEgRLaW5kGLkKDA";
+    let key = datastore::Key::new("Kind").id(1337);
+    assert_eq!(key.to_urlsafe("example"), encoded);
+    assert_eq!(datastore::Key::from_urlsafe(encoded).unwrap(), key);
+    //? Padded inputs are accepted too.
+    assert_eq!(datastore::Key::from_urlsafe("agdleGFtcGxlcgsLEgRLaW5kGLkKDA==").unwrap(), key);
+    assert_eq!(datastore::Key::urlsafe_project(encoded).unwrap(), "example");
+
+    assert!(datastore::Key::from_urlsafe("not a key").is_err());
+}
+
+#[test]
+fn datastore_slow_queries_are_reported_above_the_threshold() {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&reports);
+    let callback: datastore::SlowQueryCallback =
+        Arc::new(move |report: &datastore::SlowQuery| sink.lock().unwrap().push(report.clone()));
+
+    let log =
+        datastore::SlowQueryLog { threshold: Duration::from_secs(60), callback: Some(callback) };
+    log.report(Instant::now(), 1, || String::from("kind=fast"), 3, 3);
+    assert!(reports.lock().unwrap().is_empty());
+
+    let log = datastore::SlowQueryLog { threshold: Duration::ZERO, ..log };
+    let query = datastore::Query::new("users");
+    //? `MoreResultsAfterLimit` (2) means results remain past the cursor.
+    log.report(Instant::now(), query.fingerprint(), || query.shape(), 25, 2);
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].fingerprint, query.fingerprint());
+    assert_eq!(reports[0].shape, "kind=users");
+    assert_eq!(reports[0].entity_count, 25);
+    assert!(reports[0].more_results);
+}
+
+#[test]
+fn datastore_aggregation_results_are_typed_by_alias() {
+    use std::collections::HashMap;
+
+    let results = datastore::AggregationResults {
+        properties: HashMap::from([
+            (String::from("total"), datastore::Value::IntegerValue(42)),
+            (String::from("sum_age"), datastore::Value::IntegerValue(1260)),
+            (String::from("mean_age"), datastore::Value::DoubleValue(30.0)),
+            (String::from("mean_none"), datastore::Value::OptionValue(None)),
+        ]),
+    };
+    assert_eq!(results.count("total"), Some(42));
+    assert_eq!(results.sum("sum_age"), Some(1260.0));
+    assert_eq!(results.sum_integer("sum_age"), Some(1260));
+    assert_eq!(results.avg("mean_age"), Some(30.0));
+    //? Averages over no numeric value are null.
+    assert_eq!(results.avg("mean_none"), None);
+    assert_eq!(results.count("missing"), None);
+}
+
+#[test]
+fn datastore_projected_timestamps_are_decoded_from_microseconds() {
+    use datastore::api::{self, value::ValueType};
+    use datastore::Value;
+
+    //? Projections return timestamps as microseconds, flagged with the `GD_WHEN` meaning.
+    let time = chrono::DateTime::from_timestamp(1_700_000_000, 5_000).unwrap().naive_utc();
+    let micros = || api::Value {
+        meaning: 18,
+        value_type: Some(ValueType::IntegerValue(1_700_000_000_000_005)),
+        ..Default::default()
+    };
+    assert_eq!(Value::from(micros()), Value::TimestampValue(time));
+    let array = api::Value {
+        value_type: Some(ValueType::ArrayValue(api::ArrayValue { values: vec![micros()] })),
+        ..Default::default()
+    };
+    assert_eq!(Value::from(array), Value::ArrayValue(vec![Value::TimestampValue(time)]));
+
+    let integer =
+        api::Value { value_type: Some(ValueType::IntegerValue(42)), ..Default::default() };
+    assert_eq!(Value::from(integer), Value::IntegerValue(42));
+}
+
+#[tokio::test]
+async fn datastore_conversions_yield_periodically() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    //? On a single-threaded runtime, the spawned task only runs when the loop yields.
+    let ran = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&ran);
+    tokio::spawn(async move { flag.store(true, Ordering::SeqCst) });
+
+    let mut cooperative = datastore::CooperativeYield::new(4);
+    for _ in 0..3 {
+        cooperative.tick().await;
+    }
+    assert!(!ran.load(Ordering::SeqCst));
+    cooperative.tick().await;
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn datastore_scoped_clients_fill_in_missing_namespaces() {
+    let partition =
+        datastore::Partition { project_name: "my-project", namespace: Some("tenant-a") };
+    let namespace_of = |key: &datastore::Key| {
+        datastore::convert_key(partition, key).partition_id.unwrap().namespace_id
+    };
+
+    assert_eq!(namespace_of(&datastore::Key::new("users").id(1)), "tenant-a");
+    //? An explicit namespace wins, even the default one.
+    assert_eq!(namespace_of(&datastore::Key::new("users").id(1).namespace("tenant-b")), "tenant-b");
+    assert_eq!(namespace_of(&datastore::Key::new("users").id(1).namespace("")), "");
+}
+
+#[test]
+fn datastore_property_paths_traverse_entities_and_arrays() {
+    use datastore::{IntoValue, Value};
+    use std::collections::HashMap;
+
+    let items = vec![
+        HashMap::from([("sku".to_string(), "AB-1".into_value())]),
+        HashMap::from([("sku".to_string(), "ZX-42".into_value())]),
+    ];
+    let value = HashMap::from([
+        ("items".to_string(), items.into_value()),
+        ("matrix".to_string(), vec![vec![1_i64, 2], vec![3, 4]].into_value()),
+        ("note".to_string(), Value::OptionValue(None)),
+    ])
+    .into_value();
+
+    assert_eq!(value.get_path::<String>("items[1].sku").unwrap(), "ZX-42");
+    assert_eq!(value.get_path::<i64>("matrix[1][0]").unwrap(), 3);
+    assert_eq!(value.get_path::<Option<String>>("note").unwrap(), None);
+
+    let missing = value.at_path("items[2].sku").unwrap_err();
+    assert!(
+        matches!(missing, crate::error::ConvertError::MissingProperty(path) if path == "items[2]")
+    );
+    let missing = value.at_path("items[0].price").unwrap_err();
+    assert!(
+        matches!(missing, crate::error::ConvertError::MissingProperty(path) if path == "items[0].price")
+    );
+    assert!(matches!(
+        value.at_path("items.sku").unwrap_err(),
+        crate::error::ConvertError::UnexpectedPropertyType { .. }
+    ));
+    for invalid in ["", "items[", "items[x]", "items[0]x", ".items", "items..sku"] {
+        assert!(matches!(value.at_path(invalid), Err(crate::error::ConvertError::InvalidPath(_))));
+    }
+}
+
+#[test]
+fn datastore_durations_are_stored_as_integers() {
+    use datastore::{FromValue, IntoValue, Millis, Nanos, Value};
+    use std::time::Duration;
+
+    assert_eq!(Duration::from_millis(3).into_value(), Value::IntegerValue(3000));
+    assert_eq!(
+        Duration::from_value(Value::IntegerValue(1_500_000)).unwrap(),
+        Duration::from_millis(1500)
+    );
+    assert_eq!(
+        chrono::Duration::from_value(Value::IntegerValue(-5)).unwrap(),
+        chrono::Duration::microseconds(-5)
+    );
+    assert_eq!(
+        Millis(chrono::Duration::milliseconds(-1500)).into_value(),
+        Value::IntegerValue(-1500)
+    );
+
+    //? Out of range values fail to decode, and saturate when encoded.
+    assert!(Duration::from_value(Value::IntegerValue(-1)).is_err());
+    assert!(Nanos::<Duration>::from_value(Value::IntegerValue(-1)).is_err());
+    assert_eq!(Nanos(Duration::MAX).into_value(), Value::IntegerValue(i64::MAX));
+    assert!(chrono::Duration::from_value(Value::StringValue("1s".into())).is_err());
+}
+
+#[test]
+fn datastore_builders_configure_the_endpoint() {
+    use std::time::Duration;
+
+    let builder = datastore::ClientBuilder::new("my-project")
+        .endpoint("http://localhost:8081")
+        .connect_timeout(Duration::from_secs(1))
+        .user_agent("tests/1.0");
+    let endpoint = builder.endpoint_config().unwrap();
+    assert_eq!(endpoint.uri(), "http://localhost:8081/");
+
+    assert!(datastore::ClientBuilder::new("my-project").endpoint_config().is_ok());
+    assert!(datastore::ClientBuilder::new("my-project")
+        .endpoint("not a url")
+        .endpoint_config()
+        .is_err());
+    assert!(datastore::ClientBuilder::new("my-project")
+        .user_agent("bad\nagent")
+        .endpoint_config()
+        .is_err());
+}
+
+#[test]
+fn datastore_entities_can_be_routed_to_namespaces() {
+    use datastore::{IntoEntity, IntoValue, Key};
+    use std::collections::HashMap;
+
+    let key = Key::new("comments").id(7).parent(Key::new("users").id(1)).namespace("tenant-a");
+    let properties = HashMap::from([("text".to_string(), "hello".into_value())]);
+    let entity = (key, properties).in_namespace("tenant-b").into_entity().unwrap();
+
+    //? The whole path moves, so that the hierarchy stays valid.
+    assert_eq!(entity.key().get_namespace(), Some("tenant-b"));
+    assert_eq!(entity.key().get_parent().unwrap().get_namespace(), Some("tenant-b"));
+}
+
+#[tokio::test]
+async fn datastore_lookup_progress_is_streamed() {
+    use futures::StreamExt;
+
+    let (progress, events) = datastore::lookup_progress_stream();
+    {
+        let mut tracker = datastore::LookupTracker::new(5, None, Some(&progress));
+        //? The first round defers two keys, the second one finds them.
+        tracker.round(2, 1, 2, 2);
+        tracker.round(2, 0, 0, 0);
+    }
+    drop(progress);
+
+    let events: Vec<datastore::LookupProgress> = events.collect().await;
+    assert_eq!(events.len(), 2);
+    assert_eq!((events[0].found, events[0].missing, events[0].pending), (2, 1, 2));
+    assert!(!events[0].is_done());
+    assert_eq!((events[1].found, events[1].rounds, events[1].deferred_rounds), (4, 2, 1));
+    assert!(events[1].is_done());
+}
+
+#[test]
+fn datastore_key_queries_run_as_lookups() {
+    use datastore::{CompositeFilter, Filter, IntoValue, Key, Query};
+
+    let (user1, user2) = (Key::new("users").id(1), Key::new("users").id(2));
+    let keys = vec![user1.clone(), user2.clone(), Key::new("groups").id(3)];
+    let query = Query::new("users").filter(Filter::In("__key__".into(), keys.into_value()));
+    //? Lookups are opt-in.
+    assert_eq!(query.lookup_keys(), None);
+    let query = query.key_lookup();
+    //? Keys of other kinds cannot match the query.
+    assert_eq!(query.lookup_keys(), Some(vec![user1.clone(), user2.clone()]));
+    assert_eq!(query.clone().limit(1).lookup_keys(), None);
+    assert_eq!(query.clone().keys_only().lookup_keys(), None);
+    assert_eq!(query.clone().eventually_consistent().lookup_keys(), None);
+
+    let query = Query::new("users")
+        .filter(Filter::Equal("__key__".into(), user1.clone().into_value()))
+        .filter(Filter::Equal("__key__".into(), user2.clone().into_value()))
+        .key_lookup();
+    assert_eq!(query.lookup_keys(), None);
+    let query = query.composite_filter(CompositeFilter::Or);
+    assert_eq!(query.lookup_keys(), Some(vec![user1, user2]));
+    let query = query.filter(Filter::Equal("age".into(), 10.into_value()));
+    assert_eq!(query.lookup_keys(), None);
+}
+
+#[test]
+fn datastore_audit_events_report_applied_changes() {
+    use datastore::{AuditEvent, AuditOperation, AuditSink, Key};
+    use std::sync::Mutex;
+
+    type Recorded = (AuditOperation, Key, Vec<String>, Option<String>, bool);
+
+    #[derive(Debug, Default)]
+    struct RecordingSink(Mutex<Vec<Recorded>>);
+
+    impl AuditSink for RecordingSink {
+        fn record(&self, event: &AuditEvent<'_>) {
+            let properties = event.properties.to_vec();
+            let actor = event.actor.map(String::from);
+            let event =
+                (event.operation, event.key.clone(), properties, actor, event.transactional);
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    let log = datastore::AuditLog {
+        changes: vec![
+            Some(datastore::AuditChange {
+                operation: AuditOperation::Upsert,
+                key: Key::new("users").id(1),
+                properties: vec!["email".to_string(), "name".to_string()],
+            }),
+            Some(datastore::AuditChange {
+                operation: AuditOperation::Update,
+                key: Key::new("users").id(3),
+                properties: vec!["name".to_string()],
+            }),
+            None,
+            Some(datastore::AuditChange {
+                operation: AuditOperation::Delete,
+                key: Key::new("users").id(2),
+                properties: Vec::new(),
+            }),
+        ],
+    };
+    //? The update found a modified entity, the others applied.
+    let mut results = vec![datastore::api::MutationResult::default(); 4];
+    results[1].conflict_detected = true;
+    let sink = RecordingSink::default();
+    log.report(&sink, "my-project", Some("alice"), true, &results);
+
+    let events = sink.0.into_inner().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].0, AuditOperation::Upsert);
+    assert_eq!(events[0].1, Key::new("users").id(1));
+    assert_eq!(events[0].2, vec!["email", "name"]);
+    assert_eq!((events[0].3.as_deref(), events[0].4), (Some("alice"), true));
+    assert_eq!((events[1].0, events[1].2.len()), (AuditOperation::Delete, 0));
+}
+
+#[test]
+fn datastore_std_types_convert_to_and_from_values() {
+    use datastore::{FromValue, IntoValue, Value};
+    use std::borrow::Cow;
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+    fn round_trip<T: IntoValue + FromValue + Clone>(value: T) -> T {
+        T::from_value(value.into_value()).unwrap()
+    }
+
+    assert_eq!(round_trip(u32::MAX), u32::MAX);
+    assert_eq!(round_trip(-7i32), -7);
+    assert_eq!(round_trip(1.5f32), 1.5);
+    assert_eq!(round_trip(Cow::Borrowed("john")), "john");
+    assert_eq!(round_trip([1u8, 2, 3]), [1, 2, 3]);
+    let map = BTreeMap::from([("a".to_string(), 1i64), ("b".to_string(), 2)]);
+    assert_eq!(round_trip(map.clone()), map);
+    let set = BTreeSet::from(["x".to_string(), "y".to_string()]);
+    assert_eq!(round_trip(set.clone()), set);
+    assert_eq!(set.into_value(), Value::ArrayValue(vec!["x".into_value(), "y".into_value()]));
+
+    //? Stored values which do not fit the target type are rejected instead of truncated.
+    assert!(u32::from_value(Value::IntegerValue(-1)).is_err());
+    assert!(i8::from_value(Value::IntegerValue(300)).is_err());
+    assert_eq!(u64::from_value(Value::IntegerValue(42)).unwrap(), 42);
+    assert!(<[u8; 4]>::from_value(Value::BlobValue(vec![1, 2])).is_err());
+    let values = Value::ArrayValue(vec![1i64.into_value(), 1i64.into_value()]);
+    assert_eq!(HashSet::<i64>::from_value(values).unwrap(), HashSet::from([1]));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn datastore_keys_and_values_round_trip_through_serde() {
+    use datastore::{IntoValue, Key, KeyID, Value};
+    use std::collections::HashMap;
+
+    let key = Key::new("orgs").namespace("tenant-a").id("acme").child("users").id(1);
+    let serialized = json::to_value(&key).unwrap();
+    assert_eq!(
+        serialized,
+        json::json!({
+            "kind": "users",
+            "id": 1,
+            "namespace": "tenant-a",
+            "parent": { "kind": "orgs", "name": "acme", "namespace": "tenant-a" },
+        })
+    );
+    assert_eq!(json::from_value::<Key>(serialized).unwrap(), key);
+    assert_eq!(json::to_value(Key::new("users")).unwrap(), json::json!({ "kind": "users" }));
+    assert!(json::from_str::<Key>(r#"{"kind": "users", "id": 1, "name": "john"}"#).is_err());
+    assert_eq!(json::from_str::<KeyID>("null").unwrap(), KeyID::Incomplete);
+    assert_eq!(json::from_str::<KeyID>(r#""john""#).unwrap(), KeyID::StringID("john".into()));
+
+    let time = chrono::DateTime::from_timestamp(1_700_000_000, 5_000_000).unwrap().naive_utc();
+    let value = Value::EntityValue(HashMap::from([
+        ("name".to_string(), "john".into_value()),
+        ("age".to_string(), 42i64.into_value()),
+        ("score".to_string(), 1.5f64.into_value()),
+        ("seen".to_string(), time.into_value()),
+        ("avatar".to_string(), Value::BlobValue(vec![1, 2, 3])),
+        ("home".to_string(), Value::GeoPointValue(48.85, 2.35)),
+        ("friends".to_string(), vec![Key::new("users").id(2)].into_value()),
+        ("nickname".to_string(), Value::OptionValue(None)),
+    ]));
+    let serialized = json::to_value(&value).unwrap();
+    let properties = &serialized["entity"];
+    assert_eq!(properties["seen"], json::json!({ "timestamp": "2023-11-14T22:13:20.005Z" }));
+    assert_eq!(properties["avatar"], json::json!({ "blob": "AQID" }));
+    assert_eq!(
+        properties["home"],
+        json::json!({ "geo_point": { "latitude": 48.85, "longitude": 2.35 } })
+    );
+    assert_eq!(properties["nickname"], json::json!({ "null": null }));
+    assert_eq!(json::from_value::<Value>(serialized).unwrap(), value);
+}
+
+#[test]
+fn datastore_non_finite_doubles_follow_the_policy() {
+    use crate::error::ConvertError;
+    use datastore::{IntoValue, NonFinitePolicy, Value};
+    use std::collections::HashMap;
+
+    let item = HashMap::from([("price".to_string(), f64::NAN.into_value())]);
+    let order = || {
+        HashMap::from([
+            ("total".to_string(), 12.5f64.into_value()),
+            ("items".to_string(), vec![item.clone()].into_value()),
+        ])
+        .into_value()
+    };
+
+    let mut properties = order();
+    NonFinitePolicy::AllowNonFinite.apply(&mut properties).unwrap();
+    assert!(
+        matches!(properties.at_path("items[0].price").unwrap(), Value::DoubleValue(double) if double.is_nan())
+    );
+
+    let error = NonFinitePolicy::RejectNonFinite.apply(&mut order()).unwrap_err();
+    assert!(matches!(error, ConvertError::NonFiniteDouble(path) if path == "items[0].price"));
+
+    let mut properties = order();
+    NonFinitePolicy::ClampToNull.apply(&mut properties).unwrap();
+    assert_eq!(properties.at_path("items[0].price").unwrap(), &Value::OptionValue(None));
+    assert_eq!(properties.at_path("total").unwrap(), &Value::DoubleValue(12.5));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn datastore_serde_types_convert_through_values() {
+    use crate::error::ConvertError;
+    use datastore::{IntoValue, Key, Value};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    enum Status {
+        Active,
+        Suspended { reason: String },
+        Renamed(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Account {
+        owner: Key,
+        display_name: String,
+        balance: u32,
+        tags: Vec<String>,
+        limits: HashMap<String, f64>,
+        status: Status,
+        previous_status: Option<Status>,
+        parent: Option<Key>,
+    }
+
+    let account = Account {
+        owner: Key::new("users").id(1).parent(Key::new("orgs").id("acme")),
+        display_name: String::from("John"),
+        balance: 42,
+        tags: vec![String::from("vip")],
+        limits: HashMap::from([(String::from("daily"), 100.0)]),
+        status: Status::Suspended { reason: String::from("fraud") },
+        previous_status: Some(Status::Active),
+        parent: None,
+    };
+    let value = datastore::to_value(&account).unwrap();
+    //? Keys stay keys, so that they can be queried as such.
+    assert_eq!(value.at_path("owner").unwrap(), &account.owner.clone().into_value());
+    assert_eq!(value.at_path("displayName").unwrap(), &"John".into_value());
+    assert_eq!(value.at_path("balance").unwrap(), &Value::IntegerValue(42));
+    assert_eq!(value.at_path("status.Suspended.reason").unwrap(), &"fraud".into_value());
+    assert_eq!(value.at_path("previousStatus").unwrap(), &"Active".into_value());
+    assert_eq!(value.at_path("parent").unwrap(), &Value::OptionValue(None));
+    assert_eq!(datastore::from_value::<Account>(value).unwrap(), account);
+
+    //? Maps keyed by integers or booleans have their keys stored as property names.
+    let scores = HashMap::from([(1u32, String::from("gold")), (2, String::from("silver"))]);
+    let value = datastore::to_value(&scores).unwrap();
+    assert_eq!(value.at_path("1").unwrap(), &"gold".into_value());
+    assert_eq!(datastore::from_value::<HashMap<u32, String>>(value).unwrap(), scores);
+    let flags = HashMap::from([(true, -1i64), (false, 0)]);
+    let value = datastore::to_value(&flags).unwrap();
+    assert_eq!(datastore::from_value::<HashMap<bool, i64>>(value).unwrap(), flags);
+    let statuses = HashMap::from([(Status::Active, 1u8)]);
+    let value = datastore::to_value(&statuses).unwrap();
+    assert_eq!(datastore::from_value::<HashMap<Status, u8>>(value).unwrap(), statuses);
+    let names = Value::EntityValue(HashMap::from([("john".to_string(), 1i64.into_value())]));
+    assert!(matches!(
+        datastore::from_value::<HashMap<u32, i64>>(names),
+        Err(ConvertError::Serde(_))
+    ));
+
+    let renamed = datastore::to_value(&Status::Renamed(String::from("Jane"))).unwrap();
+    assert_eq!(datastore::from_value::<Status>(renamed).unwrap(), Status::Renamed("Jane".into()));
+    assert!(matches!(datastore::to_value(&u64::MAX), Err(ConvertError::OutOfRange(_))));
+    assert!(matches!(
+        datastore::from_value::<u8>(Value::IntegerValue(300)),
+        Err(ConvertError::Serde(_))
+    ));
+}
@@ -1,3 +1,4 @@
+mod authorize;
 #[cfg(feature = "datastore")]
 mod datastore;
 #[cfg(feature = "pubsub")]
@@ -9,6 +10,7 @@ mod vision;
 
 use crate::authorize::ApplicationCredentials;
 
+#[allow(dead_code)]
 fn load_creds() -> ApplicationCredentials {
     let creds = std::env::var("GCP_TEST_CREDENTIALS").expect("env GCP_TEST_CREDENTIALS not set");
     json::from_str::<ApplicationCredentials>(&creds)